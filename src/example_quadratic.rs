@@ -2,10 +2,10 @@ use crate::minituna_v1::Objective;
 use crate::minituna_v1::Trial;
 use crate::minituna_v1::TrialError;
 
-struct Quadratic;
+pub struct Quadratic;
 
 impl Objective for Quadratic {
-    fn objective(&self, trial: Trial) -> Result<f64, TrialError> {
+    fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
         let x = trial.suggest_uniform("x", 0.0, 10.0);
         let y = trial.suggest_uniform("y", 0.0, 10.0);
         match (x, y) {
@@ -15,3 +15,18 @@ impl Objective for Quadratic {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::create_study;
+
+    #[test]
+    fn optimize_quadratic() {
+        let study = create_study().seed(1).build();
+        study.optimize(Quadratic, 100);
+
+        let best_trial = study.best_trial().unwrap().unwrap();
+        assert!(best_trial.value().unwrap() < 1.0);
+    }
+}
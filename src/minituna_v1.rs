@@ -2,219 +2,2927 @@ use ordered_float::OrderedFloat;
 use rand::prelude::*;
 use rand::rngs::StdRng;
 use rand::SeedableRng;
-use std::{cell::RefCell, collections::HashMap};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+pub mod cmaes;
+pub mod gp;
+pub mod grid;
+pub mod hyperband;
+pub mod journal;
+mod json;
+mod math;
+pub mod nsga2;
+pub mod patient;
+pub mod percentile;
+pub mod qmc;
+pub mod retry;
+pub mod sqlite;
+pub mod threshold;
+pub mod tpe;
+mod transform;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TrialErrorKind {
+    /// The trial could not be evaluated. `Study::optimize` marks it failed.
+    Failed,
+    /// The objective stopped the trial early because `Trial::should_prune`
+    /// returned true. `Study::optimize` marks it pruned.
+    Pruned,
+}
+
+#[derive(Debug)]
 pub struct TrialError {
+    kind: TrialErrorKind,
     message: String,
 }
 
 impl TrialError {
     fn new(message: &str) -> TrialError {
         TrialError {
+            kind: TrialErrorKind::Failed,
             message: String::from(message),
         }
     }
+
+    /// The error an objective returns to stop a trial that should be pruned.
+    pub fn pruned() -> TrialError {
+        TrialError {
+            kind: TrialErrorKind::Pruned,
+            message: String::from("trial was pruned"),
+        }
+    }
+
+    pub fn kind(&self) -> TrialErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
 }
 
-#[derive(PartialEq, Clone)]
+/// Lifecycle of a trial.
+///
+/// A trial is either created `Running`, or created `Waiting` and moved to
+/// `Running` when a worker picks it up. A running trial ends up in exactly one
+/// of the finished states `Completed`, `Pruned` or `Failed`, and is never
+/// updated afterwards.
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum TrialState {
+    Waiting,
     Running,
     Completed,
+    Pruned,
     Failed,
 }
 
+impl TrialState {
+    pub fn is_finished(self) -> bool {
+        match self {
+            TrialState::Waiting | TrialState::Running => false,
+            TrialState::Completed | TrialState::Pruned | TrialState::Failed => true,
+        }
+    }
+
+    pub fn can_transition_to(self, next: TrialState) -> bool {
+        match (self, next) {
+            (TrialState::Waiting, TrialState::Running) => true,
+            (TrialState::Running, next) => next.is_finished(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub enum StudyDirection {
+    #[default]
+    Minimize,
+    Maximize,
+}
+
+/// Whether `values` Pareto-dominate `other`: no objective is worse under
+/// `directions` and at least one is strictly better. A non-finite value, NaN
+/// included, is worse than any finite one.
+pub(crate) fn dominates(values: &[f64], other: &[f64], directions: &[StudyDirection]) -> bool {
+    let worst_if_not_finite = |x: f64| if x.is_finite() { x } else { f64::INFINITY };
+    let mut strictly_better = false;
+    for ((&a, &b), direction) in values.iter().zip(other).zip(directions) {
+        let (a, b) = match direction {
+            StudyDirection::Minimize => (a, b),
+            StudyDirection::Maximize => (-a, -b),
+        };
+        let (a, b) = (worst_if_not_finite(a), worst_if_not_finite(b));
+        if a > b {
+            return false;
+        }
+        strictly_better |= a < b;
+    }
+    strictly_better
+}
+
+/// A parameter value as the objective function sees it. This is also the type
+/// of the choices of a categorical distribution.
+#[derive(PartialEq, Clone, Debug)]
+pub enum ParamValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl From<bool> for ParamValue {
+    fn from(value: bool) -> Self {
+        ParamValue::Bool(value)
+    }
+}
+
+impl From<i64> for ParamValue {
+    fn from(value: i64) -> Self {
+        ParamValue::Int(value)
+    }
+}
+
+impl From<i32> for ParamValue {
+    fn from(value: i32) -> Self {
+        ParamValue::Int(value.into())
+    }
+}
+
+impl From<f64> for ParamValue {
+    fn from(value: f64) -> Self {
+        ParamValue::Float(value)
+    }
+}
+
+impl From<&str> for ParamValue {
+    fn from(value: &str) -> Self {
+        ParamValue::Str(value.to_string())
+    }
+}
+
+impl From<String> for ParamValue {
+    fn from(value: String) -> Self {
+        ParamValue::Str(value)
+    }
+}
+
+/// The search space of a single parameter.
+///
+/// Samplers and storage work with the internal representation of a parameter,
+/// which is always an `f64`: the value itself for the numerical distributions
+/// and the index of the choice for `Categorical`.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Distribution {
+    Uniform {
+        low: f64,
+        high: f64,
+    },
+    LogUniform {
+        low: f64,
+        high: f64,
+    },
+    Int {
+        low: i64,
+        high: i64,
+        step: i64,
+        log: bool,
+    },
+    DiscreteUniform {
+        low: f64,
+        high: f64,
+        q: f64,
+    },
+    Categorical {
+        choices: Vec<ParamValue>,
+    },
+}
+
+impl Distribution {
+    /// Whether a parameter may be sampled from `self` in one trial and from
+    /// `other` in another one.
+    pub fn is_compatible(&self, other: &Distribution) -> bool {
+        match (self, other) {
+            (
+                Distribution::Categorical { choices },
+                Distribution::Categorical { choices: other },
+            ) => choices == other,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+
+    /// Whether the distribution has only one possible value.
+    pub fn single(&self) -> bool {
+        match self {
+            Distribution::Uniform { low, high } | Distribution::LogUniform { low, high } => {
+                low == high
+            }
+            Distribution::Int { low, high, .. } => low == high,
+            Distribution::DiscreteUniform { low, high, q } => high - low < *q,
+            Distribution::Categorical { choices } => choices.len() == 1,
+        }
+    }
+
+    pub fn to_external_repr(&self, internal_repr: f64) -> ParamValue {
+        match self {
+            Distribution::Int { .. } => ParamValue::Int(internal_repr as i64),
+            Distribution::Categorical { choices } => choices[internal_repr as usize].clone(),
+            _ => ParamValue::Float(internal_repr),
+        }
+    }
+
+    pub fn to_internal_repr(&self, external_repr: &ParamValue) -> Option<f64> {
+        match (self, external_repr) {
+            (Distribution::Categorical { choices }, value) => choices
+                .iter()
+                .position(|choice| choice == value)
+                .map(|index| index as f64),
+            (Distribution::Int { .. }, ParamValue::Int(value)) => Some(*value as f64),
+            (Distribution::Int { .. }, _) => None,
+            (_, ParamValue::Float(value)) => Some(*value),
+            (_, ParamValue::Int(value)) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), TrialError> {
+        let valid = match *self {
+            Distribution::Uniform { low, high } => low <= high,
+            Distribution::LogUniform { low, high } => 0.0 < low && low <= high,
+            Distribution::Int {
+                low,
+                high,
+                step,
+                log,
+            } => low <= high && 0 < step && (!log || (0 < low && step == 1)),
+            Distribution::DiscreteUniform { low, high, q } => low <= high && 0.0 < q,
+            Distribution::Categorical { ref choices } => !choices.is_empty(),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(TrialError::new(&format!(
+                "invalid distribution: {:?}",
+                self
+            )))
+        }
+    }
+
+    pub fn contains(&self, internal_repr: f64) -> bool {
+        match self {
+            Distribution::Uniform { low, high } | Distribution::LogUniform { low, high } => {
+                *low <= internal_repr && internal_repr <= *high
+            }
+            Distribution::Int { low, high, .. } => {
+                *low as f64 <= internal_repr && internal_repr <= *high as f64
+            }
+            Distribution::DiscreteUniform { low, high, .. } => {
+                *low <= internal_repr && internal_repr <= *high
+            }
+            Distribution::Categorical { choices } => {
+                0.0 <= internal_repr && internal_repr < choices.len() as f64
+            }
+        }
+    }
+}
+
+/// Distributions of the parameters that every one of `trials` suggested with
+/// the same distribution.
+pub fn intersection_search_space(trials: &[FrozenTrial]) -> HashMap<String, Distribution> {
+    let mut trials = trials.iter();
+    let mut search_space = match trials.next() {
+        Some(trial) => trial.distributions.clone(),
+        None => return HashMap::new(),
+    };
+    for trial in trials {
+        search_space
+            .retain(|name, distribution| trial.distributions.get(name) == Some(distribution));
+    }
+    search_space
+}
+
 #[derive(Clone)]
 pub struct FrozenTrial {
     trial_id: u32,
+    number: u32,
     state: TrialState,
-    value: Option<OrderedFloat<f64>>,
+    values: Option<Vec<OrderedFloat<f64>>>,
+    intermediate_values: BTreeMap<u64, f64>,
     params: HashMap<String, f64>,
+    distributions: HashMap<String, Distribution>,
+    user_attrs: HashMap<String, String>,
+    system_attrs: HashMap<String, String>,
 }
 
 impl FrozenTrial {
-    pub fn new(trial_id: u32) -> FrozenTrial {
+    pub fn new(trial_id: u32, state: TrialState) -> FrozenTrial {
         FrozenTrial {
             trial_id,
-            state: TrialState::Running,
-            value: None,
+            number: 0,
+            state,
+            values: None,
+            intermediate_values: BTreeMap::new(),
             params: HashMap::new(),
+            distributions: HashMap::new(),
+            user_attrs: HashMap::new(),
+            system_attrs: HashMap::new(),
         }
     }
 
     pub fn is_finished(&self) -> bool {
-        self.state != TrialState::Running
+        self.state.is_finished()
+    }
+
+    /// Id of the trial, unique across the studies of its storage.
+    pub fn trial_id(&self) -> u32 {
+        self.trial_id
+    }
+
+    /// Position of the trial in its study, counted from 0.
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn state(&self) -> TrialState {
+        self.state
+    }
+
+    /// Objective value of a single-objective trial. `None` for trials of a
+    /// multi-objective study.
+    pub fn value(&self) -> Option<f64> {
+        match self.values.as_deref() {
+            Some([value]) => Some(value.into_inner()),
+            _ => None,
+        }
+    }
+
+    /// Objective values, one per direction of the study.
+    pub fn values(&self) -> Option<Vec<f64>> {
+        self.values
+            .as_ref()
+            .map(|values| values.iter().map(|v| v.into_inner()).collect())
+    }
+
+    /// Values reported with `Trial::report`, by step.
+    pub fn intermediate_values(&self) -> &BTreeMap<u64, f64> {
+        &self.intermediate_values
+    }
+
+    /// The last step a value was reported for.
+    pub fn last_step(&self) -> Option<u64> {
+        self.intermediate_values.keys().next_back().copied()
+    }
+
+    /// Parameters in the representation the objective function received them,
+    /// e.g. the chosen value rather than its index for categorical parameters.
+    pub fn params(&self) -> HashMap<String, ParamValue> {
+        self.params
+            .iter()
+            .map(|(name, &value)| {
+                let distribution = &self.distributions[name];
+                (name.clone(), distribution.to_external_repr(value))
+            })
+            .collect()
+    }
+
+    /// Parameters in the representation samplers and storage work with.
+    pub fn internal_params(&self) -> &HashMap<String, f64> {
+        &self.params
+    }
+
+    pub fn distributions(&self) -> &HashMap<String, Distribution> {
+        &self.distributions
+    }
+
+    /// Attributes the objective function attaches to the trial.
+    pub fn user_attrs(&self) -> &HashMap<String, String> {
+        &self.user_attrs
+    }
+
+    /// Attributes samplers and pruners attach to the trial for their own
+    /// bookkeeping.
+    pub fn system_attrs(&self) -> &HashMap<String, String> {
+        &self.system_attrs
+    }
+}
+
+/// Where studies and their trials live.
+///
+/// Every study has a unique name and a `study_id`, and every trial a
+/// `trial_id` unique across the studies of the storage as well as a `number`
+/// counting the trials of its study. Ids of deleted studies and trials are not
+/// reused. Trials are only updated while `TrialState::Running`, except for
+/// their state, which follows `TrialState::can_transition_to`.
+/// `InMemoryStorage` is the default backend; persistent and remote backends
+/// implement this trait as well, and fail reads as well as writes when their
+/// database or file cannot be accessed.
+pub trait Storage {
+    /// Creates an empty study. Fails when `study_name` is taken.
+    fn create_new_study(&mut self, study_name: &str) -> Result<u32, TrialError>;
+
+    fn get_study_id_from_name(&self, study_name: &str) -> Result<Option<u32>, TrialError>;
+
+    fn get_study_name_from_id(&self, study_id: u32) -> Result<Option<String>, TrialError>;
+
+    /// Ids of every study in the storage, in the order they were created.
+    fn get_all_study_ids(&self) -> Result<Vec<u32>, TrialError>;
+
+    /// Deletes the study and its trials.
+    fn delete_study(&mut self, study_id: u32) -> Result<(), TrialError>;
+
+    /// Sets the directions of the study. Fails when the study has other
+    /// directions already.
+    fn set_study_directions(
+        &mut self,
+        study_id: u32,
+        directions: &[StudyDirection],
+    ) -> Result<(), TrialError>;
+
+    /// Directions of the study, empty until they are set.
+    fn get_study_directions(&self, study_id: u32) -> Result<Vec<StudyDirection>, TrialError>;
+
+    /// Creates a trial of the study, `TrialState::Running` and empty unless
+    /// `template` gives its state, values, params and attributes. The trial
+    /// gets the next `number` of the study, whatever the number of `template`.
+    fn create_new_trial(
+        &mut self,
+        study_id: u32,
+        template: Option<&FrozenTrial>,
+    ) -> Result<u32, TrialError>;
+
+    /// Creates a trial that is not evaluated yet and has to be moved to
+    /// `TrialState::Running` by the worker that picks it up.
+    fn create_waiting_trial(&mut self, study_id: u32) -> Result<u32, TrialError> {
+        let template = FrozenTrial::new(0, TrialState::Waiting);
+        self.create_new_trial(study_id, Some(&template))
+    }
+
+    /// Sets a param of a running trial. Fails when the study suggested the
+    /// param from an incompatible distribution before, or `value` is out of
+    /// `distribution`.
+    fn set_trial_param(
+        &mut self,
+        trial_id: u32,
+        name: &str,
+        value: f64,
+        distribution: Distribution,
+    ) -> Result<(), TrialError>;
+
+    fn set_trial_state(&mut self, trial_id: u32, state: TrialState) -> Result<(), TrialError>;
+
+    fn set_trial_values(&mut self, trial_id: u32, values: &[f64]) -> Result<(), TrialError>;
+
+    fn set_trial_value(&mut self, trial_id: u32, value: f64) -> Result<(), TrialError> {
+        self.set_trial_values(trial_id, &[value])
+    }
+
+    fn set_trial_intermediate_value(
+        &mut self,
+        trial_id: u32,
+        step: u64,
+        value: f64,
+    ) -> Result<(), TrialError>;
+
+    fn set_trial_user_attr(
+        &mut self,
+        trial_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError>;
+
+    fn set_trial_system_attr(
+        &mut self,
+        trial_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError>;
+
+    fn set_study_user_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError>;
+
+    fn set_study_system_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError>;
+
+    /// Sets a system attribute of the study if it has the value `expected`,
+    /// or is unset for `None`, and returns whether it did. The check and the
+    /// update are atomic for all processes sharing the storage, so workers
+    /// can claim something only once, or count without losing updates.
+    fn compare_and_set_study_system_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+    ) -> Result<bool, TrialError>;
+
+    fn get_study_user_attrs(&self, study_id: u32) -> Result<HashMap<String, String>, TrialError>;
+
+    fn get_study_system_attrs(&self, study_id: u32) -> Result<HashMap<String, String>, TrialError>;
+
+    fn get_trial(&self, trial_id: u32) -> Result<Option<FrozenTrial>, TrialError>;
+
+    /// Trials of the study in the order they were created, optionally only
+    /// those in one of `states`.
+    fn get_all_trials(
+        &self,
+        study_id: u32,
+        states: Option<&[TrialState]>,
+    ) -> Result<Vec<FrozenTrial>, TrialError>;
+
+    /// The completed trial with the best value under `direction`. Trials
+    /// whose value is NaN or infinite are never the best, as in `dominates`.
+    fn get_best_trial(
+        &self,
+        study_id: u32,
+        direction: StudyDirection,
+    ) -> Result<Option<FrozenTrial>, TrialError> {
+        let completed_trials = self.get_all_trials(study_id, Some(&[TrialState::Completed]))?;
+        let completed_trials = completed_trials
+            .into_iter()
+            .filter(|t| t.value().is_some_and(f64::is_finite));
+        let value = |trial: &FrozenTrial| trial.value().map(OrderedFloat::from);
+        Ok(match direction {
+            StudyDirection::Minimize => completed_trials.min_by_key(value),
+            StudyDirection::Maximize => completed_trials.max_by_key(value),
+        })
+    }
+
+    /// Completed trials whose values no other completed trial dominates under
+    /// `directions`.
+    fn get_pareto_front_trials(
+        &self,
+        study_id: u32,
+        directions: &[StudyDirection],
+    ) -> Result<Vec<FrozenTrial>, TrialError> {
+        let completed: Vec<(FrozenTrial, Vec<f64>)> = self
+            .get_all_trials(study_id, Some(&[TrialState::Completed]))?
+            .into_iter()
+            .filter_map(|trial| {
+                let values = trial.values()?;
+                Some((trial, values))
+            })
+            .collect();
+        Ok(completed
+            .iter()
+            .filter(|(_, values)| {
+                !completed
+                    .iter()
+                    .any(|(_, other)| dominates(other, values, directions))
+            })
+            .map(|(trial, _)| trial.clone())
+            .collect())
     }
 }
 
 #[derive(Clone)]
-pub struct Storage {
-    trials: Vec<FrozenTrial>,
+struct InMemoryStudy {
+    study_name: String,
+    directions: Vec<StudyDirection>,
+    trial_ids: Vec<u32>,
+    user_attrs: HashMap<String, String>,
+    system_attrs: HashMap<String, String>,
+}
+
+/// A `Storage` which keeps everything in memory, for the lifetime of the
+/// process.
+#[derive(Clone, Default)]
+pub struct InMemoryStorage {
+    studies: BTreeMap<u32, InMemoryStudy>,
+    /// Trials by `trial_id`, with the `study_id` they belong to.
+    trials: BTreeMap<u32, (u32, FrozenTrial)>,
+    next_study_id: u32,
+    next_trial_id: u32,
 }
 
-impl Storage {
-    pub fn create_new_trial(&mut self) -> u32 {
-        let trial_id = self.trials.len() as u32;
-        let trial = FrozenTrial::new(trial_id);
-        self.trials.push(trial);
-        trial_id
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        InMemoryStorage {
+            studies: BTreeMap::new(),
+            trials: BTreeMap::new(),
+            next_study_id: 0,
+            next_trial_id: 0,
+        }
+    }
+
+    fn get_study_mut(&mut self, study_id: u32) -> Result<&mut InMemoryStudy, TrialError> {
+        self.studies
+            .get_mut(&study_id)
+            .ok_or_else(|| TrialError::new(&format!("study_id={} is not found", study_id)))
+    }
+
+    fn get_trial_mut(&mut self, trial_id: u32) -> Result<&mut FrozenTrial, TrialError> {
+        self.trials
+            .get_mut(&trial_id)
+            .map(|(_, trial)| trial)
+            .ok_or_else(|| TrialError::new(&format!("trial_id={} is not found", trial_id)))
     }
 
-    pub fn get_trial(&self, trial_id: u32) -> Option<FrozenTrial> {
-        self.trials.get(trial_id as usize).map(|v| v.clone())
+    fn get_running_trial_mut(&mut self, trial_id: u32) -> Result<&mut FrozenTrial, TrialError> {
+        let trial = self.get_trial_mut(trial_id)?;
+        if trial.state != TrialState::Running {
+            return Err(TrialError::new(&format!(
+                "cannot update trial_id={} in state {:?}",
+                trial_id, trial.state
+            )));
+        }
+        Ok(trial)
+    }
+}
+
+impl Storage for InMemoryStorage {
+    fn create_new_study(&mut self, study_name: &str) -> Result<u32, TrialError> {
+        if self.get_study_id_from_name(study_name)?.is_some() {
+            return Err(TrialError::new(&format!(
+                "study {} already exists",
+                study_name
+            )));
+        }
+        let study_id = self.next_study_id;
+        self.next_study_id += 1;
+        let study = InMemoryStudy {
+            study_name: study_name.to_string(),
+            directions: Vec::new(),
+            trial_ids: Vec::new(),
+            user_attrs: HashMap::new(),
+            system_attrs: HashMap::new(),
+        };
+        self.studies.insert(study_id, study);
+        Ok(study_id)
     }
 
-    pub fn get_best_trial(&self) -> Option<FrozenTrial> {
-        let completed_trials: Vec<FrozenTrial> = self
-            .trials
+    fn get_study_id_from_name(&self, study_name: &str) -> Result<Option<u32>, TrialError> {
+        Ok(self
+            .studies
             .iter()
-            .filter(|trial| trial.state == TrialState::Completed)
-            .map(|v| v.clone())
-            .collect();
-        let best_trial = completed_trials.into_iter().min_by_key(|t| t.value);
-        best_trial
+            .find(|(_, study)| study.study_name == study_name)
+            .map(|(&study_id, _)| study_id))
     }
 
-    pub fn set_trial_value(&mut self, trial_id: u32, value: f64) -> Result<(), TrialError> {
-        let maybe_trial = self.trials.get_mut(trial_id as usize);
-        if let Some(trial) = maybe_trial {
-            if !trial.is_finished() {
-                return Err(TrialError::new("cannot update finished trial"));
-            }
-            trial.value = Some(OrderedFloat::from(value)); // TODO いけてんの？？
+    fn get_study_name_from_id(&self, study_id: u32) -> Result<Option<String>, TrialError> {
+        Ok(self
+            .studies
+            .get(&study_id)
+            .map(|study| study.study_name.clone()))
+    }
+
+    fn get_all_study_ids(&self) -> Result<Vec<u32>, TrialError> {
+        Ok(self.studies.keys().copied().collect())
+    }
+
+    fn delete_study(&mut self, study_id: u32) -> Result<(), TrialError> {
+        let study = self.get_study_mut(study_id)?;
+        for trial_id in std::mem::take(&mut study.trial_ids) {
+            self.trials.remove(&trial_id);
         }
+        self.studies.remove(&study_id);
         Ok(())
     }
 
-    pub fn set_trial_state(&mut self, trial_id: u32, state: TrialState) -> Result<(), TrialError> {
-        let maybe_trial = self.trials.get_mut(trial_id as usize);
-        if let Some(trial) = maybe_trial {
-            if !trial.is_finished() {
-                return Err(TrialError::new("cannot update finished trial"));
-            }
-            trial.state = state;
+    fn set_study_directions(
+        &mut self,
+        study_id: u32,
+        directions: &[StudyDirection],
+    ) -> Result<(), TrialError> {
+        let study = self.get_study_mut(study_id)?;
+        if !study.directions.is_empty() && study.directions != directions {
+            return Err(TrialError::new(&format!(
+                "cannot change directions of study {} from {:?} to {:?}",
+                study.study_name, study.directions, directions
+            )));
         }
+        study.directions = directions.to_vec();
         Ok(())
     }
 
-    pub fn set_trial_param(
+    fn get_study_directions(&self, study_id: u32) -> Result<Vec<StudyDirection>, TrialError> {
+        Ok(self
+            .studies
+            .get(&study_id)
+            .map(|study| study.directions.clone())
+            .unwrap_or_default())
+    }
+
+    fn create_new_trial(
+        &mut self,
+        study_id: u32,
+        template: Option<&FrozenTrial>,
+    ) -> Result<u32, TrialError> {
+        let trial_id = self.next_trial_id;
+        let study = self.get_study_mut(study_id)?;
+        let number = study.trial_ids.len() as u32;
+        study.trial_ids.push(trial_id);
+        let trial = match template {
+            Some(template) => FrozenTrial {
+                trial_id,
+                number,
+                ..template.clone()
+            },
+            None => FrozenTrial {
+                number,
+                ..FrozenTrial::new(trial_id, TrialState::Running)
+            },
+        };
+        self.trials.insert(trial_id, (study_id, trial));
+        self.next_trial_id += 1;
+        Ok(trial_id)
+    }
+
+    fn set_trial_param(
         &mut self,
         trial_id: u32,
         name: &str,
         value: f64,
+        distribution: Distribution,
     ) -> Result<(), TrialError> {
-        let maybe_trial = self.trials.get_mut(trial_id as usize);
-        if let Some(trial) = maybe_trial {
-            if !trial.is_finished() {
-                return Err(TrialError::new("cannot update finished trial"));
+        let study_id = match self.trials.get(&trial_id) {
+            Some((study_id, _)) => *study_id,
+            None => {
+                let message = format!("trial_id={} is not found", trial_id);
+                return Err(TrialError::new(&message));
             }
-            trial.params.insert(name.to_string(), value);
+        };
+        let incompatible = self.studies[&study_id].trial_ids.iter().any(|trial_id| {
+            self.trials[trial_id]
+                .1
+                .distributions
+                .get(name)
+                .is_some_and(|d| !d.is_compatible(&distribution))
+        });
+        if incompatible {
+            return Err(TrialError::new(&format!(
+                "distribution of param {} is incompatible with previous trials: {:?}",
+                name, distribution
+            )));
+        }
+        if !distribution.contains(value) {
+            return Err(TrialError::new(&format!(
+                "param {}={} is out of {:?}",
+                name, value, distribution
+            )));
         }
+
+        let trial = self.get_running_trial_mut(trial_id)?;
+        trial.params.insert(name.to_string(), value);
+        trial.distributions.insert(name.to_string(), distribution);
         Ok(())
     }
-}
-
-pub struct Trial {
-    study: RefCell<Study>,
-    trial_id: u32,
-    state: TrialState,
-}
 
-impl Trial {
-    pub fn new(trial_id: u32, study: &Study) -> Self {
-        Trial {
-            study: RefCell::new(study.clone()), // TODO check performance
-            trial_id,
-            state: TrialState::Running,
+    fn set_trial_state(&mut self, trial_id: u32, state: TrialState) -> Result<(), TrialError> {
+        let trial = self.get_trial_mut(trial_id)?;
+        if !trial.state.can_transition_to(state) {
+            return Err(TrialError::new(&format!(
+                "cannot change state of trial_id={} from {:?} to {:?}",
+                trial_id, trial.state, state
+            )));
         }
+        trial.state = state;
+        Ok(())
     }
 
-    pub fn suggest_uniform(&self, name: &str, low: f64, high: f64) -> Result<f64, TrialError> {
-        let maybe_trial = self.study.borrow().storage.get_trial(self.trial_id);
-        if let Some(trial) = maybe_trial {
-            let mut distribution = HashMap::new();
-            distribution.insert(String::from("low"), low);
-            distribution.insert(String::from("high"), high);
-            let param = self.study.borrow_mut().sampler.sample_independent(
-                &self.study.borrow(),
-                &trial,
-                name,
-                distribution,
-            );
+    fn set_trial_values(&mut self, trial_id: u32, values: &[f64]) -> Result<(), TrialError> {
+        let trial = self.get_running_trial_mut(trial_id)?;
+        trial.values = Some(values.iter().cloned().map(OrderedFloat::from).collect());
+        Ok(())
+    }
 
-            match self
-                .study
-                .borrow_mut()
-                .storage
-                .set_trial_param(self.trial_id, name, param)
-            {
-                Ok(_) => Ok(param),
-                Err(err) => Err(err),
-            }
-        } else {
-            Err(TrialError::new("Not found specific trial"))
-        }
+    fn set_trial_intermediate_value(
+        &mut self,
+        trial_id: u32,
+        step: u64,
+        value: f64,
+    ) -> Result<(), TrialError> {
+        let trial = self.get_running_trial_mut(trial_id)?;
+        trial.intermediate_values.insert(step, value);
+        Ok(())
     }
-}
 
-#[derive(Clone)]
-pub struct Sampler {
-    rng: StdRng,
-}
+    fn set_trial_user_attr(
+        &mut self,
+        trial_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        let trial = self.get_running_trial_mut(trial_id)?;
+        trial.user_attrs.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
 
-impl Sampler {
-    pub fn new(seed: u64) -> Self {
-        let rng = SeedableRng::seed_from_u64(seed);
-        Sampler { rng }
+    fn set_trial_system_attr(
+        &mut self,
+        trial_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        let trial = self.get_running_trial_mut(trial_id)?;
+        trial
+            .system_attrs
+            .insert(key.to_string(), value.to_string());
+        Ok(())
     }
 
-    pub fn sample_independent(
+    fn set_study_user_attr(
         &mut self,
-        _study: &Study,
-        _trial: &FrozenTrial,
-        _name: &str,
-        distribution: HashMap<String, f64>,
-    ) -> f64 {
-        assert!(distribution.get("low").is_some());
-        assert!(distribution.get("high").is_some());
-        self.rng.gen_range(
-            distribution.get("low").unwrap(),
-            distribution.get("high").unwrap(),
-        )
+        study_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        let study = self.get_study_mut(study_id)?;
+        study.user_attrs.insert(key.to_string(), value.to_string());
+        Ok(())
     }
-}
 
-#[derive(Clone)]
-pub struct Study {
-    storage: Storage,
-    sampler: Sampler,
+    fn set_study_system_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        let study = self.get_study_mut(study_id)?;
+        study
+            .system_attrs
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn compare_and_set_study_system_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+    ) -> Result<bool, TrialError> {
+        let study = self.get_study_mut(study_id)?;
+        if study.system_attrs.get(key).map(String::as_str) != expected {
+            return Ok(false);
+        }
+        study
+            .system_attrs
+            .insert(key.to_string(), value.to_string());
+        Ok(true)
+    }
+
+    fn get_study_user_attrs(&self, study_id: u32) -> Result<HashMap<String, String>, TrialError> {
+        Ok(self
+            .studies
+            .get(&study_id)
+            .map(|study| study.user_attrs.clone())
+            .unwrap_or_default())
+    }
+
+    fn get_study_system_attrs(&self, study_id: u32) -> Result<HashMap<String, String>, TrialError> {
+        Ok(self
+            .studies
+            .get(&study_id)
+            .map(|study| study.system_attrs.clone())
+            .unwrap_or_default())
+    }
+
+    fn get_trial(&self, trial_id: u32) -> Result<Option<FrozenTrial>, TrialError> {
+        Ok(self.trials.get(&trial_id).map(|(_, trial)| trial.clone()))
+    }
+
+    fn get_all_trials(
+        &self,
+        study_id: u32,
+        states: Option<&[TrialState]>,
+    ) -> Result<Vec<FrozenTrial>, TrialError> {
+        let trial_ids = match self.studies.get(&study_id) {
+            Some(study) => &study.trial_ids,
+            None => return Ok(Vec::new()),
+        };
+        Ok(trial_ids
+            .iter()
+            .map(|trial_id| &self.trials[trial_id].1)
+            .filter(|trial| states.is_none_or(|states| states.contains(&trial.state)))
+            .cloned()
+            .collect())
+    }
 }
 
-impl Study {
-    pub fn optimize<T: Objective>(&mut self, objective: T, n_trials: u32) {
-        for _ in 0..n_trials {
-            let trial_id = self.storage.create_new_trial();
-            let trial = Trial::new(trial_id, self);
-            let value = objective.objective(trial);
-
-            let result = value
-                .and_then(|v| self.storage.set_trial_value(trial_id, v))
-                .and_then(|_| {
-                    self.storage
-                        .set_trial_state(trial_id, TrialState::Completed)
-                });
+/// A handle to a single trial that is being evaluated by the objective function.
+///
+/// `Trial` borrows the `Study` which created it, so every suggested parameter is
+/// written into the study's own storage and sampled with the study's sampler.
+/// Parameters of the relative search space are sampled jointly when the trial is
+/// created and handed out by the `suggest_*` methods afterwards.
+pub struct Trial<'a> {
+    study: &'a Study,
+    trial_id: u32,
+    relative_search_space: HashMap<String, Distribution>,
+    relative_params: HashMap<String, f64>,
+}
 
-            match result {
-                Ok(()) => (),
-                Err(err) => eprintln!("trial_id={} is failed by {}", trial_id, err.message),
-            }
+impl<'a> Trial<'a> {
+    pub fn new(trial_id: u32, study: &'a Study) -> Self {
+        let mut trial = Trial {
+            study,
+            trial_id,
+            relative_search_space: HashMap::new(),
+            relative_params: HashMap::new(),
+        };
+        // A trial that cannot be read gets no relative params, and its
+        // suggestions fail as long as the storage cannot be read.
+        let maybe_trial = study.storage.borrow().get_trial(trial_id);
+        if let Ok(Some(frozen_trial)) = maybe_trial {
+            study.with_sampler(&frozen_trial, |sampler| {
+                trial.relative_search_space =
+                    sampler.infer_relative_search_space(study, &frozen_trial);
+                trial.relative_params =
+                    sampler.sample_relative(study, &frozen_trial, &trial.relative_search_space);
+            });
         }
+        trial
     }
 
-    pub fn best_trial(self) -> Option<FrozenTrial> {
-        self.storage.get_best_trial()
+    pub fn trial_id(&self) -> u32 {
+        self.trial_id
     }
-}
 
-pub trait Objective {
-    fn objective(&self, trial: Trial) -> Result<f64, TrialError>;
+    /// Attaches `value` to the trial, e.g. a metric that is not optimized.
+    pub fn set_user_attr(&self, key: &str, value: &str) -> Result<(), TrialError> {
+        self.study
+            .storage
+            .borrow_mut()
+            .set_trial_user_attr(self.trial_id, key, value)
+    }
+
+    pub fn suggest_uniform(&self, name: &str, low: f64, high: f64) -> Result<f64, TrialError> {
+        self.suggest(name, Distribution::Uniform { low, high })
+    }
+
+    pub fn suggest_loguniform(&self, name: &str, low: f64, high: f64) -> Result<f64, TrialError> {
+        self.suggest(name, Distribution::LogUniform { low, high })
+    }
+
+    /// Suggests a value from `low`, `low + q`, `low + 2q`, ... up to `high`.
+    /// `high` is rounded down when the range is not a multiple of `q`.
+    pub fn suggest_discrete_uniform(
+        &self,
+        name: &str,
+        low: f64,
+        high: f64,
+        q: f64,
+    ) -> Result<f64, TrialError> {
+        let high = if 0.0 < q {
+            ((high - low) / q).floor() * q + low
+        } else {
+            high
+        };
+        self.suggest(name, Distribution::DiscreteUniform { low, high, q })
+    }
+
+    /// Suggests an integer from `low`, `low + step`, ... up to `high`, sampled in
+    /// log scale when `log` is set. A log scale requires `step == 1`.
+    pub fn suggest_int(
+        &self,
+        name: &str,
+        low: i64,
+        high: i64,
+        step: i64,
+        log: bool,
+    ) -> Result<i64, TrialError> {
+        let high = if 0 < step && low <= high {
+            (high - low) / step * step + low
+        } else {
+            high
+        };
+        let distribution = Distribution::Int {
+            low,
+            high,
+            step,
+            log,
+        };
+        self.suggest(name, distribution).map(|v| v as i64)
+    }
+
+    /// Suggests one of `choices`, e.g. `&["adam", "sgd"]` or `&[true, false]`.
+    pub fn suggest_categorical<T>(&self, name: &str, choices: &[T]) -> Result<T, TrialError>
+    where
+        T: Clone + Into<ParamValue>,
+    {
+        let distribution = Distribution::Categorical {
+            choices: choices.iter().cloned().map(Into::into).collect(),
+        };
+        let index = self.suggest(name, distribution)?;
+        Ok(choices[index as usize].clone())
+    }
+
+    /// Reports the objective value at `step` of an iterative evaluation, e.g.
+    /// the validation loss after each epoch, for pruners to judge the trial.
+    /// Only the first value reported for a step is kept.
+    pub fn report(&self, step: u64, value: f64) -> Result<(), TrialError> {
+        if self.study.directions.len() != 1 {
+            return Err(TrialError::new(
+                "intermediate values are not supported in multi-objective studies",
+            ));
+        }
+        let maybe_trial = self.study.storage.borrow().get_trial(self.trial_id)?;
+        let trial = maybe_trial.ok_or_else(|| TrialError::new("Not found specific trial"))?;
+        if trial.intermediate_values.contains_key(&step) {
+            return Ok(());
+        }
+        self.study.record_heartbeat(self.trial_id);
+        self.study
+            .storage
+            .borrow_mut()
+            .set_trial_intermediate_value(self.trial_id, step, value)
+    }
+
+    /// Records a heartbeat of the trial if the study records heartbeats.
+    /// Objectives which compute for longer than the grace period of the study
+    /// without suggesting or reporting anything must call it meanwhile, see
+    /// `StudyBuilder::heartbeat_interval`.
+    pub fn heartbeat(&self) {
+        self.study.record_heartbeat(self.trial_id);
+    }
+
+    /// Whether the study's pruner judges that the trial should stop, based on
+    /// the values reported so far. The objective stops it by returning
+    /// `TrialError::pruned()`. A trial that cannot be read is not pruned.
+    pub fn should_prune(&self) -> bool {
+        let maybe_trial = self.study.storage.borrow().get_trial(self.trial_id);
+        match maybe_trial {
+            Ok(Some(trial)) => self.study.pruner.borrow_mut().prune(self.study, &trial),
+            _ => false,
+        }
+    }
+
+    fn suggest(&self, name: &str, distribution: Distribution) -> Result<f64, TrialError> {
+        distribution.validate()?;
+        self.study.record_heartbeat(self.trial_id);
+        let maybe_trial = self.study.storage.borrow().get_trial(self.trial_id)?;
+        let trial = maybe_trial.ok_or_else(|| TrialError::new("Not found specific trial"))?;
+        if let Some(param) = trial.params.get(name) {
+            return if trial.distributions[name].is_compatible(&distribution) {
+                Ok(*param)
+            } else {
+                Err(TrialError::new(&format!(
+                    "param {} is already suggested with another distribution",
+                    name
+                )))
+            };
+        }
+
+        let relative_param = self.relative_params.get(name).filter(|_| {
+            self.relative_search_space
+                .get(name)
+                .is_some_and(|d| d == &distribution)
+        });
+        let param = match relative_param {
+            Some(&param) => param,
+            None => self.study.with_sampler(&trial, |sampler| {
+                sampler.sample_independent(self.study, &trial, name, &distribution)
+            }),
+        };
+        self.study.storage.borrow_mut().set_trial_param(
+            self.trial_id,
+            name,
+            param,
+            distribution,
+        )?;
+        Ok(param)
+    }
+}
+
+/// Decides the parameters of every trial.
+///
+/// Parameters are sampled in two ways. Relative sampling decides the
+/// parameters of `infer_relative_search_space` jointly when a trial starts,
+/// taking correlations between them into account. Every other parameter is
+/// sampled on its own by `sample_independent` when the objective asks for it.
+/// All values are in the internal representation of their `Distribution`.
+/// Sampling cannot fail: a sampler that cannot read the trials of the study
+/// samples as if there were none.
+pub trait Sampler {
+    fn infer_relative_search_space(
+        &mut self,
+        _study: &Study,
+        _trial: &FrozenTrial,
+    ) -> HashMap<String, Distribution> {
+        HashMap::new()
+    }
+
+    fn sample_relative(
+        &mut self,
+        _study: &Study,
+        _trial: &FrozenTrial,
+        _search_space: &HashMap<String, Distribution>,
+    ) -> HashMap<String, f64> {
+        HashMap::new()
+    }
+
+    fn sample_independent(
+        &mut self,
+        study: &Study,
+        trial: &FrozenTrial,
+        name: &str,
+        distribution: &Distribution,
+    ) -> f64;
+
+    /// Called when a trial is created, before any parameter is sampled.
+    fn before_trial(&mut self, _study: &Study, _trial: &FrozenTrial) {}
+
+    /// Called when the objective function returns, before `state` and `values`
+    /// are written to storage.
+    fn after_trial(
+        &mut self,
+        _study: &Study,
+        _trial: &FrozenTrial,
+        _state: TrialState,
+        _values: Option<&[f64]>,
+    ) {
+    }
+}
+
+/// Decides whether a trial should stop early, from the intermediate values it
+/// and the other trials of the study reported. A pruner that cannot read the
+/// trials of the study does not prune.
+pub trait Pruner {
+    fn prune(&mut self, study: &Study, trial: &FrozenTrial) -> bool;
+
+    /// The part of `trials` the sampler considers when it samples the trial
+    /// with number `trial_number`. Pruners that run separate groups of trials,
+    /// such as the brackets of `HyperbandPruner`, keep each group to itself.
+    fn filter_trials(&self, _trial_number: u32, trials: Vec<FrozenTrial>) -> Vec<FrozenTrial> {
+        trials
+    }
+}
+
+/// A pruner that never prunes.
+pub struct NopPruner;
+
+impl Pruner for NopPruner {
+    fn prune(&mut self, _study: &Study, _trial: &FrozenTrial) -> bool {
+        false
+    }
+}
+
+/// Random number generator of a sampler, reseeded from the seed of the
+/// sampler and the number of every trial it samples.
+///
+/// The samples of a trial thus only depend on the seed, the trial number and
+/// the trials in storage, so a study resumed from its storage by another
+/// process samples the remaining trials as an uninterrupted run would.
+pub(crate) struct TrialRng {
+    seed: u64,
+    number: Option<u32>,
+    rng: StdRng,
+}
+
+impl TrialRng {
+    pub(crate) fn new(seed: u64) -> Self {
+        TrialRng {
+            seed,
+            number: None,
+            rng: SeedableRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Reseeds the generator for `trial`, unless it was reseeded for it last.
+    pub(crate) fn reseed_for(&mut self, trial: &FrozenTrial) {
+        if self.number == Some(trial.number) {
+            return;
+        }
+        let seed = self.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            ^ u64::from(trial.number).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        self.rng = SeedableRng::seed_from_u64(seed);
+        self.number = Some(trial.number);
+    }
+}
+
+impl std::ops::Deref for TrialRng {
+    type Target = StdRng;
+
+    fn deref(&self) -> &StdRng {
+        &self.rng
+    }
+}
+
+impl std::ops::DerefMut for TrialRng {
+    fn deref_mut(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+}
+
+pub struct RandomSampler {
+    rng: TrialRng,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> Self {
+        RandomSampler {
+            rng: TrialRng::new(seed),
+        }
+    }
+
+    fn uniform(&mut self, low: f64, high: f64) -> f64 {
+        if low < high {
+            self.rng.gen_range(low, high)
+        } else {
+            low
+        }
+    }
+}
+
+impl Sampler for RandomSampler {
+    /// Samples the internal representation of a parameter uniformly at random.
+    fn sample_independent(
+        &mut self,
+        _study: &Study,
+        trial: &FrozenTrial,
+        _name: &str,
+        distribution: &Distribution,
+    ) -> f64 {
+        self.rng.reseed_for(trial);
+        match *distribution {
+            Distribution::Uniform { low, high } => self.uniform(low, high),
+            Distribution::LogUniform { low, high } => self.uniform(low.ln(), high.ln()).exp(),
+            Distribution::Int {
+                low,
+                high,
+                step,
+                log,
+            } => {
+                let (low, high, step) = (low as f64, high as f64, step as f64);
+                let value = if log {
+                    self.uniform((low - 0.5).ln(), (high + 0.5).ln()).exp()
+                } else {
+                    self.uniform(low - 0.5 * step, high + 0.5 * step)
+                };
+                let value = ((value - low) / step).round() * step + low;
+                value.max(low).min(high)
+            }
+            Distribution::DiscreteUniform { low, high, q } => {
+                let value = self.uniform(low - 0.5 * q, high + 0.5 * q);
+                let value = ((value - low) / q).round() * q + low;
+                value.max(low).min(high)
+            }
+            Distribution::Categorical { ref choices } => {
+                self.rng.gen_range(0, choices.len()) as f64
+            }
+        }
+    }
+}
+
+/// System attribute of a trial naming the process which runs it, as
+/// `{host}:{namespace}:{pid}`, see `pid_namespace`.
+const WORKER_ATTR: &str = "worker";
+/// System attribute of a running trial with the time of its last heartbeat,
+/// in seconds since the Unix epoch.
+const HEARTBEAT_ATTR: &str = "heartbeat";
+/// Study system attribute with the seed of the default sampler.
+const SAMPLER_SEED_ATTR: &str = "sampler_seed";
+
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |time| time.as_secs_f64())
+}
+
+fn worker_name() -> String {
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| "localhost".to_string());
+    let namespace = pid_namespace().unwrap_or_else(|| "unknown".to_string());
+    format!("{}:{}:{}", host.trim(), namespace, std::process::id())
+}
+
+/// The boot of the kernel and the PID namespace of this process, as
+/// `{boot_id}/{inode}`, within which pids name one process. Containers which
+/// share a hostname have PID namespaces of their own.
+fn pid_namespace() -> Option<String> {
+    let boot_id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?;
+    let link = std::fs::read_link("/proc/self/ns/pid").ok()?;
+    let inode: String = link
+        .to_string_lossy()
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect();
+    Some(format!("{}/{}", boot_id.trim(), inode))
+}
+
+/// Whether `worker`, a `worker_name` of some process, names another process of
+/// this host and PID namespace which exited. Only known where `/proc` lists
+/// processes.
+fn is_dead_worker(worker: &str) -> bool {
+    if pid_namespace().is_none() {
+        return false;
+    }
+    let this_worker = worker_name();
+    let (namespace, pid) = match worker.rsplit_once(':') {
+        Some(split) => split,
+        None => return false,
+    };
+    let this_namespace = this_worker.rsplit_once(':').map(|(namespace, _)| namespace);
+    worker != this_worker
+        && this_namespace == Some(namespace)
+        && !Path::new("/proc").join(pid).exists()
+}
+
+pub struct Study {
+    study_name: String,
+    study_id: u32,
+    directions: Vec<StudyDirection>,
+    storage: Rc<RefCell<dyn Storage>>,
+    sampler: RefCell<Box<dyn Sampler>>,
+    pruner: RefCell<Box<dyn Pruner>>,
+    seed: Option<u64>,
+    stop_flag: Cell<bool>,
+    /// Number of the trial the sampler is working on, if any.
+    sampling_trial_number: Cell<Option<u32>>,
+    heartbeat_interval: Option<Duration>,
+    grace_period: Duration,
+    failed_trial_callback: Option<Box<dyn FailedTrialCallback>>,
+    /// The trial this study recorded a heartbeat for last, and when.
+    last_heartbeat: Cell<Option<(u32, Instant)>>,
+}
+
+impl Study {
+    pub fn study_name(&self) -> &str {
+        &self.study_name
+    }
+
+    pub fn study_id(&self) -> u32 {
+        self.study_id
+    }
+
+    /// The seed given to `StudyBuilder::seed`, if any.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// The storage of the study, to share it with other studies.
+    pub fn storage(&self) -> Rc<RefCell<dyn Storage>> {
+        Rc::clone(&self.storage)
+    }
+
+    /// Direction of a single-objective study, or of the first objective of a
+    /// multi-objective one.
+    pub fn direction(&self) -> StudyDirection {
+        self.directions[0]
+    }
+
+    pub fn directions(&self) -> &[StudyDirection] {
+        &self.directions
+    }
+
+    /// Runs `n_trials` more trials, waiting ones first, after failing the
+    /// trials that processes which exited left running, see
+    /// `fail_trials_of_dead_workers`. Stale trials are failed before every
+    /// trial, see `fail_stale_trials`.
+    pub fn optimize<T: MultiObjective>(&self, objective: T, n_trials: u32) {
+        self.stop_flag.set(false);
+        if let Err(err) = self.fail_trials_of_dead_workers() {
+            eprintln!("cannot read the running trials: {}", err.message);
+            return;
+        }
+        let worker = worker_name();
+        for _ in 0..n_trials {
+            if self.stop_flag.get() {
+                break;
+            }
+            let started = self
+                .fail_stale_trials()
+                .and_then(|_| self.start_trial(&worker));
+            match started {
+                Ok(trial_id) => self.run_trial(&objective, trial_id),
+                Err(err) => {
+                    eprintln!("cannot create a trial: {}", err.message);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Marks the running trials of processes of this host which exited, e.g.
+    /// by a crash, as failed, and returns their ids. `optimize` records the
+    /// process of every trial it runs. Trials of other hosts, or of other PID
+    /// namespaces of this host such as other containers, are left alone.
+    pub fn fail_trials_of_dead_workers(&self) -> Result<Vec<u32>, TrialError> {
+        self.fail_running_trials(|trial| {
+            let worker = trial.system_attrs.get(WORKER_ATTR);
+            worker.is_some_and(|worker| is_dead_worker(worker))
+        })
+    }
+
+    /// Marks the running trials whose last heartbeat is older than the grace
+    /// period as failed, and returns their ids. Nothing is stale unless the
+    /// study records heartbeats, see `StudyBuilder::heartbeat_interval`.
+    pub fn fail_stale_trials(&self) -> Result<Vec<u32>, TrialError> {
+        if self.heartbeat_interval.is_none() {
+            return Ok(Vec::new());
+        }
+        let now = unix_time();
+        let grace_period = self.grace_period.as_secs_f64();
+        self.fail_running_trials(|trial| {
+            let heartbeat = trial.system_attrs.get(HEARTBEAT_ATTR);
+            let heartbeat = heartbeat.and_then(|heartbeat| heartbeat.parse::<f64>().ok());
+            heartbeat.is_some_and(|heartbeat| now - heartbeat > grace_period)
+        })
+    }
+
+    /// Fails the running trials `is_abandoned` picks, then passes each one to
+    /// the failed trial callback.
+    fn fail_running_trials(
+        &self,
+        is_abandoned: impl Fn(&FrozenTrial) -> bool,
+    ) -> Result<Vec<u32>, TrialError> {
+        let running = self
+            .storage
+            .borrow()
+            .get_all_trials(self.study_id, Some(&[TrialState::Running]))?;
+        // Another worker may fail the same trial first.
+        let failed: Vec<FrozenTrial> = running
+            .into_iter()
+            .filter(|trial| is_abandoned(trial))
+            .filter(|trial| {
+                let mut storage = self.storage.borrow_mut();
+                storage
+                    .set_trial_state(trial.trial_id, TrialState::Failed)
+                    .is_ok()
+            })
+            .map(|trial| FrozenTrial {
+                state: TrialState::Failed,
+                ..trial
+            })
+            .collect();
+        if let Some(callback) = &self.failed_trial_callback {
+            for trial in &failed {
+                callback.on_failed_trial(self, trial);
+            }
+        }
+        Ok(failed.iter().map(|trial| trial.trial_id).collect())
+    }
+
+    /// Moves the oldest waiting trial to running, or creates a new trial if
+    /// there is none, on behalf of `worker`.
+    fn start_trial(&self, worker: &str) -> Result<u32, TrialError> {
+        let waiting = self
+            .storage
+            .borrow()
+            .get_all_trials(self.study_id, Some(&[TrialState::Waiting]))?;
+        for trial in waiting {
+            // Another worker may start the same trial first.
+            let mut storage = self.storage.borrow_mut();
+            if storage
+                .set_trial_state(trial.trial_id, TrialState::Running)
+                .is_ok()
+            {
+                storage.set_trial_system_attr(trial.trial_id, WORKER_ATTR, worker)?;
+                return Ok(trial.trial_id);
+            }
+        }
+        let mut template = FrozenTrial::new(0, TrialState::Running);
+        template
+            .system_attrs
+            .insert(WORKER_ATTR.to_string(), worker.to_string());
+        self.storage
+            .borrow_mut()
+            .create_new_trial(self.study_id, Some(&template))
+    }
+
+    /// Records a heartbeat of the running trial, at most once per heartbeat
+    /// interval.
+    fn record_heartbeat(&self, trial_id: u32) {
+        let interval = match self.heartbeat_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let now = Instant::now();
+        if let Some((last_trial_id, last)) = self.last_heartbeat.get() {
+            if last_trial_id == trial_id && now.duration_since(last) < interval {
+                return;
+            }
+        }
+        let heartbeat = unix_time().to_string();
+        let recorded = self.set_trial_system_attr(trial_id, HEARTBEAT_ATTR, &heartbeat);
+        if recorded.is_ok() {
+            self.last_heartbeat.set(Some((trial_id, now)));
+        }
+    }
+
+    fn run_trial<T: MultiObjective>(&self, objective: &T, trial_id: u32) {
+        self.record_heartbeat(trial_id);
+        let maybe_trial = self.storage.borrow().get_trial(trial_id);
+        if let Ok(Some(frozen_trial)) = maybe_trial {
+            self.with_sampler(&frozen_trial, |sampler| {
+                sampler.before_trial(self, &frozen_trial)
+            });
+        }
+
+        let trial = Trial::new(trial_id, self);
+        let values = objective.objectives(trial).and_then(|values| {
+            if values.len() == self.directions.len() {
+                Ok(values)
+            } else {
+                Err(TrialError::new(&format!(
+                    "objective returned {} values for {} directions",
+                    values.len(),
+                    self.directions.len()
+                )))
+            }
+        });
+
+        // A trial that cannot be read back fails with the error of the storage.
+        let maybe_trial = self.storage.borrow().get_trial(trial_id);
+        let (values, maybe_trial) = match maybe_trial {
+            Ok(maybe_trial) => (values, maybe_trial),
+            Err(err) => (Err(err), None),
+        };
+        // A pruned trial takes its last intermediate value as its value.
+        let (state, values) = match values {
+            Ok(values) => (TrialState::Completed, Ok(Some(values))),
+            Err(err) if err.kind == TrialErrorKind::Pruned => {
+                let last_value = maybe_trial.as_ref().and_then(|t| {
+                    let step = t.last_step()?;
+                    Some(vec![t.intermediate_values[&step]])
+                });
+                (TrialState::Pruned, Ok(last_value))
+            }
+            Err(err) => (TrialState::Failed, Err(err)),
+        };
+        if let Some(frozen_trial) = maybe_trial {
+            let values = values.as_ref().ok().and_then(|v| v.as_deref());
+            self.with_sampler(&frozen_trial, |sampler| {
+                sampler.after_trial(self, &frozen_trial, state, values)
+            });
+        }
+
+        let result = values
+            .and_then(|values| match values {
+                Some(v) => self.storage.borrow_mut().set_trial_values(trial_id, &v),
+                None => Ok(()),
+            })
+            .and_then(|_| self.storage.borrow_mut().set_trial_state(trial_id, state));
+
+        if let Err(err) = result {
+            eprintln!("trial_id={} is failed by {}", trial_id, err.message);
+            let _ = self
+                .storage
+                .borrow_mut()
+                .set_trial_state(trial_id, TrialState::Failed);
+        }
+    }
+
+    pub fn set_trial_system_attr(
+        &self,
+        trial_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        self.storage
+            .borrow_mut()
+            .set_trial_system_attr(trial_id, key, value)
+    }
+
+    pub fn set_user_attr(&self, key: &str, value: &str) -> Result<(), TrialError> {
+        self.storage
+            .borrow_mut()
+            .set_study_user_attr(self.study_id, key, value)
+    }
+
+    /// Attributes the user attaches to the study.
+    pub fn user_attrs(&self) -> Result<HashMap<String, String>, TrialError> {
+        self.storage.borrow().get_study_user_attrs(self.study_id)
+    }
+
+    pub fn set_system_attr(&self, key: &str, value: &str) {
+        let _ = self
+            .storage
+            .borrow_mut()
+            .set_study_system_attr(self.study_id, key, value);
+    }
+
+    /// Sets a system attribute if it has the value `expected`, or is unset
+    /// for `None`, and returns whether it did, atomically for all workers of
+    /// the study. See `Storage::compare_and_set_study_system_attr`.
+    pub fn compare_and_set_system_attr(
+        &self,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+    ) -> Result<bool, TrialError> {
+        self.storage.borrow_mut().compare_and_set_study_system_attr(
+            self.study_id,
+            key,
+            expected,
+            value,
+        )
+    }
+
+    /// Attributes samplers and pruners attach to the study for their own
+    /// bookkeeping.
+    pub fn system_attrs(&self) -> Result<HashMap<String, String>, TrialError> {
+        self.storage.borrow().get_study_system_attrs(self.study_id)
+    }
+
+    /// Makes the running `optimize` return once the current trial finishes.
+    pub fn stop(&self) {
+        self.stop_flag.set(true);
+    }
+
+    /// Trials of this study, optionally only those in one of `states`.
+    ///
+    /// While the sampler works on a trial, only the trials the pruner lets it
+    /// see for that trial are returned, see `Pruner::filter_trials`.
+    pub fn get_trials(
+        &self,
+        states: Option<&[TrialState]>,
+    ) -> Result<Vec<FrozenTrial>, TrialError> {
+        let trials = self
+            .storage
+            .borrow()
+            .get_all_trials(self.study_id, states)?;
+        Ok(match self.sampling_trial_number.get() {
+            Some(number) => self.pruner.borrow().filter_trials(number, trials),
+            None => trials,
+        })
+    }
+
+    /// Every trial of this study, optionally only those in one of `states`,
+    /// even while the sampler works on a trial. For bookkeeping which spans
+    /// the groups of trials the pruner keeps apart, see `get_trials`.
+    pub fn get_unfiltered_trials(
+        &self,
+        states: Option<&[TrialState]>,
+    ) -> Result<Vec<FrozenTrial>, TrialError> {
+        self.storage.borrow().get_all_trials(self.study_id, states)
+    }
+
+    fn with_sampler<R>(&self, trial: &FrozenTrial, f: impl FnOnce(&mut dyn Sampler) -> R) -> R {
+        self.sampling_trial_number.set(Some(trial.number));
+        let result = f(self.sampler.borrow_mut().as_mut());
+        self.sampling_trial_number.set(None);
+        result
+    }
+
+    /// Best completed trial of a single-objective study. `None` for a
+    /// multi-objective study, see `best_trials`.
+    pub fn best_trial(&self) -> Result<Option<FrozenTrial>, TrialError> {
+        if self.directions.len() != 1 {
+            return Ok(None);
+        }
+        self.storage
+            .borrow()
+            .get_best_trial(self.study_id, self.direction())
+    }
+
+    /// The Pareto front: completed trials that no other completed trial
+    /// dominates. For a single-objective study, the trials sharing the best
+    /// value.
+    pub fn best_trials(&self) -> Result<Vec<FrozenTrial>, TrialError> {
+        self.storage
+            .borrow()
+            .get_pareto_front_trials(self.study_id, &self.directions)
+    }
+}
+
+/// Builder returned by `create_study`.
+///
+/// Every setting is optional: a study without a name gets a random one, a study
+/// without directions minimizes a single objective, a study without storage starts
+/// with an empty `InMemoryStorage`, a study without a sampler uses
+/// `RandomSampler::new(seed)`, where the seed is random unless given or stored
+/// for the study when it was started, and a
+/// study without a pruner never prunes. A study named like a study in the
+/// storage continues it, with the directions stored for it.
+#[derive(Default)]
+pub struct StudyBuilder {
+    study_name: Option<String>,
+    directions: Option<Vec<StudyDirection>>,
+    storage: Option<Rc<RefCell<dyn Storage>>>,
+    sampler: Option<Box<dyn Sampler>>,
+    pruner: Option<Box<dyn Pruner>>,
+    seed: Option<u64>,
+    heartbeat_interval: Option<Duration>,
+    grace_period: Option<Duration>,
+    failed_trial_callback: Option<Box<dyn FailedTrialCallback>>,
+}
+
+impl StudyBuilder {
+    pub fn study_name(mut self, study_name: &str) -> Self {
+        self.study_name = Some(study_name.to_string());
+        self
+    }
+
+    pub fn direction(mut self, direction: StudyDirection) -> Self {
+        self.directions = Some(vec![direction]);
+        self
+    }
+
+    /// Directions of a multi-objective study, one per value the objective
+    /// returns.
+    pub fn directions(mut self, directions: &[StudyDirection]) -> Self {
+        self.directions = Some(directions.to_vec());
+        self
+    }
+
+    pub fn storage<S: Storage + 'static>(mut self, storage: S) -> Self {
+        self.storage = Some(Rc::new(RefCell::new(storage)));
+        self
+    }
+
+    /// A storage shared with other studies, e.g. from `Study::storage`.
+    pub fn shared_storage(mut self, storage: Rc<RefCell<dyn Storage>>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    pub fn sampler<S: Sampler + 'static>(mut self, sampler: S) -> Self {
+        self.sampler = Some(Box::new(sampler));
+        self
+    }
+
+    pub fn pruner<P: Pruner + 'static>(mut self, pruner: P) -> Self {
+        self.pruner = Some(Box::new(pruner));
+        self
+    }
+
+    /// Seed of the default sampler. Samplers given explicitly use their own
+    /// seed, except that a scrambling `QmcSampler` scrambles with this one.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Makes `optimize` record a heartbeat of the running trial in storage
+    /// when it starts, suggests or reports, at most once per
+    /// `heartbeat_interval`, and fail the running trials whose heartbeat is
+    /// older than the grace period.
+    ///
+    /// Heartbeats are only recorded from within those calls, not in the
+    /// background. An objective which computes for longer than the grace
+    /// period after its last suggestion or report must call
+    /// `Trial::heartbeat` meanwhile, or other workers fail its trial and
+    /// discard its result.
+    pub fn heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = Some(heartbeat_interval);
+        self
+    }
+
+    /// How long a running trial may go without a heartbeat before it is
+    /// failed. Ten times the heartbeat interval unless given.
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = Some(grace_period);
+        self
+    }
+
+    /// Called with every running trial the study fails because its worker
+    /// exited or its heartbeat is stale, see `FailedTrialCallback`.
+    pub fn failed_trial_callback<C: FailedTrialCallback + 'static>(mut self, callback: C) -> Self {
+        self.failed_trial_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Panics when the study exists with other directions.
+    pub fn build(self) -> Study {
+        self.open(true)
+            .unwrap_or_else(|err| panic!("cannot create the study: {}", err.message))
+    }
+
+    /// Like `build`, but fails unless the storage has the study already.
+    pub fn load(self) -> Result<Study, TrialError> {
+        self.open(false)
+    }
+
+    fn open(self, create: bool) -> Result<Study, TrialError> {
+        let study_name = match self.study_name {
+            Some(study_name) => study_name,
+            None if create => format!("no-name-{:016x}", random::<u64>()),
+            None => return Err(TrialError::new("a study to load needs a name")),
+        };
+        let storage = self
+            .storage
+            .unwrap_or_else(|| Rc::new(RefCell::new(InMemoryStorage::new())));
+        let directions = self.directions.filter(|directions| !directions.is_empty());
+        let (study_id, directions) = {
+            let mut storage = storage.borrow_mut();
+            let study_id = match storage.get_study_id_from_name(&study_name)? {
+                Some(study_id) => study_id,
+                None if create => storage.create_new_study(&study_name)?,
+                None => {
+                    let message = format!("study {} is not found", study_name);
+                    return Err(TrialError::new(&message));
+                }
+            };
+            let stored = storage.get_study_directions(study_id)?;
+            let directions = match directions {
+                Some(directions) => directions,
+                None if !stored.is_empty() => stored.clone(),
+                None => vec![StudyDirection::default()],
+            };
+            if stored != directions {
+                storage.set_study_directions(study_id, &directions)?;
+            }
+            (study_id, directions)
+        };
+        // The default sampler of a continued study keeps the seed it started
+        // with, so that it samples the remaining trials deterministically.
+        let stored_seed = storage
+            .borrow()
+            .get_study_system_attrs(study_id)?
+            .get(SAMPLER_SEED_ATTR)
+            .and_then(|seed| seed.parse().ok());
+        let seed = self.seed.or(stored_seed).unwrap_or_else(random);
+        if self.sampler.is_none() && stored_seed.is_none() {
+            storage.borrow_mut().set_study_system_attr(
+                study_id,
+                SAMPLER_SEED_ATTR,
+                &seed.to_string(),
+            )?;
+        }
+        let grace_period = self
+            .grace_period
+            .or(self.heartbeat_interval.map(|interval| interval * 10))
+            .unwrap_or_default();
+        Ok(Study {
+            study_name,
+            study_id,
+            directions,
+            storage,
+            sampler: RefCell::new(
+                self.sampler
+                    .unwrap_or_else(|| Box::new(RandomSampler::new(seed))),
+            ),
+            pruner: RefCell::new(self.pruner.unwrap_or_else(|| Box::new(NopPruner))),
+            seed: self.seed,
+            stop_flag: Cell::new(false),
+            sampling_trial_number: Cell::new(None),
+            heartbeat_interval: self.heartbeat_interval,
+            grace_period,
+            failed_trial_callback: self.failed_trial_callback,
+            last_heartbeat: Cell::new(None),
+        })
+    }
+}
+
+pub fn create_study() -> StudyBuilder {
+    StudyBuilder::default()
+}
+
+/// Loads a study of `storage` with its stored directions, the default sampler
+/// and no pruner. `create_study().load()` loads it with other settings.
+pub fn load_study(
+    study_name: &str,
+    storage: Rc<RefCell<dyn Storage>>,
+) -> Result<Study, TrialError> {
+    create_study()
+        .study_name(study_name)
+        .shared_storage(storage)
+        .load()
+}
+
+pub fn delete_study(study_name: &str, storage: &mut dyn Storage) -> Result<(), TrialError> {
+    let study_id = find_study_id(study_name, storage)?;
+    storage.delete_study(study_id)
+}
+
+/// Copies a study of `from_storage` with its attributes and trials to
+/// `to_storage`, named `to_study_name` or like the original. Trials keep their
+/// numbers and get new trial ids. Returns the `study_id` of the copy.
+pub fn copy_study(
+    from_study_name: &str,
+    from_storage: &dyn Storage,
+    to_storage: &mut dyn Storage,
+    to_study_name: Option<&str>,
+) -> Result<u32, TrialError> {
+    let from_study_id = find_study_id(from_study_name, from_storage)?;
+    let study_id = to_storage.create_new_study(to_study_name.unwrap_or(from_study_name))?;
+    let directions = from_storage.get_study_directions(from_study_id)?;
+    if !directions.is_empty() {
+        to_storage.set_study_directions(study_id, &directions)?;
+    }
+    for (key, value) in from_storage.get_study_user_attrs(from_study_id)? {
+        to_storage.set_study_user_attr(study_id, &key, &value)?;
+    }
+    for (key, value) in from_storage.get_study_system_attrs(from_study_id)? {
+        to_storage.set_study_system_attr(study_id, &key, &value)?;
+    }
+    for trial in from_storage.get_all_trials(from_study_id, None)? {
+        to_storage.create_new_trial(study_id, Some(&trial))?;
+    }
+    Ok(study_id)
+}
+
+fn find_study_id(study_name: &str, storage: &dyn Storage) -> Result<u32, TrialError> {
+    storage
+        .get_study_id_from_name(study_name)?
+        .ok_or_else(|| TrialError::new(&format!("study {} is not found", study_name)))
+}
+
+/// A study of a storage, as `get_all_study_summaries` lists it.
+#[derive(Clone)]
+pub struct StudySummary {
+    study_name: String,
+    study_id: u32,
+    directions: Vec<StudyDirection>,
+    n_trials: usize,
+    best_trial: Option<FrozenTrial>,
+    user_attrs: HashMap<String, String>,
+    system_attrs: HashMap<String, String>,
+}
+
+impl StudySummary {
+    pub fn study_name(&self) -> &str {
+        &self.study_name
+    }
+
+    pub fn study_id(&self) -> u32 {
+        self.study_id
+    }
+
+    /// Directions of the study, empty if no `Study` was built for it yet.
+    pub fn directions(&self) -> &[StudyDirection] {
+        &self.directions
+    }
+
+    pub fn n_trials(&self) -> usize {
+        self.n_trials
+    }
+
+    /// Best completed trial of a single-objective study.
+    pub fn best_trial(&self) -> Option<&FrozenTrial> {
+        self.best_trial.as_ref()
+    }
+
+    pub fn user_attrs(&self) -> &HashMap<String, String> {
+        &self.user_attrs
+    }
+
+    pub fn system_attrs(&self) -> &HashMap<String, String> {
+        &self.system_attrs
+    }
+}
+
+/// Summaries of every study in `storage`, in the order they were created.
+pub fn get_all_study_summaries(storage: &dyn Storage) -> Result<Vec<StudySummary>, TrialError> {
+    let mut summaries = Vec::new();
+    for study_id in storage.get_all_study_ids()? {
+        let study_name = match storage.get_study_name_from_id(study_id)? {
+            Some(study_name) => study_name,
+            None => continue,
+        };
+        let directions = storage.get_study_directions(study_id)?;
+        let best_trial = match directions[..] {
+            [direction] => storage.get_best_trial(study_id, direction)?,
+            _ => None,
+        };
+        summaries.push(StudySummary {
+            study_name,
+            study_id,
+            directions,
+            n_trials: storage.get_all_trials(study_id, None)?.len(),
+            best_trial,
+            user_attrs: storage.get_study_user_attrs(study_id)?,
+            system_attrs: storage.get_study_system_attrs(study_id)?,
+        });
+    }
+    Ok(summaries)
+}
+
+pub trait Objective {
+    fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError>;
+}
+
+/// An objective function returning one value per direction of the study.
+/// Every `Objective` is a `MultiObjective` with a single value.
+pub trait MultiObjective {
+    fn objectives(&self, trial: Trial<'_>) -> Result<Vec<f64>, TrialError>;
+}
+
+impl<T: Objective> MultiObjective for T {
+    fn objectives(&self, trial: Trial<'_>) -> Result<Vec<f64>, TrialError> {
+        self.objective(trial).map(|value| vec![value])
+    }
+}
+
+/// Called with every running trial a study fails, e.g. to enqueue it again,
+/// see `retry::RetryFailedTrialCallback`: trials of workers which exited, see
+/// `Study::fail_trials_of_dead_workers`, and trials whose heartbeat is stale,
+/// see `Study::fail_stale_trials`. The worker of a stale trial may still be
+/// running it.
+pub trait FailedTrialCallback {
+    fn on_failed_trial(&self, study: &Study, trial: &FrozenTrial);
+}
+
+impl<F: Fn(&Study, &FrozenTrial)> FailedTrialCallback for F {
+    fn on_failed_trial(&self, study: &Study, trial: &FrozenTrial) {
+        self(study, trial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Quadratic;
+
+    impl Objective for Quadratic {
+        fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+            let x = trial.suggest_uniform("x", -10.0, 10.0)?;
+            Ok(x * x)
+        }
+    }
+
+    #[test]
+    fn suggested_params_are_persisted() {
+        let study = create_study().seed(1).build();
+        study.optimize(Quadratic, 10);
+
+        let best_trial = study.best_trial().unwrap().unwrap();
+        let x = best_trial.internal_params()["x"];
+        assert_eq!(best_trial.value(), Some(x * x));
+    }
+
+    #[test]
+    fn sampler_rng_advances_across_trials() {
+        let study = create_study().seed(1).build();
+        study.optimize(Quadratic, 2);
+
+        let storage = study.storage.borrow();
+        let x0 = storage.get_trial(0).unwrap().unwrap().internal_params()["x"];
+        let x1 = storage.get_trial(1).unwrap().unwrap().internal_params()["x"];
+        assert_ne!(x0, x1);
+    }
+
+    #[test]
+    fn best_trial_respects_direction() {
+        let minimize = create_study().seed(3).build();
+        let maximize = create_study()
+            .seed(3)
+            .direction(StudyDirection::Maximize)
+            .build();
+        minimize.optimize(Quadratic, 20);
+        maximize.optimize(Quadratic, 20);
+
+        let min_value = minimize.best_trial().unwrap().unwrap().value().unwrap();
+        let max_value = maximize.best_trial().unwrap().unwrap().value().unwrap();
+        assert!(min_value < max_value);
+        assert_eq!(maximize.direction(), StudyDirection::Maximize);
+    }
+
+    #[test]
+    fn best_trial_skips_non_finite_values() {
+        for &direction in [StudyDirection::Minimize, StudyDirection::Maximize].iter() {
+            let mut storage = InMemoryStorage::new();
+            let study_id = storage.create_new_study("study").unwrap();
+            let values = [1.0, f64::NAN, 2.0, f64::INFINITY, f64::NEG_INFINITY];
+            for &value in values.iter() {
+                let trial_id = storage.create_new_trial(study_id, None).unwrap();
+                storage.set_trial_value(trial_id, value).unwrap();
+                storage
+                    .set_trial_state(trial_id, TrialState::Completed)
+                    .unwrap();
+            }
+            let study = create_study()
+                .study_name("study")
+                .direction(direction)
+                .storage(storage)
+                .build();
+            let best = study.best_trial().unwrap().unwrap();
+            let expected = match direction {
+                StudyDirection::Minimize => 1.0,
+                StudyDirection::Maximize => 2.0,
+            };
+            assert_eq!(best.value(), Some(expected));
+            let best_trials = study.best_trials().unwrap();
+            assert_eq!(best_trials.len(), 1);
+            assert_eq!(best_trials[0].trial_id(), best.trial_id());
+        }
+    }
+
+    const ALL_STATES: [TrialState; 5] = [
+        TrialState::Waiting,
+        TrialState::Running,
+        TrialState::Completed,
+        TrialState::Pruned,
+        TrialState::Failed,
+    ];
+
+    fn storage_with_trial_in(state: TrialState) -> (InMemoryStorage, u32) {
+        let mut storage = InMemoryStorage::new();
+        let study_id = storage.create_new_study("study").unwrap();
+        let trial_id = storage.create_waiting_trial(study_id).unwrap();
+        match state {
+            TrialState::Waiting => (),
+            TrialState::Running => storage.set_trial_state(trial_id, state).unwrap(),
+            _ => {
+                storage
+                    .set_trial_state(trial_id, TrialState::Running)
+                    .unwrap();
+                storage.set_trial_state(trial_id, state).unwrap();
+            }
+        }
+        (storage, trial_id)
+    }
+
+    #[test]
+    fn legal_state_transitions() {
+        let legal = [
+            (TrialState::Waiting, TrialState::Running),
+            (TrialState::Running, TrialState::Completed),
+            (TrialState::Running, TrialState::Pruned),
+            (TrialState::Running, TrialState::Failed),
+        ];
+        for &(from, to) in legal.iter() {
+            let (mut storage, trial_id) = storage_with_trial_in(from);
+            assert!(storage.set_trial_state(trial_id, to).is_ok());
+            assert_eq!(storage.get_trial(trial_id).unwrap().unwrap().state(), to);
+        }
+    }
+
+    #[test]
+    fn illegal_state_transitions() {
+        for &from in ALL_STATES.iter() {
+            for &to in ALL_STATES.iter() {
+                if from.can_transition_to(to) {
+                    continue;
+                }
+                let (mut storage, trial_id) = storage_with_trial_in(from);
+                assert!(storage.set_trial_state(trial_id, to).is_err());
+                assert_eq!(storage.get_trial(trial_id).unwrap().unwrap().state(), from);
+            }
+        }
+    }
+
+    #[test]
+    fn only_running_trials_accept_values_and_params() {
+        for &state in ALL_STATES.iter() {
+            let (mut storage, trial_id) = storage_with_trial_in(state);
+            let running = state == TrialState::Running;
+            assert_eq!(storage.set_trial_value(trial_id, 1.0).is_ok(), running);
+            let distribution = Distribution::Uniform {
+                low: 0.0,
+                high: 1.0,
+            };
+            assert_eq!(
+                storage
+                    .set_trial_param(trial_id, "x", 1.0, distribution)
+                    .is_ok(),
+                running
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_trial_id_is_an_error() {
+        let mut storage = InMemoryStorage::new();
+        assert!(storage.create_new_trial(0, None).is_err());
+        assert!(storage.set_trial_value(0, 1.0).is_err());
+        let distribution = Distribution::Uniform {
+            low: 0.0,
+            high: 1.0,
+        };
+        assert!(storage.set_trial_param(0, "x", 1.0, distribution).is_err());
+        assert!(storage.set_trial_state(0, TrialState::Completed).is_err());
+    }
+
+    #[test]
+    fn failed_objective_marks_trial_failed() {
+        struct Failing;
+
+        impl Objective for Failing {
+            fn objective(&self, _trial: Trial<'_>) -> Result<f64, TrialError> {
+                Err(TrialError::new("diverged"))
+            }
+        }
+
+        let study = create_study().build();
+        study.optimize(Failing, 1);
+        let trial = study.storage.borrow().get_trial(0).unwrap().unwrap();
+        assert_eq!(trial.state(), TrialState::Failed);
+        assert!(study.best_trial().unwrap().is_none());
+    }
+
+    #[test]
+    fn objectives_return_one_value_per_direction() {
+        struct TwoValues;
+
+        impl MultiObjective for TwoValues {
+            fn objectives(&self, trial: Trial<'_>) -> Result<Vec<f64>, TrialError> {
+                let x = trial.suggest_uniform("x", 0.0, 1.0)?;
+                Ok(vec![x, 1.0 - x])
+            }
+        }
+
+        let study = create_study()
+            .directions(&[StudyDirection::Minimize, StudyDirection::Maximize])
+            .build();
+        study.optimize(TwoValues, 1);
+        let trial = study.storage.borrow().get_trial(0).unwrap().unwrap();
+        let x = trial.internal_params()["x"];
+        assert_eq!(trial.values(), Some(vec![x, 1.0 - x]));
+        assert_eq!(trial.value(), None);
+
+        let single = create_study().build();
+        single.optimize(TwoValues, 1);
+        let trial = single.storage.borrow().get_trial(0).unwrap().unwrap();
+        assert_eq!(trial.state(), TrialState::Failed);
+        assert_eq!(trial.values(), None);
+    }
+
+    #[test]
+    fn best_trials_are_the_pareto_front() {
+        let directions = [StudyDirection::Minimize, StudyDirection::Maximize];
+        let mut storage = InMemoryStorage::new();
+        let study_id = storage.create_new_study("pareto").unwrap();
+        let values = [[1.0, 1.0], [0.0, 0.0], [2.0, 2.0], [2.0, 1.0], [1.0, 1.0]];
+        for values in values.iter() {
+            let trial_id = storage.create_new_trial(study_id, None).unwrap();
+            storage.set_trial_values(trial_id, values).unwrap();
+            storage
+                .set_trial_state(trial_id, TrialState::Completed)
+                .unwrap();
+        }
+        let trial_id = storage.create_new_trial(study_id, None).unwrap();
+        storage.set_trial_values(trial_id, &[-1.0, 3.0]).unwrap();
+
+        let study = create_study()
+            .study_name("pareto")
+            .directions(&directions)
+            .storage(storage)
+            .build();
+        let mut front: Vec<u32> = study
+            .best_trials()
+            .unwrap()
+            .iter()
+            .map(|t| t.trial_id())
+            .collect();
+        front.sort_unstable();
+        assert_eq!(front, vec![0, 1, 2, 4]);
+        assert!(study.best_trial().unwrap().is_none());
+
+        // NaN and infinite values are worse than any finite value.
+        assert!(!dominates(&[f64::NAN, 0.0], &[1.0, 1.0], &directions));
+        assert!(dominates(&[1.0, 1.0], &[f64::NAN, 1.0], &directions));
+        assert!(dominates(&[1.0, 1.0], &[f64::INFINITY, 1.0], &directions));
+        assert!(!dominates(
+            &[f64::NAN, 1.0],
+            &[f64::INFINITY, 1.0],
+            &directions
+        ));
+
+        let single = create_study().seed(1).build();
+        single.optimize(Quadratic, 5);
+        let best_trials = single.best_trials().unwrap();
+        assert_eq!(best_trials.len(), 1);
+        assert_eq!(
+            best_trials[0].trial_id(),
+            single.best_trial().unwrap().unwrap().trial_id()
+        );
+    }
+
+    #[test]
+    fn pruned_objective_marks_trial_pruned() {
+        struct PruneAfterTwoSteps;
+
+        impl Pruner for PruneAfterTwoSteps {
+            fn prune(&mut self, _study: &Study, trial: &FrozenTrial) -> bool {
+                trial.intermediate_values().len() >= 2
+            }
+        }
+
+        struct Training;
+
+        impl Objective for Training {
+            fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+                for step in 0..10 {
+                    let loss = 1.0 / (step + 1) as f64;
+                    trial.report(step, loss)?;
+                    trial.report(step, 0.0)?;
+                    if trial.should_prune() {
+                        return Err(TrialError::pruned());
+                    }
+                }
+                Ok(0.0)
+            }
+        }
+
+        let study = create_study().pruner(PruneAfterTwoSteps).build();
+        study.optimize(Training, 1);
+        let trial = study.storage.borrow().get_trial(0).unwrap().unwrap();
+        assert_eq!(trial.state(), TrialState::Pruned);
+        assert_eq!(trial.last_step(), Some(1));
+        assert_eq!(trial.intermediate_values()[&0], 1.0);
+        assert_eq!(trial.value(), Some(0.5));
+
+        let unpruned = create_study().build();
+        unpruned.optimize(Training, 1);
+        let trial = unpruned.best_trial().unwrap().unwrap();
+        assert_eq!(trial.intermediate_values().len(), 10);
+        assert_eq!(trial.value(), Some(0.0));
+    }
+
+    #[test]
+    fn sample_independent_dispatches_on_distribution() {
+        let study = create_study().seed(5).build();
+        let trial = FrozenTrial::new(0, TrialState::Running);
+        let distributions = [
+            Distribution::Uniform {
+                low: -1.0,
+                high: 1.0,
+            },
+            Distribution::LogUniform {
+                low: 1e-5,
+                high: 1e-1,
+            },
+            Distribution::Int {
+                low: 1,
+                high: 9,
+                step: 2,
+                log: false,
+            },
+            Distribution::Int {
+                low: 1,
+                high: 1024,
+                step: 1,
+                log: true,
+            },
+            Distribution::DiscreteUniform {
+                low: 0.0,
+                high: 0.5,
+                q: 0.1,
+            },
+            Distribution::Categorical {
+                choices: vec![ParamValue::Bool(true), ParamValue::Bool(false)],
+            },
+        ];
+
+        let mut sampler = RandomSampler::new(5);
+        for distribution in distributions.iter() {
+            for _ in 0..100 {
+                let value = sampler.sample_independent(&study, &trial, "x", distribution);
+                assert!(distribution.contains(value), "{} {:?}", value, distribution);
+                match *distribution {
+                    Distribution::Int { low, step, .. } => {
+                        assert_eq!((value as i64 - low) % step, 0)
+                    }
+                    Distribution::Categorical { .. } => assert_eq!(value.fract(), 0.0),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn suggest_records_params_with_distribution() {
+        struct Model;
+
+        impl Objective for Model {
+            fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+                let n_layers = trial.suggest_int("n_layers", 1, 8, 1, false)?;
+                let n_units = trial.suggest_int("n_units", 4, 1024, 1, true)?;
+                let lr = trial.suggest_loguniform("lr", 1e-5, 1e-1)?;
+                let dropout = trial.suggest_discrete_uniform("dropout", 0.0, 0.55, 0.1)?;
+                assert!((1..=8).contains(&n_layers));
+                assert!((4..=1024).contains(&n_units));
+                assert!((1e-5..=1e-1).contains(&lr));
+                assert!((0.0..=0.5).contains(&dropout));
+                Ok(lr * n_layers as f64)
+            }
+        }
+
+        let study = create_study().seed(11).build();
+        study.optimize(Model, 20);
+
+        let best_trial = study.best_trial().unwrap().unwrap();
+        assert_eq!(best_trial.params().len(), 4);
+        assert_eq!(
+            best_trial.distributions()["dropout"],
+            Distribution::DiscreteUniform {
+                low: 0.0,
+                high: 0.5,
+                q: 0.1
+            }
+        );
+        assert!(matches!(
+            best_trial.params()["n_layers"],
+            ParamValue::Int(_)
+        ));
+    }
+
+    #[test]
+    fn suggest_categorical_returns_choices() {
+        struct Classifier;
+
+        impl Objective for Classifier {
+            fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+                let optimizer = trial.suggest_categorical("optimizer", &["adam", "sgd"])?;
+                let batch_norm = trial.suggest_categorical("batch_norm", &[true, false])?;
+                let penalty = if batch_norm { 0.0 } else { 1.0 };
+                Ok(if optimizer == "adam" {
+                    penalty
+                } else {
+                    2.0 + penalty
+                })
+            }
+        }
+
+        let study = create_study().seed(1).build();
+        study.optimize(Classifier, 20);
+
+        let params = study.best_trial().unwrap().unwrap().params();
+        assert_eq!(params["optimizer"], ParamValue::from("adam"));
+        assert_eq!(params["batch_norm"], ParamValue::Bool(true));
+    }
+
+    #[test]
+    fn categorical_repr_round_trip() {
+        let distribution = Distribution::Categorical {
+            choices: vec![ParamValue::from("relu"), ParamValue::from(0.5)],
+        };
+        let relu = ParamValue::from("relu");
+        assert_eq!(distribution.to_internal_repr(&relu), Some(0.0));
+        assert_eq!(distribution.to_external_repr(1.0), ParamValue::Float(0.5));
+        assert_eq!(distribution.to_internal_repr(&ParamValue::Bool(true)), None);
+    }
+
+    #[test]
+    fn suggest_rejects_invalid_ranges() {
+        struct Invalid;
+
+        impl Objective for Invalid {
+            fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+                assert!(trial.suggest_loguniform("lr", 0.0, 1.0).is_err());
+                assert!(trial.suggest_int("n", 1, 10, 2, true).is_err());
+                assert!(trial.suggest_int("m", 10, 1, 1, false).is_err());
+                assert!(trial.suggest_discrete_uniform("q", 0.0, 1.0, 0.0).is_err());
+                Ok(0.0)
+            }
+        }
+
+        let study = create_study().build();
+        study.optimize(Invalid, 1);
+        assert!(study.best_trial().unwrap().unwrap().params().is_empty());
+    }
+
+    #[test]
+    fn custom_sampler_is_plugged_in() {
+        use std::rc::Rc;
+
+        struct FixedSampler {
+            finished: Rc<RefCell<Vec<TrialState>>>,
+        }
+
+        impl Sampler for FixedSampler {
+            fn infer_relative_search_space(
+                &mut self,
+                _study: &Study,
+                _trial: &FrozenTrial,
+            ) -> HashMap<String, Distribution> {
+                let mut search_space = HashMap::new();
+                let distribution = Distribution::Uniform {
+                    low: -10.0,
+                    high: 10.0,
+                };
+                search_space.insert("x".to_string(), distribution);
+                search_space
+            }
+
+            fn sample_relative(
+                &mut self,
+                _study: &Study,
+                _trial: &FrozenTrial,
+                search_space: &HashMap<String, Distribution>,
+            ) -> HashMap<String, f64> {
+                search_space
+                    .keys()
+                    .map(|name| (name.clone(), 2.0))
+                    .collect()
+            }
+
+            fn sample_independent(
+                &mut self,
+                _study: &Study,
+                _trial: &FrozenTrial,
+                _name: &str,
+                _distribution: &Distribution,
+            ) -> f64 {
+                unreachable!()
+            }
+
+            fn after_trial(
+                &mut self,
+                _study: &Study,
+                _trial: &FrozenTrial,
+                state: TrialState,
+                _values: Option<&[f64]>,
+            ) {
+                self.finished.borrow_mut().push(state);
+            }
+        }
+
+        let finished = Rc::new(RefCell::new(Vec::new()));
+        let sampler = FixedSampler {
+            finished: Rc::clone(&finished),
+        };
+        let study = create_study().sampler(sampler).build();
+        study.optimize(Quadratic, 3);
+
+        assert_eq!(study.best_trial().unwrap().unwrap().value(), Some(4.0));
+        assert_eq!(*finished.borrow(), vec![TrialState::Completed; 3]);
+    }
+
+    #[test]
+    fn relative_params_are_only_used_for_their_distribution() {
+        struct RelativeSampler;
+
+        impl Sampler for RelativeSampler {
+            fn infer_relative_search_space(
+                &mut self,
+                _study: &Study,
+                _trial: &FrozenTrial,
+            ) -> HashMap<String, Distribution> {
+                let mut search_space = HashMap::new();
+                let distribution = Distribution::Uniform {
+                    low: -10.0,
+                    high: 10.0,
+                };
+                search_space.insert("x".to_string(), distribution);
+                search_space
+            }
+
+            fn sample_relative(
+                &mut self,
+                _study: &Study,
+                _trial: &FrozenTrial,
+                search_space: &HashMap<String, Distribution>,
+            ) -> HashMap<String, f64> {
+                search_space
+                    .keys()
+                    .map(|name| (name.clone(), 2.0))
+                    .collect()
+            }
+
+            fn sample_independent(
+                &mut self,
+                _study: &Study,
+                _trial: &FrozenTrial,
+                _name: &str,
+                _distribution: &Distribution,
+            ) -> f64 {
+                0.5
+            }
+        }
+
+        // The objective narrowed the range of x since the relative search
+        // space was inferred.
+        struct Narrowed;
+
+        impl Objective for Narrowed {
+            fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+                trial.suggest_uniform("x", 0.0, 1.0)
+            }
+        }
+
+        let study = create_study().sampler(RelativeSampler).build();
+        study.optimize(Narrowed, 1);
+        let trial = &study.get_trials(None).unwrap()[0];
+        assert_eq!(trial.state(), TrialState::Completed);
+        assert_eq!(trial.value(), Some(0.5));
+    }
+
+    #[test]
+    fn storage_rejects_incompatible_distributions() {
+        let mut storage = InMemoryStorage::new();
+        let study_id = storage.create_new_study("study").unwrap();
+        let other_study_id = storage.create_new_study("other").unwrap();
+        let first = storage.create_new_trial(study_id, None).unwrap();
+        let second = storage.create_new_trial(study_id, None).unwrap();
+        let other = storage.create_new_trial(other_study_id, None).unwrap();
+        let uniform = Distribution::Uniform {
+            low: 0.0,
+            high: 1.0,
+        };
+        let log_uniform = Distribution::LogUniform {
+            low: 0.1,
+            high: 1.0,
+        };
+
+        assert!(storage.set_trial_param(first, "x", 0.5, uniform).is_ok());
+        assert!(storage
+            .set_trial_param(second, "x", 0.5, log_uniform.clone())
+            .is_err());
+        assert!(storage
+            .set_trial_param(second, "y", 0.5, log_uniform)
+            .is_ok());
+        assert_eq!(
+            storage
+                .get_trial(second)
+                .unwrap()
+                .unwrap()
+                .distributions()
+                .len(),
+            1
+        );
+        // Other studies may use the name with another distribution.
+        assert!(storage
+            .set_trial_param(
+                other,
+                "x",
+                0.5,
+                Distribution::LogUniform {
+                    low: 0.1,
+                    high: 1.0
+                }
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn studies_sharing_a_storage_keep_their_own_trials() {
+        struct Tagged;
+
+        impl Objective for Tagged {
+            fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+                trial.set_user_attr("tag", "seen")?;
+                trial.suggest_uniform("x", 0.0, 1.0)
+            }
+        }
+
+        let first = create_study().study_name("first").build();
+        let second = create_study()
+            .study_name("second")
+            .shared_storage(first.storage())
+            .build();
+        first.optimize(Tagged, 2);
+        second.optimize(Tagged, 3);
+        second.set_user_attr("owner", "me").unwrap();
+
+        assert_ne!(first.study_id(), second.study_id());
+        assert_eq!(first.get_trials(None).unwrap().len(), 2);
+        assert_eq!(second.get_trials(None).unwrap().len(), 3);
+        assert_eq!(second.get_trials(None).unwrap()[0].trial_id(), 2);
+        let numbers: Vec<u32> = second
+            .get_trials(None)
+            .unwrap()
+            .iter()
+            .map(|t| t.number())
+            .collect();
+        assert_eq!(numbers, vec![0, 1, 2]);
+        assert_eq!(
+            second.get_trials(None).unwrap()[0].user_attrs()["tag"],
+            "seen"
+        );
+        assert_eq!(second.user_attrs().unwrap()["owner"], "me");
+        assert!(first.user_attrs().unwrap().is_empty());
+
+        let again = create_study()
+            .study_name("first")
+            .shared_storage(first.storage())
+            .build();
+        assert_eq!(again.study_id(), first.study_id());
+        assert_eq!(again.get_trials(None).unwrap().len(), 2);
+        let taken = first.storage().borrow_mut().create_new_study("first");
+        assert!(taken.is_err());
+    }
+
+    #[test]
+    fn studies_are_loaded_listed_and_deleted() {
+        let storage: Rc<RefCell<dyn Storage>> = Rc::new(RefCell::new(InMemoryStorage::new()));
+        assert!(load_study("maximized", Rc::clone(&storage)).is_err());
+        let maximized = create_study()
+            .study_name("maximized")
+            .direction(StudyDirection::Maximize)
+            .shared_storage(Rc::clone(&storage))
+            .seed(1)
+            .build();
+        maximized.optimize(Quadratic, 3);
+        let other = create_study()
+            .study_name("other")
+            .shared_storage(Rc::clone(&storage))
+            .build();
+        other.optimize(Quadratic, 1);
+
+        let loaded = load_study("maximized", Rc::clone(&storage)).unwrap();
+        assert_eq!(loaded.study_id(), maximized.study_id());
+        assert_eq!(loaded.directions(), &[StudyDirection::Maximize]);
+        let minimized = create_study()
+            .study_name("maximized")
+            .direction(StudyDirection::Minimize)
+            .shared_storage(Rc::clone(&storage))
+            .load();
+        assert!(minimized.is_err());
+
+        let summaries = get_all_study_summaries(&*storage.borrow()).unwrap();
+        let names: Vec<&str> = summaries.iter().map(|s| s.study_name()).collect();
+        assert_eq!(names, vec!["maximized", "other"]);
+        assert_eq!(summaries[0].n_trials(), 3);
+        let best = summaries[0].best_trial().unwrap();
+        assert_eq!(
+            best.value(),
+            maximized.best_trial().unwrap().unwrap().value()
+        );
+
+        delete_study("maximized", &mut *storage.borrow_mut()).unwrap();
+        assert!(delete_study("maximized", &mut *storage.borrow_mut()).is_err());
+        assert!(storage.borrow().get_trial(0).unwrap().is_none());
+        assert_eq!(
+            get_all_study_summaries(&*storage.borrow()).unwrap().len(),
+            1
+        );
+        let recreated = create_study()
+            .study_name("maximized")
+            .shared_storage(Rc::clone(&storage))
+            .build();
+        recreated.optimize(Quadratic, 1);
+        assert_eq!(recreated.direction(), StudyDirection::Minimize);
+        assert!(recreated.study_id() > other.study_id());
+        assert_eq!(recreated.get_trials(None).unwrap()[0].trial_id(), 4);
+        assert_eq!(recreated.get_trials(None).unwrap()[0].number(), 0);
+    }
+
+    #[test]
+    fn loaded_study_samples_like_an_uninterrupted_run() {
+        let uninterrupted = create_study().seed(7).build();
+        uninterrupted.optimize(Quadratic, 6);
+
+        let interrupted = create_study().study_name("resumed").seed(7).build();
+        interrupted.optimize(Quadratic, 3);
+        let storage = interrupted.storage();
+        drop(interrupted);
+        let resumed = load_study("resumed", storage).unwrap();
+        resumed.optimize(Quadratic, 3);
+
+        let trials = resumed.get_trials(None).unwrap();
+        let numbers: Vec<u32> = trials.iter().map(|t| t.number()).collect();
+        assert_eq!(numbers, vec![0, 1, 2, 3, 4, 5]);
+        for (trial, expected) in trials.iter().zip(uninterrupted.get_trials(None).unwrap()) {
+            assert_eq!(trial.internal_params(), expected.internal_params());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn trials_of_dead_workers_are_failed() {
+        let study = create_study().study_name("crashed").build();
+        let mut exited = std::process::Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        let this_worker = worker_name();
+        let namespace = this_worker.rsplit_once(':').unwrap().0;
+        let host = namespace.split_once(':').unwrap().0;
+        let workers = [
+            format!("{}:{}", namespace, exited.id()),
+            this_worker.clone(),
+            format!("elsewhere:{}", exited.id()),
+            format!("{}:another-container:{}", host, exited.id()),
+        ];
+        for worker in &workers {
+            let mut template = FrozenTrial::new(0, TrialState::Running);
+            template
+                .system_attrs
+                .insert(WORKER_ATTR.to_string(), worker.clone());
+            let mut storage = study.storage.borrow_mut();
+            storage
+                .create_new_trial(study.study_id(), Some(&template))
+                .unwrap();
+        }
+
+        study.optimize(Quadratic, 1);
+        let states: Vec<TrialState> = study
+            .get_trials(None)
+            .unwrap()
+            .iter()
+            .map(|t| t.state())
+            .collect();
+        let expected = [
+            TrialState::Failed,
+            TrialState::Running,
+            TrialState::Running,
+            TrialState::Running,
+            TrialState::Completed,
+        ];
+        assert_eq!(states, expected);
+        let trial = &study.get_trials(None).unwrap()[4];
+        assert_eq!(trial.system_attrs()[WORKER_ATTR], this_worker);
+        assert!(study.fail_trials_of_dead_workers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stale_trials_are_failed_after_the_grace_period() {
+        let failed = Rc::new(RefCell::new(Vec::new()));
+        let seen = Rc::clone(&failed);
+        let study = create_study()
+            .heartbeat_interval(Duration::from_millis(10))
+            .grace_period(Duration::from_secs(30))
+            .failed_trial_callback(move |_: &Study, trial: &FrozenTrial| {
+                assert_eq!(trial.state(), TrialState::Failed);
+                seen.borrow_mut().push(trial.number());
+            })
+            .build();
+        let heartbeats = [Some(unix_time() - 60.0), Some(unix_time()), None];
+        for heartbeat in &heartbeats {
+            let mut template = FrozenTrial::new(0, TrialState::Running);
+            if let Some(heartbeat) = heartbeat {
+                template
+                    .system_attrs
+                    .insert(HEARTBEAT_ATTR.to_string(), heartbeat.to_string());
+            }
+            let mut storage = study.storage.borrow_mut();
+            storage
+                .create_new_trial(study.study_id(), Some(&template))
+                .unwrap();
+        }
+
+        study.optimize(Quadratic, 1);
+        assert_eq!(*failed.borrow(), vec![0]);
+        let trials = study.get_trials(None).unwrap();
+        let states: Vec<TrialState> = trials.iter().map(|t| t.state()).collect();
+        let expected = [
+            TrialState::Failed,
+            TrialState::Running,
+            TrialState::Running,
+            TrialState::Completed,
+        ];
+        assert_eq!(states, expected);
+        assert!(trials[3].system_attrs().contains_key(HEARTBEAT_ATTR));
+
+        let default_grace_period = create_study()
+            .heartbeat_interval(Duration::from_secs(60))
+            .build();
+        assert_eq!(default_grace_period.grace_period, Duration::from_secs(600));
+
+        let without_heartbeats = create_study().build();
+        without_heartbeats.optimize(Quadratic, 1);
+        let trial = &without_heartbeats.get_trials(None).unwrap()[0];
+        assert!(!trial.system_attrs().contains_key(HEARTBEAT_ATTR));
+    }
+
+    #[test]
+    fn copied_study_keeps_trials_and_attributes() {
+        struct Square;
+
+        impl MultiObjective for Square {
+            fn objectives(&self, trial: Trial<'_>) -> Result<Vec<f64>, TrialError> {
+                let x = trial.suggest_uniform("x", 0.0, 1.0)?;
+                Ok(vec![x, x * x])
+            }
+        }
+
+        let study = create_study()
+            .study_name("original")
+            .directions(&[StudyDirection::Minimize, StudyDirection::Maximize])
+            .build();
+        study.optimize(Square, 3);
+        study.set_user_attr("owner", "me").unwrap();
+
+        let mut copies = InMemoryStorage::new();
+        copies.create_new_study("unrelated").unwrap();
+        let from = study.storage();
+        let copy_id = copy_study("original", &*from.borrow(), &mut copies, Some("copy")).unwrap();
+        assert!(copy_study("original", &*from.borrow(), &mut copies, Some("copy")).is_err());
+
+        let copies: Rc<RefCell<dyn Storage>> = Rc::new(RefCell::new(copies));
+        let copy = load_study("copy", copies).unwrap();
+        assert_eq!(copy.study_id(), copy_id);
+        assert_eq!(copy.directions(), study.directions());
+        assert_eq!(copy.user_attrs().unwrap()["owner"], "me");
+        let (trials, copied) = (
+            study.get_trials(None).unwrap(),
+            copy.get_trials(None).unwrap(),
+        );
+        assert_eq!(copied.len(), 3);
+        for (trial, copied) in trials.iter().zip(copied.iter()) {
+            assert_eq!(copied.number(), trial.number());
+            assert_eq!(copied.values(), trial.values());
+            assert_eq!(copied.params(), trial.params());
+        }
+    }
+
+    #[test]
+    fn create_study_uses_given_settings() {
+        let study = create_study()
+            .study_name("quadratic")
+            .sampler(RandomSampler::new(1))
+            .build();
+        assert_eq!(study.study_name(), "quadratic");
+
+        let unnamed = create_study().build();
+        assert!(unnamed.study_name().starts_with("no-name-"));
+    }
+
+    #[test]
+    fn same_seed_gives_same_samples() {
+        let first = create_study().seed(7).build();
+        let second = create_study().seed(7).build();
+        first.optimize(Quadratic, 3);
+        second.optimize(Quadratic, 3);
+
+        assert_eq!(
+            first.best_trial().unwrap().unwrap().params(),
+            second.best_trial().unwrap().unwrap().params()
+        );
+    }
 }
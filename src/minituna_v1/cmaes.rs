@@ -0,0 +1,587 @@
+//! CMA-ES sampler for continuous search spaces.
+//!
+//! The intersection search space of the completed trials is normalized to the
+//! unit hypercube and sampled jointly by a covariance matrix adaptation
+//! evolution strategy. Categorical parameters, and parameters outside the
+//! intersection search space, are left to an independent sampler.
+//!
+//! Every trial records the optimizer state it was sampled from and its
+//! generation as system attributes, so a study can be resumed from storage.
+
+use super::math;
+use super::transform::{transform, transformed_bounds, untransform};
+use super::{
+    intersection_search_space, Distribution, FrozenTrial, RandomSampler, Sampler, Study,
+    StudyDirection, TrialRng, TrialState,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+
+const OPTIMIZER_ATTR: &str = "cma:optimizer";
+const GENERATION_ATTR: &str = "cma:generation";
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RestartStrategy {
+    Never,
+    /// Restarts with a population `inc_popsize` times larger whenever the
+    /// optimizer converges.
+    Ipop,
+}
+
+pub struct CmaEsSampler {
+    sigma0: f64,
+    popsize: Option<usize>,
+    n_startup_trials: usize,
+    restart_strategy: RestartStrategy,
+    inc_popsize: usize,
+    independent_sampler: Box<dyn Sampler>,
+    rng: TrialRng,
+}
+
+impl CmaEsSampler {
+    pub fn new(seed: u64) -> Self {
+        CmaEsSampler {
+            sigma0: 1.0 / 6.0,
+            popsize: None,
+            n_startup_trials: 0,
+            restart_strategy: RestartStrategy::Never,
+            inc_popsize: 2,
+            independent_sampler: Box::new(RandomSampler::new(seed.wrapping_add(1))),
+            rng: TrialRng::new(seed),
+        }
+    }
+
+    /// Initial step size, relative to the width of every parameter's range.
+    pub fn sigma0(mut self, sigma0: f64) -> Self {
+        self.sigma0 = sigma0;
+        self
+    }
+
+    /// Population size of the first run. Defaults to `4 + 3 ln(n)` for `n`
+    /// parameters.
+    pub fn popsize(mut self, popsize: usize) -> Self {
+        self.popsize = Some(popsize.max(2));
+        self
+    }
+
+    /// Number of completed trials sampled by the independent sampler before
+    /// CMA-ES kicks in.
+    pub fn n_startup_trials(mut self, n_startup_trials: usize) -> Self {
+        self.n_startup_trials = n_startup_trials;
+        self
+    }
+
+    pub fn restart_strategy(mut self, restart_strategy: RestartStrategy) -> Self {
+        self.restart_strategy = restart_strategy;
+        self
+    }
+
+    pub fn inc_popsize(mut self, inc_popsize: usize) -> Self {
+        self.inc_popsize = inc_popsize.max(1);
+        self
+    }
+
+    pub fn independent_sampler<S: Sampler + 'static>(mut self, sampler: S) -> Self {
+        self.independent_sampler = Box::new(sampler);
+        self
+    }
+
+    fn init_optimizer(&self, dim: usize, popsize: Option<usize>, n_restarts: usize) -> CmaEs {
+        let popsize = popsize
+            .or(self.popsize)
+            .unwrap_or(4 + (3.0 * (dim as f64).ln()).floor() as usize);
+        CmaEs::new(dim, popsize, self.sigma0, n_restarts)
+    }
+}
+
+impl Sampler for CmaEsSampler {
+    fn infer_relative_search_space(
+        &mut self,
+        study: &Study,
+        _trial: &FrozenTrial,
+    ) -> HashMap<String, Distribution> {
+        let trials = study
+            .get_trials(Some(&[TrialState::Completed]))
+            .unwrap_or_default();
+        let mut search_space = intersection_search_space(&trials);
+        search_space.retain(|_, d| !d.single() && !matches!(d, Distribution::Categorical { .. }));
+        search_space
+    }
+
+    fn sample_relative(
+        &mut self,
+        study: &Study,
+        trial: &FrozenTrial,
+        search_space: &HashMap<String, Distribution>,
+    ) -> HashMap<String, f64> {
+        // CMA-ES adapts correlations between parameters and is pointless below
+        // two dimensions.
+        if search_space.len() < 2 {
+            return HashMap::new();
+        }
+        let completed_trials = match study.get_trials(Some(&[TrialState::Completed])) {
+            Ok(trials) if trials.len() >= self.n_startup_trials => trials,
+            _ => return HashMap::new(),
+        };
+        let mut search_space: Vec<(&String, &Distribution)> = search_space.iter().collect();
+        search_space.sort_by(|a, b| a.0.cmp(b.0));
+        let dim = search_space.len();
+
+        let mut optimizer = completed_trials
+            .iter()
+            .rev()
+            .filter_map(|t| t.system_attrs().get(OPTIMIZER_ATTR))
+            .find_map(|attr| CmaEs::from_attr(attr))
+            .filter(|optimizer| optimizer.dim() == dim)
+            .unwrap_or_else(|| self.init_optimizer(dim, None, 0));
+
+        let generation = optimizer.generation_key();
+        let sign = match study.direction() {
+            StudyDirection::Minimize => 1.0,
+            StudyDirection::Maximize => -1.0,
+        };
+        let solutions: Vec<(Vec<f64>, f64)> = completed_trials
+            .iter()
+            .filter(|t| t.system_attrs().get(GENERATION_ATTR) == Some(&generation))
+            .filter_map(|t| {
+                let x = search_space
+                    .iter()
+                    .map(|(name, d)| {
+                        let value = t.internal_params().get(*name)?;
+                        let (low, high, _) = transformed_bounds(d);
+                        Some((transform(d, *value) - low) / (high - low))
+                    })
+                    .collect::<Option<Vec<f64>>>()?;
+                // NaN and infinite values are the worst, as in `dominates`.
+                let value = sign * t.value()?;
+                Some((
+                    x,
+                    if value.is_finite() {
+                        value
+                    } else {
+                        f64::INFINITY
+                    },
+                ))
+            })
+            .take(optimizer.popsize)
+            .collect();
+        if solutions.len() >= optimizer.popsize {
+            optimizer.tell(solutions);
+            if self.restart_strategy == RestartStrategy::Ipop && optimizer.should_stop() {
+                let popsize = optimizer.popsize * self.inc_popsize;
+                optimizer = self.init_optimizer(dim, Some(popsize), optimizer.n_restarts + 1);
+            }
+        }
+
+        self.rng.reseed_for(trial);
+        let seed = self.rng.gen::<u64>();
+        let x = optimizer.ask(&mut StdRng::seed_from_u64(seed));
+        let stored = study
+            .set_trial_system_attr(trial.trial_id(), OPTIMIZER_ATTR, &optimizer.to_attr())
+            .and_then(|_| {
+                study.set_trial_system_attr(
+                    trial.trial_id(),
+                    GENERATION_ATTR,
+                    &optimizer.generation_key(),
+                )
+            });
+        if stored.is_err() {
+            return HashMap::new();
+        }
+
+        search_space
+            .iter()
+            .zip(x)
+            .map(|((name, d), u)| {
+                let (low, high, _) = transformed_bounds(d);
+                ((*name).clone(), untransform(d, low + u * (high - low)))
+            })
+            .collect()
+    }
+
+    fn sample_independent(
+        &mut self,
+        study: &Study,
+        trial: &FrozenTrial,
+        name: &str,
+        distribution: &Distribution,
+    ) -> f64 {
+        self.independent_sampler
+            .sample_independent(study, trial, name, distribution)
+    }
+}
+
+/// State of a CMA-ES run minimizing a function on `[0, 1]^dim`.
+#[derive(Debug)]
+struct CmaEs {
+    n_restarts: usize,
+    generation: usize,
+    popsize: usize,
+    sigma0: f64,
+    sigma: f64,
+    mean: Vec<f64>,
+    c: Vec<Vec<f64>>,
+    pc: Vec<f64>,
+    ps: Vec<f64>,
+    /// Best value of each of the latest generations.
+    best_history: Vec<f64>,
+    /// Eigendecomposition of `c`: `c = b * diag(d^2) * b^T`.
+    b: Vec<Vec<f64>>,
+    d: Vec<f64>,
+}
+
+impl CmaEs {
+    fn new(dim: usize, popsize: usize, sigma0: f64, n_restarts: usize) -> Self {
+        let identity: Vec<Vec<f64>> = (0..dim)
+            .map(|i| (0..dim).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+            .collect();
+        CmaEs {
+            n_restarts,
+            generation: 0,
+            popsize,
+            sigma0,
+            sigma: sigma0,
+            mean: vec![0.5; dim],
+            c: identity.clone(),
+            pc: vec![0.0; dim],
+            ps: vec![0.0; dim],
+            best_history: Vec::new(),
+            b: identity,
+            d: vec![1.0; dim],
+        }
+    }
+
+    fn dim(&self) -> usize {
+        self.mean.len()
+    }
+
+    fn generation_key(&self) -> String {
+        format!("{}:{}", self.n_restarts, self.generation)
+    }
+
+    /// Recombination weights of the best `mu` solutions and their variance
+    /// effective selection mass.
+    fn weights(&self) -> (Vec<f64>, f64) {
+        let mu = self.popsize / 2;
+        let raw: Vec<f64> = (1..=mu)
+            .map(|i| ((self.popsize as f64 + 1.0) / 2.0).ln() - (i as f64).ln())
+            .collect();
+        let total: f64 = raw.iter().sum();
+        let weights: Vec<f64> = raw.iter().map(|w| w / total).collect();
+        let mu_eff = 1.0 / weights.iter().map(|w| w * w).sum::<f64>();
+        (weights, mu_eff)
+    }
+
+    fn ask<R: Rng>(&self, rng: &mut R) -> Vec<f64> {
+        let dim = self.dim();
+        let mut x = Vec::new();
+        for _ in 0..100 {
+            let z: Vec<f64> = (0..dim)
+                .map(|i| self.d[i] * math::sample_standard_normal(rng))
+                .collect();
+            x = (0..dim)
+                .map(|i| {
+                    let y: f64 = (0..dim).map(|j| self.b[i][j] * z[j]).sum();
+                    self.mean[i] + self.sigma * y
+                })
+                .collect();
+            if x.iter().all(|v| (0.0..=1.0).contains(v)) {
+                return x;
+            }
+        }
+        x.iter().map(|v| v.clamp(0.0, 1.0)).collect()
+    }
+
+    fn tell(&mut self, mut solutions: Vec<(Vec<f64>, f64)>) {
+        let n = self.dim();
+        let nf = n as f64;
+        let (weights, mu_eff) = self.weights();
+        let cc = (4.0 + mu_eff / nf) / (nf + 4.0 + 2.0 * mu_eff / nf);
+        let cs = (mu_eff + 2.0) / (nf + mu_eff + 5.0);
+        let c1 = 2.0 / ((nf + 1.3).powi(2) + mu_eff);
+        let cmu =
+            (1.0 - c1).min(2.0 * (mu_eff - 2.0 + 1.0 / mu_eff) / ((nf + 2.0).powi(2) + mu_eff));
+        let damps = 1.0 + 2.0 * (((mu_eff - 1.0) / (nf + 1.0)).sqrt() - 1.0).max(0.0) + cs;
+        let chi_n = nf.sqrt() * (1.0 - 1.0 / (4.0 * nf) + 1.0 / (21.0 * nf * nf));
+
+        solutions.sort_by(|a, b| a.1.total_cmp(&b.1));
+        self.best_history.push(solutions[0].1);
+        let history_len = 10 + (30.0 * nf / self.popsize as f64).ceil() as usize;
+        if self.best_history.len() > history_len {
+            self.best_history.remove(0);
+        }
+
+        let ys: Vec<Vec<f64>> = solutions
+            .iter()
+            .take(weights.len())
+            .map(|(x, _)| (0..n).map(|i| (x[i] - self.mean[i]) / self.sigma).collect())
+            .collect();
+        let y_w: Vec<f64> = (0..n)
+            .map(|i| weights.iter().zip(&ys).map(|(w, y)| w * y[i]).sum())
+            .collect();
+        for (m, y) in self.mean.iter_mut().zip(&y_w) {
+            *m += self.sigma * y;
+        }
+
+        // C^(-1/2) * y_w = B * D^(-1) * B^T * y_w
+        let bt_y: Vec<f64> = (0..n)
+            .map(|j| (0..n).map(|i| self.b[i][j] * y_w[i]).sum::<f64>() / self.d[j])
+            .collect();
+        let c_inv_sqrt_y: Vec<f64> = (0..n)
+            .map(|i| (0..n).map(|j| self.b[i][j] * bt_y[j]).sum())
+            .collect();
+        let ps_factor = (cs * (2.0 - cs) * mu_eff).sqrt();
+        for (ps, y) in self.ps.iter_mut().zip(&c_inv_sqrt_y) {
+            *ps = (1.0 - cs) * *ps + ps_factor * y;
+        }
+        let norm_ps = self.ps.iter().map(|v| v * v).sum::<f64>().sqrt();
+        let h_sigma_threshold = (1.4 + 2.0 / (nf + 1.0)) * chi_n;
+        let h_sigma = norm_ps / (1.0 - (1.0 - cs).powi(2 * (self.generation as i32 + 1))).sqrt()
+            < h_sigma_threshold;
+        let h_sigma = if h_sigma { 1.0 } else { 0.0 };
+
+        let pc_factor = h_sigma * (cc * (2.0 - cc) * mu_eff).sqrt();
+        for (pc, y) in self.pc.iter_mut().zip(&y_w) {
+            *pc = (1.0 - cc) * *pc + pc_factor * y;
+        }
+
+        let delta = (1.0 - h_sigma) * cc * (2.0 - cc);
+        let decay = 1.0 + c1 * delta - c1 - cmu * weights.iter().sum::<f64>();
+        for i in 0..n {
+            for j in 0..n {
+                let rank_mu: f64 = weights.iter().zip(&ys).map(|(w, y)| w * y[i] * y[j]).sum();
+                self.c[i][j] = decay * self.c[i][j] + c1 * self.pc[i] * self.pc[j] + cmu * rank_mu;
+            }
+        }
+
+        self.sigma *= ((cs / damps) * (norm_ps / chi_n - 1.0)).min(1.0).exp();
+        self.generation += 1;
+        self.update_eigen();
+    }
+
+    fn update_eigen(&mut self) {
+        let (values, vectors) = math::symmetric_eigen(&self.c);
+        self.d = values.iter().map(|v| v.max(1e-14).sqrt()).collect();
+        self.b = vectors;
+    }
+
+    /// Whether the run converged or stalled, so that a restart is worthwhile.
+    fn should_stop(&self) -> bool {
+        let n = self.dim();
+        let history_len = 10 + (30.0 * n as f64 / self.popsize as f64).ceil() as usize;
+        if self.best_history.len() >= history_len {
+            let max = self.best_history.iter().cloned().fold(f64::MIN, f64::max);
+            let min = self.best_history.iter().cloned().fold(f64::MAX, f64::min);
+            if max - min < 1e-12 {
+                return true;
+            }
+        }
+
+        let tolx = 1e-12 * self.sigma0;
+        if (0..n).all(|i| self.sigma * self.pc[i].abs().max(self.c[i][i].sqrt()) < tolx) {
+            return true;
+        }
+        if (0..n).any(|i| self.mean[i] == self.mean[i] + 0.2 * self.sigma * self.c[i][i].sqrt()) {
+            return true;
+        }
+        let d_max = self.d.iter().cloned().fold(f64::MIN, f64::max);
+        let d_min = self.d.iter().cloned().fold(f64::MAX, f64::min);
+        (d_max / d_min).powi(2) > 1e14
+    }
+
+    fn to_attr(&self) -> String {
+        let mut tokens = vec![
+            self.n_restarts.to_string(),
+            self.generation.to_string(),
+            self.popsize.to_string(),
+            self.dim().to_string(),
+            self.sigma0.to_string(),
+            self.sigma.to_string(),
+            self.best_history.len().to_string(),
+        ];
+        let vectors = self
+            .mean
+            .iter()
+            .chain(&self.pc)
+            .chain(&self.ps)
+            .chain(self.c.iter().flatten())
+            .chain(&self.best_history);
+        tokens.extend(vectors.map(|v| v.to_string()));
+        tokens.join(" ")
+    }
+
+    fn from_attr(attr: &str) -> Option<Self> {
+        let tokens = attr
+            .split_whitespace()
+            .map(|t| t.parse::<f64>().ok())
+            .collect::<Option<Vec<f64>>>()?;
+        if tokens.len() < 7 {
+            return None;
+        }
+        let (header, mut rest) = tokens.split_at(7);
+        let dim = header[3] as usize;
+        let history_len = header[6] as usize;
+        if rest.len() != dim * (dim + 3) + history_len {
+            return None;
+        }
+        let mut vector = |len: usize| {
+            let (head, tail) = rest.split_at(len);
+            rest = tail;
+            head.to_vec()
+        };
+        let mean = vector(dim);
+        let pc = vector(dim);
+        let ps = vector(dim);
+        let c = (0..dim).map(|_| vector(dim)).collect();
+        let best_history = vector(history_len);
+        let (n_restarts, generation, popsize) =
+            (header[0] as usize, header[1] as usize, header[2] as usize);
+        let (sigma0, sigma) = (header[4], header[5]);
+
+        let mut optimizer = CmaEs {
+            n_restarts,
+            generation,
+            popsize,
+            sigma0,
+            sigma,
+            mean,
+            c,
+            pc,
+            ps,
+            best_history,
+            b: Vec::new(),
+            d: Vec::new(),
+        };
+        optimizer.update_eigen();
+        Some(optimizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::{create_study, Objective, Trial, TrialError};
+
+    struct Ellipsoid;
+
+    impl Objective for Ellipsoid {
+        fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+            let x = trial.suggest_uniform("x", -2.0, 2.0)?;
+            let y = trial.suggest_uniform("y", -1.0, 3.0)?;
+            let n = trial.suggest_int("n", 0, 10, 1, false)?;
+            Ok((x - 1.0).powi(2) + 10.0 * (y - x - 1.0).powi(2) + (n as f64 - 3.0).powi(2))
+        }
+    }
+
+    fn generation(trial: &FrozenTrial) -> (usize, usize) {
+        let key = &trial.system_attrs()[GENERATION_ATTR];
+        let mut parts = key.split(':').map(|v| v.parse().unwrap());
+        (parts.next().unwrap(), parts.next().unwrap())
+    }
+
+    #[test]
+    fn cmaes_optimizes_a_continuous_function() {
+        let study = create_study().sampler(CmaEsSampler::new(1)).build();
+        study.optimize(Ellipsoid, 200);
+        let random = create_study().seed(1).build();
+        random.optimize(Ellipsoid, 200);
+
+        let value = study.best_trial().unwrap().unwrap().value().unwrap();
+        let random_value = random.best_trial().unwrap().unwrap().value().unwrap();
+        assert!(value < 0.01, "{}", value);
+        assert!(value < random_value);
+    }
+
+    #[test]
+    fn optimizer_state_round_trips() {
+        let mut optimizer = CmaEs::new(3, 6, 0.2, 1);
+        let mut rng = StdRng::seed_from_u64(0);
+        let solutions = (0..6)
+            .map(|_| {
+                let x = optimizer.ask(&mut rng);
+                let value = x.iter().map(|v| v * v).sum();
+                (x, value)
+            })
+            .collect();
+        optimizer.tell(solutions);
+
+        let restored = CmaEs::from_attr(&optimizer.to_attr()).unwrap();
+        assert_eq!(restored.to_attr(), optimizer.to_attr());
+        assert_eq!(restored.generation_key(), "1:1");
+        assert!(CmaEs::from_attr("1 2 3").is_none());
+    }
+
+    #[test]
+    fn resumed_study_continues_from_stored_state() {
+        let study = create_study().sampler(CmaEsSampler::new(1)).build();
+        study.optimize(Ellipsoid, 30);
+        let last_generation = study
+            .get_trials(None)
+            .unwrap()
+            .iter()
+            .filter(|t| t.system_attrs().contains_key(GENERATION_ATTR))
+            .map(generation)
+            .max()
+            .unwrap();
+        assert!(last_generation.1 > 0);
+
+        let resumed = create_study()
+            .study_name(study.study_name())
+            .shared_storage(study.storage())
+            .sampler(CmaEsSampler::new(2))
+            .build();
+        resumed.optimize(Ellipsoid, 1);
+        let trials = resumed.get_trials(None).unwrap();
+        assert!(generation(trials.last().unwrap()) >= last_generation);
+    }
+
+    #[test]
+    fn nan_values_rank_last_in_a_generation() {
+        struct NanOnTheLeft;
+
+        impl Objective for NanOnTheLeft {
+            fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+                let x = trial.suggest_uniform("x", -2.0, 2.0)?;
+                let y = trial.suggest_uniform("y", -2.0, 2.0)?;
+                Ok(if x < 0.0 {
+                    f64::NAN
+                } else {
+                    (x - 1.0).powi(2) + y * y
+                })
+            }
+        }
+
+        let study = create_study()
+            .sampler(CmaEsSampler::new(4).popsize(40))
+            .build();
+        study.optimize(NanOnTheLeft, 200);
+        let trials = study.get_trials(None).unwrap();
+        assert!(trials.iter().all(|t| t.state() == TrialState::Completed));
+        assert!(trials
+            .iter()
+            .filter(|t| t.system_attrs().contains_key(GENERATION_ATTR))
+            .any(|t| generation(t).1 >= 3));
+        assert!(study.best_trial().unwrap().unwrap().value().unwrap() < 0.1);
+    }
+
+    #[test]
+    fn ipop_restarts_with_a_larger_population() {
+        let sampler = CmaEsSampler::new(3)
+            .popsize(4)
+            .sigma0(1e-17)
+            .restart_strategy(RestartStrategy::Ipop);
+        let study = create_study().sampler(sampler).build();
+        study.optimize(Ellipsoid, 20);
+
+        let restarts = study
+            .get_trials(None)
+            .unwrap()
+            .iter()
+            .filter(|t| t.system_attrs().contains_key(GENERATION_ATTR))
+            .map(|t| generation(t).0)
+            .max()
+            .unwrap();
+        assert!(restarts > 0);
+    }
+}
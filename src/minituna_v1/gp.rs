@@ -0,0 +1,677 @@
+//! Gaussian-process based Bayesian optimization sampler.
+//!
+//! The objective values of the completed trials are modeled by a Gaussian
+//! process with a Matérn 5/2 kernel whose length scales, scale and noise are
+//! fit by maximizing the marginal likelihood. The next parameters maximize the
+//! expected improvement over the best value so far, or its logarithm, which
+//! stays informative where the improvement is vanishingly small.
+
+use super::math;
+use super::transform::{transform, transformed_bounds, untransform};
+use super::{
+    intersection_search_space, Distribution, FrozenTrial, RandomSampler, Sampler, Study,
+    StudyDirection, TrialRng, TrialState,
+};
+use rand::Rng;
+use std::collections::HashMap;
+
+const SQRT_5: f64 = 2.236_067_977_499_79;
+const KERNEL_ATTR: &str = "gp:kernel_params";
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Acquisition {
+    Ei,
+    LogEi,
+}
+
+/// Samples the intersection search space of the completed trials with
+/// Gaussian-process based Bayesian optimization. Parameters outside of it,
+/// and all parameters of the first `n_startup_trials` trials, are sampled by
+/// an independent sampler.
+///
+/// Numerical parameters are modeled on the unit interval, in log scale where
+/// the distribution is, and categorical ones by whether two choices are equal.
+/// The acquisition function is maximized from the best of
+/// `n_preliminary_samples` random points with `n_local_search` runs of a
+/// bounded L-BFGS method, followed by a sweep over the categorical choices.
+///
+/// The fitted kernel parameters are recorded as a system attribute of the
+/// trial, and the fit for a trial starts from those of the latest earlier
+/// trial, so a resumed study fits like an uninterrupted one.
+pub struct GpSampler {
+    n_startup_trials: usize,
+    n_preliminary_samples: usize,
+    n_local_search: usize,
+    acquisition: Acquisition,
+    independent_sampler: Box<dyn Sampler>,
+    rng: TrialRng,
+}
+
+impl GpSampler {
+    pub fn new(seed: u64) -> Self {
+        GpSampler {
+            n_startup_trials: 10,
+            n_preliminary_samples: 2048,
+            n_local_search: 10,
+            acquisition: Acquisition::LogEi,
+            independent_sampler: Box::new(RandomSampler::new(seed.wrapping_add(1))),
+            rng: TrialRng::new(seed),
+        }
+    }
+
+    pub fn n_startup_trials(mut self, n_startup_trials: usize) -> Self {
+        self.n_startup_trials = n_startup_trials;
+        self
+    }
+
+    pub fn n_preliminary_samples(mut self, n_preliminary_samples: usize) -> Self {
+        self.n_preliminary_samples = n_preliminary_samples.max(1);
+        self
+    }
+
+    pub fn n_local_search(mut self, n_local_search: usize) -> Self {
+        self.n_local_search = n_local_search;
+        self
+    }
+
+    pub fn acquisition(mut self, acquisition: Acquisition) -> Self {
+        self.acquisition = acquisition;
+        self
+    }
+
+    pub fn independent_sampler<S: Sampler + 'static>(mut self, sampler: S) -> Self {
+        self.independent_sampler = Box::new(sampler);
+        self
+    }
+
+    fn random_point(&mut self, is_categorical: &[Option<usize>]) -> Vec<f64> {
+        let rng = &mut self.rng;
+        is_categorical
+            .iter()
+            .map(|n_choices| match n_choices {
+                Some(n) => rng.gen_range(0, *n) as f64,
+                None => rng.gen::<f64>(),
+            })
+            .collect()
+    }
+
+    /// Maximizes the acquisition function, first over random points and then
+    /// by local search from the most promising of them.
+    fn optimize_acquisition(
+        &mut self,
+        gp: &GaussianProcess,
+        observed: &[Vec<f64>],
+        n_choices: &[Option<usize>],
+    ) -> Vec<f64> {
+        let acquisition = self.acquisition;
+        let score = |x: &[f64]| gp.acquisition(x, acquisition);
+
+        let mut candidates: Vec<(Vec<f64>, f64)> = (0..self.n_preliminary_samples)
+            .map(|_| self.random_point(n_choices))
+            .chain(observed.iter().cloned())
+            .map(|x| {
+                let value = score(&x);
+                (x, value)
+            })
+            .collect();
+        // Candidates whose score is NaN rank last.
+        let score_or_worst = |value: f64| {
+            if value.is_nan() {
+                f64::NEG_INFINITY
+            } else {
+                value
+            }
+        };
+        candidates.sort_by(|a, b| score_or_worst(b.1).total_cmp(&score_or_worst(a.1)));
+        candidates.truncate(self.n_local_search.max(1));
+
+        let mut best = candidates[0].clone();
+        for (start, _) in candidates.into_iter().take(self.n_local_search) {
+            let (x, value) = local_search(&score, start, n_choices);
+            if value > best.1 {
+                best = (x, value);
+            }
+        }
+        best.0
+    }
+}
+
+impl Sampler for GpSampler {
+    fn infer_relative_search_space(
+        &mut self,
+        study: &Study,
+        _trial: &FrozenTrial,
+    ) -> HashMap<String, Distribution> {
+        let trials = study
+            .get_trials(Some(&[TrialState::Completed]))
+            .unwrap_or_default();
+        let mut search_space = intersection_search_space(&trials);
+        search_space.retain(|_, d| !d.single());
+        search_space
+    }
+
+    fn sample_relative(
+        &mut self,
+        study: &Study,
+        trial: &FrozenTrial,
+        search_space: &HashMap<String, Distribution>,
+    ) -> HashMap<String, f64> {
+        if search_space.is_empty() {
+            return HashMap::new();
+        }
+        self.rng.reseed_for(trial);
+        let mut search_space: Vec<(&String, &Distribution)> = search_space.iter().collect();
+        search_space.sort_by(|a, b| a.0.cmp(b.0));
+
+        let sign = match study.direction() {
+            StudyDirection::Minimize => -1.0,
+            StudyDirection::Maximize => 1.0,
+        };
+        let (xs, ys): (Vec<Vec<f64>>, Vec<f64>) = study
+            .get_trials(Some(&[TrialState::Completed]))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|t| {
+                let value = t.value().filter(|v| v.is_finite())?;
+                let x = search_space
+                    .iter()
+                    .map(|(name, d)| Some(to_unit(d, *t.internal_params().get(*name)?)))
+                    .collect::<Option<Vec<f64>>>()?;
+                Some((x, sign * value))
+            })
+            .unzip();
+        if xs.len() < self.n_startup_trials.max(1) {
+            return HashMap::new();
+        }
+
+        let mean = ys.iter().sum::<f64>() / ys.len() as f64;
+        let std = (ys.iter().map(|y| (y - mean).powi(2)).sum::<f64>() / ys.len() as f64)
+            .sqrt()
+            .max(1e-10);
+        let ys: Vec<f64> = ys.iter().map(|y| (y - mean) / std).collect();
+        let n_choices: Vec<Option<usize>> = search_space
+            .iter()
+            .map(|(_, d)| match d {
+                Distribution::Categorical { choices } => Some(choices.len()),
+                _ => None,
+            })
+            .collect();
+        let is_categorical: Vec<bool> = n_choices.iter().map(Option::is_some).collect();
+
+        let initial = previous_kernel_params(study, trial)
+            .filter(|params| params.len() == search_space.len() + 2);
+        let gp = match GaussianProcess::fit(xs.clone(), ys, is_categorical, initial) {
+            Some(gp) => gp,
+            None => return HashMap::new(),
+        };
+        let params: Vec<String> = gp.kernel_params().iter().map(f64::to_string).collect();
+        let _ = study.set_trial_system_attr(trial.trial_id(), KERNEL_ATTR, &params.join(" "));
+
+        let x = self.optimize_acquisition(&gp, &xs, &n_choices);
+        search_space
+            .into_iter()
+            .zip(x)
+            .map(|((name, d), u)| (name.clone(), from_unit(d, u)))
+            .collect()
+    }
+
+    fn sample_independent(
+        &mut self,
+        study: &Study,
+        trial: &FrozenTrial,
+        name: &str,
+        distribution: &Distribution,
+    ) -> f64 {
+        self.independent_sampler
+            .sample_independent(study, trial, name, distribution)
+    }
+}
+
+/// Kernel parameters the latest trial before `trial` was sampled with, to warm
+/// start the fit.
+fn previous_kernel_params(study: &Study, trial: &FrozenTrial) -> Option<Vec<f64>> {
+    let trials = study.get_trials(None).ok()?;
+    let previous = trials
+        .iter()
+        .filter(|t| t.number() < trial.number() && t.system_attrs().contains_key(KERNEL_ATTR))
+        .max_by_key(|t| t.number())?;
+    previous.system_attrs()[KERNEL_ATTR]
+        .split_whitespace()
+        .map(|param| param.parse().ok())
+        .collect()
+}
+
+/// Bounded L-BFGS on the numerical coordinates followed by a greedy sweep over
+/// the categorical ones, maximizing `score`.
+fn local_search<F: Fn(&[f64]) -> f64>(
+    score: &F,
+    start: Vec<f64>,
+    n_choices: &[Option<usize>],
+) -> (Vec<f64>, f64) {
+    // Categorical coordinates stay at their starting choice during L-BFGS.
+    let bounds: Vec<(f64, f64)> = start
+        .iter()
+        .zip(n_choices)
+        .map(|(&s, n)| if n.is_some() { (s, s) } else { (0.0, 1.0) })
+        .collect();
+    let objective = |x: &[f64]| {
+        let gradient = (0..x.len())
+            .map(|i| {
+                if n_choices[i].is_some() {
+                    return 0.0;
+                }
+                let h = 1e-6;
+                let mut x_low = x.to_vec();
+                let mut x_high = x.to_vec();
+                x_low[i] = (x[i] - h).max(0.0);
+                x_high[i] = (x[i] + h).min(1.0);
+                (score(&x_low) - score(&x_high)) / (x_high[i] - x_low[i])
+            })
+            .collect();
+        (-score(x), gradient)
+    };
+    let (mut x, value) = math::minimize_lbfgsb(objective, &start, &bounds, 100);
+    let mut best_value = -value;
+
+    for (i, n) in n_choices.iter().enumerate() {
+        if let Some(n) = n {
+            for choice in 0..*n {
+                let mut candidate = x.clone();
+                candidate[i] = choice as f64;
+                let value = score(&candidate);
+                if value > best_value {
+                    best_value = value;
+                    x = candidate;
+                }
+            }
+        }
+    }
+    (x, best_value)
+}
+
+/// A Gaussian process fit to standardized observations on the unit
+/// hypercube.
+struct GaussianProcess {
+    xs: Vec<Vec<f64>>,
+    is_categorical: Vec<bool>,
+    log_lengthscales: Vec<f64>,
+    log_scale: f64,
+    log_noise: f64,
+    chol: Vec<Vec<f64>>,
+    alpha: Vec<f64>,
+    best_y: f64,
+}
+
+impl GaussianProcess {
+    fn fit(
+        xs: Vec<Vec<f64>>,
+        ys: Vec<f64>,
+        is_categorical: Vec<bool>,
+        initial: Option<Vec<f64>>,
+    ) -> Option<Self> {
+        let dim = is_categorical.len();
+        let initial = initial.unwrap_or_else(|| {
+            let mut params = vec![0.0; dim];
+            params.push(0.0);
+            params.push((1e-2f64).ln());
+            params
+        });
+        let mut bounds = vec![((1e-3f64).ln(), (1e3f64).ln()); dim];
+        bounds.push(((1e-3f64).ln(), (1e3f64).ln()));
+        bounds.push(((1e-6f64).ln(), 0.0));
+
+        let objective = |params: &[f64]| match neg_log_posterior(&xs, &ys, &is_categorical, params)
+        {
+            Some(result) => result,
+            None => (f64::INFINITY, vec![0.0; params.len()]),
+        };
+        let (params, _) = math::minimize_lbfgsb(objective, &initial, &bounds, 200);
+
+        let log_lengthscales = params[..dim].to_vec();
+        let (log_scale, log_noise) = (params[dim], params[dim + 1]);
+        let cov = covariance(
+            &xs,
+            &is_categorical,
+            &log_lengthscales,
+            log_scale,
+            log_noise,
+        );
+        let chol = math::cholesky(&cov)?;
+        let alpha = math::solve_upper_transposed(&chol, &math::solve_lower(&chol, &ys));
+        let best_y = ys.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        Some(GaussianProcess {
+            xs,
+            is_categorical,
+            log_lengthscales,
+            log_scale,
+            log_noise,
+            chol,
+            alpha,
+            best_y,
+        })
+    }
+
+    fn kernel_params(&self) -> Vec<f64> {
+        let mut params = self.log_lengthscales.clone();
+        params.push(self.log_scale);
+        params.push(self.log_noise);
+        params
+    }
+
+    /// Posterior mean and standard deviation of the latent function at `x`.
+    fn posterior(&self, x: &[f64]) -> (f64, f64) {
+        let scale = self.log_scale.exp();
+        let k: Vec<f64> = self
+            .xs
+            .iter()
+            .map(|xi| {
+                let u = scaled_sq_dists(x, xi, &self.is_categorical, &self.log_lengthscales);
+                scale * matern52(u.iter().sum::<f64>().sqrt())
+            })
+            .collect();
+        let mean: f64 = k.iter().zip(&self.alpha).map(|(a, b)| a * b).sum();
+        let v = math::solve_lower(&self.chol, &k);
+        let var = scale - v.iter().map(|v| v * v).sum::<f64>();
+        (mean, var.max(1e-12).sqrt())
+    }
+
+    fn acquisition(&self, x: &[f64], acquisition: Acquisition) -> f64 {
+        let (mean, std) = self.posterior(x);
+        let z = (mean - self.best_y) / std;
+        match acquisition {
+            Acquisition::Ei => std * (math::norm_pdf(z) + z * math::norm_cdf(z)),
+            Acquisition::LogEi => std.ln() + log_h(z),
+        }
+    }
+}
+
+/// `ln(pdf(z) + z * cdf(z))` of the standard normal distribution, computed
+/// without underflow for very negative `z`.
+fn log_h(z: f64) -> f64 {
+    if z > -1.0 {
+        (math::norm_pdf(z) + z * math::norm_cdf(z)).ln()
+    } else if z > -10.0 {
+        let ratio =
+            -z * (std::f64::consts::PI / 2.0).sqrt() * math::erfcx(-z / std::f64::consts::SQRT_2);
+        math::log_norm_pdf(z) + (1.0 - ratio).ln()
+    } else {
+        let z2 = z * z;
+        math::log_norm_pdf(z) - z2.ln() + (1.0 - 3.0 / z2 + 15.0 / (z2 * z2)).ln()
+    }
+}
+
+fn matern52(r: f64) -> f64 {
+    (1.0 + SQRT_5 * r + 5.0 / 3.0 * r * r) * (-SQRT_5 * r).exp()
+}
+
+/// Squared distance of every dimension divided by its squared length scale.
+/// Categorical dimensions contribute whether the choices differ.
+fn scaled_sq_dists(
+    a: &[f64],
+    b: &[f64],
+    is_categorical: &[bool],
+    log_lengthscales: &[f64],
+) -> Vec<f64> {
+    a.iter()
+        .zip(b)
+        .zip(is_categorical)
+        .zip(log_lengthscales)
+        .map(|(((a, b), &categorical), log_l)| {
+            let d2 = if categorical {
+                if a == b {
+                    0.0
+                } else {
+                    1.0
+                }
+            } else {
+                (a - b) * (a - b)
+            };
+            d2 * (-2.0 * log_l).exp()
+        })
+        .collect()
+}
+
+fn covariance(
+    xs: &[Vec<f64>],
+    is_categorical: &[bool],
+    log_lengthscales: &[f64],
+    log_scale: f64,
+    log_noise: f64,
+) -> Vec<Vec<f64>> {
+    let scale = log_scale.exp();
+    let noise = log_noise.exp();
+    xs.iter()
+        .enumerate()
+        .map(|(i, xi)| {
+            xs.iter()
+                .enumerate()
+                .map(|(j, xj)| {
+                    let u = scaled_sq_dists(xi, xj, is_categorical, log_lengthscales);
+                    let k = scale * matern52(u.iter().sum::<f64>().sqrt());
+                    if i == j {
+                        k + noise
+                    } else {
+                        k
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Negative log marginal likelihood plus weak log-normal priors on the kernel
+/// parameters, and its gradient with respect to `params`, which are the log
+/// length scales, the log kernel scale and the log noise variance.
+fn neg_log_posterior(
+    xs: &[Vec<f64>],
+    ys: &[f64],
+    is_categorical: &[bool],
+    params: &[f64],
+) -> Option<(f64, Vec<f64>)> {
+    let n = xs.len();
+    let dim = is_categorical.len();
+    let log_lengthscales = &params[..dim];
+    let (log_scale, log_noise) = (params[dim], params[dim + 1]);
+    let scale = log_scale.exp();
+    let noise = log_noise.exp();
+
+    let cov = covariance(xs, is_categorical, log_lengthscales, log_scale, log_noise);
+    let chol = math::cholesky(&cov)?;
+    let alpha = math::solve_upper_transposed(&chol, &math::solve_lower(&chol, ys));
+    let log_det: f64 = (0..n).map(|i| chol[i][i].ln()).sum();
+    let data_fit: f64 = ys.iter().zip(&alpha).map(|(y, a)| y * a).sum();
+    let log_likelihood =
+        -0.5 * data_fit - log_det - 0.5 * n as f64 * (2.0 * std::f64::consts::PI).ln();
+
+    // W = alpha * alpha^T - K^-1, so that d(log likelihood) = tr(W dK) / 2.
+    let mut w: Vec<Vec<f64>> = (0..n)
+        .map(|i| {
+            let mut e = vec![0.0; n];
+            e[i] = 1.0;
+            math::solve_upper_transposed(&chol, &math::solve_lower(&chol, &e))
+        })
+        .collect();
+    for i in 0..n {
+        for j in 0..n {
+            w[i][j] = alpha[i] * alpha[j] - w[i][j];
+        }
+    }
+
+    let mut gradient = vec![0.0; dim + 2];
+    for i in 0..n {
+        for j in 0..n {
+            let u = scaled_sq_dists(&xs[i], &xs[j], is_categorical, log_lengthscales);
+            let r = u.iter().sum::<f64>().sqrt();
+            let common = scale * 5.0 / 3.0 * (1.0 + SQRT_5 * r) * (-SQRT_5 * r).exp();
+            for d in 0..dim {
+                gradient[d] += 0.5 * w[i][j] * common * u[d];
+            }
+            gradient[dim] += 0.5 * w[i][j] * scale * matern52(r);
+        }
+        gradient[dim + 1] += 0.5 * w[i][i] * noise;
+    }
+
+    // Log-normal priors centered on length scales of half the unit interval,
+    // a unit kernel scale and a small noise variance.
+    let priors: Vec<(f64, f64)> = (0..dim)
+        .map(|_| ((0.5f64).ln(), 1.5))
+        .chain(vec![(0.0, 1.5), ((1e-3f64).ln(), 3.0)])
+        .collect();
+    let mut log_prior = 0.0;
+    for (k, (mu, sigma)) in priors.iter().enumerate() {
+        let z = (params[k] - mu) / sigma;
+        log_prior -= 0.5 * z * z;
+        gradient[k] -= z / sigma;
+    }
+
+    Some((
+        -(log_likelihood + log_prior),
+        gradient.iter().map(|g| -g).collect(),
+    ))
+}
+
+fn to_unit(distribution: &Distribution, internal_repr: f64) -> f64 {
+    match distribution {
+        Distribution::Categorical { .. } => internal_repr,
+        _ => {
+            let (low, high, _) = transformed_bounds(distribution);
+            (transform(distribution, internal_repr) - low) / (high - low)
+        }
+    }
+}
+
+fn from_unit(distribution: &Distribution, u: f64) -> f64 {
+    match distribution {
+        Distribution::Categorical { .. } => u,
+        _ => {
+            let (low, high, _) = transformed_bounds(distribution);
+            untransform(distribution, low + u * (high - low))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::{create_study, Objective, Trial, TrialError};
+
+    struct Branin;
+
+    impl Objective for Branin {
+        fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+            let x = trial.suggest_uniform("x", -5.0, 10.0)?;
+            let y = trial.suggest_uniform("y", 0.0, 15.0)?;
+            let activation = trial.suggest_categorical("activation", &["relu", "tanh"])?;
+            let pi = std::f64::consts::PI;
+            let a = y - 5.1 / (4.0 * pi * pi) * x * x + 5.0 / pi * x - 6.0;
+            let penalty = if activation == "relu" { 0.0 } else { 10.0 };
+            Ok(a * a + 10.0 * (1.0 - 1.0 / (8.0 * pi)) * x.cos() + 10.0 + penalty)
+        }
+    }
+
+    #[test]
+    fn gradient_of_the_marginal_likelihood_matches_finite_differences() {
+        let xs = vec![
+            vec![0.1, 0.0],
+            vec![0.5, 1.0],
+            vec![0.9, 0.0],
+            vec![0.3, 1.0],
+        ];
+        let ys = vec![0.5, -1.0, 1.2, -0.7];
+        let is_categorical = vec![false, true];
+        let params = vec![-0.5, 0.2, 0.1, -3.0];
+        let (_, gradient) = neg_log_posterior(&xs, &ys, &is_categorical, &params).unwrap();
+        for k in 0..params.len() {
+            let h = 1e-6;
+            let mut plus = params.clone();
+            let mut minus = params.clone();
+            plus[k] += h;
+            minus[k] -= h;
+            let f_plus = neg_log_posterior(&xs, &ys, &is_categorical, &plus)
+                .unwrap()
+                .0;
+            let f_minus = neg_log_posterior(&xs, &ys, &is_categorical, &minus)
+                .unwrap()
+                .0;
+            let numerical = (f_plus - f_minus) / (2.0 * h);
+            assert!(
+                (numerical - gradient[k]).abs() < 1e-4,
+                "{} {} {}",
+                k,
+                numerical,
+                gradient[k]
+            );
+        }
+    }
+
+    #[test]
+    fn log_h_is_continuous_and_finite() {
+        for &z in [-1.0, -10.0].iter() {
+            assert!((log_h(z - 1e-9) - log_h(z + 1e-9)).abs() < 1e-3, "{}", z);
+        }
+        assert!(log_h(-100.0).is_finite());
+        assert!(
+            (log_h(2.0) - (math::norm_pdf(2.0) + 2.0 * math::norm_cdf(2.0)).ln()).abs() < 1e-12
+        );
+    }
+
+    #[test]
+    fn gp_finds_a_good_optimum_with_few_trials() {
+        for &acquisition in [Acquisition::LogEi, Acquisition::Ei].iter() {
+            let sampler = GpSampler::new(1)
+                .n_preliminary_samples(256)
+                .acquisition(acquisition);
+            let study = create_study().sampler(sampler).build();
+            study.optimize(Branin, 30);
+            let value = study.best_trial().unwrap().unwrap().value().unwrap();
+            assert!(value < 1.0, "{:?} {}", acquisition, value);
+        }
+    }
+
+    #[test]
+    fn candidates_scored_nan_rank_last() {
+        let xs = vec![vec![0.1], vec![0.4], vec![0.7], vec![0.9]];
+        let ys = vec![0.5, -1.0, 1.2, -0.7];
+        let gp = GaussianProcess::fit(xs, ys, vec![false], None).unwrap();
+        let observed: Vec<Vec<f64>> = (0..64)
+            .map(|i| {
+                vec![if i % 3 == 0 {
+                    f64::NAN
+                } else {
+                    i as f64 / 64.0
+                }]
+            })
+            .collect();
+        let mut sampler = GpSampler::new(1).n_preliminary_samples(0);
+        let x = sampler.optimize_acquisition(&gp, &observed, &[None]);
+        assert!(x[0].is_finite());
+    }
+
+    #[test]
+    fn resumed_study_samples_like_an_uninterrupted_run() {
+        let sampler = || {
+            GpSampler::new(2)
+                .n_startup_trials(4)
+                .n_preliminary_samples(64)
+                .n_local_search(2)
+        };
+        let uninterrupted = create_study().sampler(sampler()).build();
+        uninterrupted.optimize(Branin, 10);
+
+        let interrupted = create_study().study_name("gp").sampler(sampler()).build();
+        interrupted.optimize(Branin, 7);
+        let resumed = create_study()
+            .study_name("gp")
+            .shared_storage(interrupted.storage())
+            .sampler(sampler())
+            .load()
+            .unwrap();
+        resumed.optimize(Branin, 3);
+
+        let trials = resumed.get_trials(None).unwrap();
+        assert!(trials[9].system_attrs().contains_key(KERNEL_ATTR));
+        for (trial, expected) in trials.iter().zip(uninterrupted.get_trials(None).unwrap()) {
+            assert_eq!(trial.internal_params(), expected.internal_params());
+        }
+    }
+}
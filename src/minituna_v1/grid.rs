@@ -0,0 +1,277 @@
+//! Sampler which evaluates every combination of a fixed grid of values.
+
+use super::{
+    Distribution, FrozenTrial, ParamValue, Sampler, Study, TrialError, TrialRng, TrialState,
+};
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+
+const GRID_ID_ATTR: &str = "grid_id";
+const CLAIMED_GRID_ID_ATTR_PREFIX: &str = "grid:claimed:";
+
+/// Samples every combination of the values given per parameter name once.
+///
+/// The combination of a trial is picked in `before_trial` among those no trial
+/// has claimed yet. The claim is a study system attribute set with
+/// `Study::compare_and_set_system_attr`, so several workers sharing a storage
+/// split the grid between them without taking a combination twice. Once every
+/// combination has been claimed, the study is stopped. A worker starting a
+/// trial while the last combinations are still running evaluates a random
+/// combination again.
+///
+/// Suggesting a parameter that is missing from the grid, or whose grid value
+/// is outside its distribution, fails the trial.
+pub struct GridSampler {
+    param_names: Vec<String>,
+    grids: Vec<Vec<ParamValue>>,
+    rng: TrialRng,
+}
+
+impl GridSampler {
+    pub fn new(search_space: HashMap<String, Vec<ParamValue>>, seed: u64) -> Self {
+        let mut search_space: Vec<(String, Vec<ParamValue>)> = search_space.into_iter().collect();
+        search_space.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut grids: Vec<Vec<ParamValue>> = vec![Vec::new()];
+        for (_, values) in search_space.iter() {
+            grids = grids
+                .iter()
+                .flat_map(|grid| {
+                    values.iter().map(move |value| {
+                        let mut grid = grid.clone();
+                        grid.push(value.clone());
+                        grid
+                    })
+                })
+                .collect();
+        }
+
+        GridSampler {
+            param_names: search_space.into_iter().map(|(name, _)| name).collect(),
+            grids,
+            rng: TrialRng::new(seed),
+        }
+    }
+
+    pub fn n_grids(&self) -> usize {
+        self.grids.len()
+    }
+
+    fn unvisited_grid_ids(&self, study: &Study) -> Result<Vec<usize>, TrialError> {
+        let visited: HashSet<usize> = study
+            .system_attrs()?
+            .keys()
+            .filter_map(|key| key.strip_prefix(CLAIMED_GRID_ID_ATTR_PREFIX)?.parse().ok())
+            .collect();
+        Ok((0..self.grids.len())
+            .filter(|id| !visited.contains(id))
+            .collect())
+    }
+}
+
+impl Sampler for GridSampler {
+    fn before_trial(&mut self, study: &Study, trial: &FrozenTrial) {
+        if self.grids.is_empty() {
+            return;
+        }
+        // Without a grid id, the params of the trial are missing from the
+        // grid, which fails it.
+        let mut unvisited = match self.unvisited_grid_ids(study) {
+            Ok(unvisited) => unvisited,
+            Err(_) => return,
+        };
+        self.rng.reseed_for(trial);
+        let grid_id = loop {
+            if unvisited.is_empty() {
+                break self.rng.gen_range(0, self.grids.len());
+            }
+            let grid_id = unvisited.swap_remove(self.rng.gen_range(0, unvisited.len()));
+            // Another worker may claim the same combination first.
+            let key = format!("{}{}", CLAIMED_GRID_ID_ATTR_PREFIX, grid_id);
+            match study.compare_and_set_system_attr(&key, None, &trial.number().to_string()) {
+                Ok(true) => break grid_id,
+                Ok(false) => continue,
+                Err(_) => return,
+            }
+        };
+        let _ = study.set_trial_system_attr(trial.trial_id(), GRID_ID_ATTR, &grid_id.to_string());
+    }
+
+    fn sample_independent(
+        &mut self,
+        _study: &Study,
+        trial: &FrozenTrial,
+        name: &str,
+        distribution: &Distribution,
+    ) -> f64 {
+        let grid_id: Option<usize> = trial
+            .system_attrs()
+            .get(GRID_ID_ATTR)
+            .and_then(|id| id.parse().ok());
+        let param_index = self.param_names.iter().position(|n| n == name);
+        match (grid_id, param_index) {
+            (Some(grid_id), Some(param_index)) => distribution
+                .to_internal_repr(&self.grids[grid_id][param_index])
+                .unwrap_or(f64::NAN),
+            _ => f64::NAN,
+        }
+    }
+
+    fn after_trial(
+        &mut self,
+        study: &Study,
+        _trial: &FrozenTrial,
+        _state: TrialState,
+        _values: Option<&[f64]>,
+    ) {
+        if self
+            .unvisited_grid_ids(study)
+            .is_ok_and(|unvisited| unvisited.is_empty())
+        {
+            study.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::hyperband::HyperbandPruner;
+    use crate::minituna_v1::sqlite::SqliteStorage;
+    use crate::minituna_v1::{create_study, Objective, Storage, Trial, TrialError};
+    use std::fs;
+    use std::thread;
+
+    struct Model;
+
+    impl Objective for Model {
+        fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+            let x = trial.suggest_uniform("x", -10.0, 10.0)?;
+            let n = trial.suggest_int("n", 1, 5, 1, false)?;
+            let optimizer = trial.suggest_categorical("optimizer", &["adam", "sgd"])?;
+            let penalty = if optimizer == "sgd" { 1.0 } else { 0.0 };
+            Ok(x * x + n as f64 + penalty)
+        }
+    }
+
+    fn search_space() -> HashMap<String, Vec<ParamValue>> {
+        let mut search_space = HashMap::new();
+        search_space.insert(
+            "x".to_string(),
+            vec![
+                ParamValue::Float(-1.0),
+                ParamValue::Float(0.0),
+                ParamValue::Int(2),
+            ],
+        );
+        search_space.insert(
+            "n".to_string(),
+            vec![ParamValue::Int(1), ParamValue::Int(3)],
+        );
+        search_space.insert(
+            "optimizer".to_string(),
+            vec![ParamValue::from("adam"), ParamValue::from("sgd")],
+        );
+        search_space
+    }
+
+    #[test]
+    fn every_combination_is_evaluated_once() {
+        let sampler = GridSampler::new(search_space(), 1);
+        assert_eq!(sampler.n_grids(), 12);
+        let study = create_study().sampler(sampler).build();
+        study.optimize(Model, 100);
+
+        let trials = study.get_trials(Some(&[TrialState::Completed])).unwrap();
+        assert_eq!(trials.len(), 12);
+        let combinations: HashSet<String> = trials
+            .iter()
+            .map(|t| {
+                let params = t.params();
+                format!(
+                    "{:?} {:?} {:?}",
+                    params["x"], params["n"], params["optimizer"]
+                )
+            })
+            .collect();
+        assert_eq!(combinations.len(), 12);
+
+        let best = study.best_trial().unwrap().unwrap().params();
+        assert_eq!(best["x"], ParamValue::Float(0.0));
+        assert_eq!(best["n"], ParamValue::Int(1));
+    }
+
+    #[test]
+    fn grid_spans_the_brackets_of_hyperband() {
+        let study = create_study()
+            .sampler(GridSampler::new(search_space(), 1))
+            .pruner(HyperbandPruner::new(1, 9))
+            .build();
+        study.optimize(Model, 100);
+
+        let trials = study.get_trials(None).unwrap();
+        assert_eq!(trials.len(), 12);
+        let grid_ids: HashSet<&String> = trials
+            .iter()
+            .map(|t| &t.system_attrs()[GRID_ID_ATTR])
+            .collect();
+        assert_eq!(grid_ids.len(), 12);
+    }
+
+    #[test]
+    fn workers_sharing_a_storage_never_take_a_combination_twice() {
+        let path = std::env::temp_dir().join(format!("minituna-grid-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        SqliteStorage::new(&path)
+            .unwrap()
+            .create_new_study("grid")
+            .unwrap();
+
+        // Samplers with the same seed are the most likely to pick alike.
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let study = create_study()
+                        .study_name("grid")
+                        .storage(SqliteStorage::new(&path).unwrap())
+                        .sampler(GridSampler::new(search_space(), 1))
+                        .build();
+                    study.optimize(Model, 3);
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let storage = SqliteStorage::new(&path).unwrap();
+        let study_id = storage.get_study_id_from_name("grid").unwrap().unwrap();
+        let trials = storage.get_all_trials(study_id, None).unwrap();
+        assert_eq!(trials.len(), 12);
+        let grid_ids: HashSet<&String> = trials
+            .iter()
+            .map(|t| &t.system_attrs()[GRID_ID_ATTR])
+            .collect();
+        assert_eq!(grid_ids.len(), 12);
+        for suffix in ["", "-wal", "-shm"].iter() {
+            let mut file = path.as_os_str().to_os_string();
+            file.push(suffix);
+            let _ = fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn params_missing_from_the_grid_fail_the_trial() {
+        let mut search_space = search_space();
+        search_space.remove("n");
+        let study = create_study()
+            .sampler(GridSampler::new(search_space, 1))
+            .build();
+        study.optimize(Model, 1);
+        assert_eq!(
+            study.get_trials(None).unwrap()[0].state(),
+            TrialState::Failed
+        );
+    }
+}
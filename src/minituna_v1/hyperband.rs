@@ -0,0 +1,367 @@
+//! Successive Halving and Hyperband, pruners which spend most of the budget
+//! on the trials doing best at a few promotion steps.
+
+use super::{FrozenTrial, Pruner, Study, StudyDirection};
+
+fn rung_attr(rung: u32) -> String {
+    format!("completed_rung_{}", rung)
+}
+
+/// Asynchronous Successive Halving.
+///
+/// A trial completes rung `r` at step
+/// `min_resource * reduction_factor^(min_early_stopping_rate + r)`, which
+/// records its intermediate value as the trial system attribute
+/// `completed_rung_{r}`. It is promoted to the next
+/// rung when that value is among the best `1 / reduction_factor` of the values
+/// every trial recorded for the rung so far, and pruned otherwise. A trial
+/// reporting NaN at a rung is pruned, and recorded NaN values do not compete.
+pub struct SuccessiveHalvingPruner {
+    min_resource: u64,
+    reduction_factor: u64,
+    min_early_stopping_rate: u32,
+}
+
+impl SuccessiveHalvingPruner {
+    pub fn new() -> Self {
+        SuccessiveHalvingPruner {
+            min_resource: 1,
+            reduction_factor: 4,
+            min_early_stopping_rate: 0,
+        }
+    }
+
+    pub fn min_resource(mut self, min_resource: u64) -> Self {
+        self.min_resource = min_resource.max(1);
+        self
+    }
+
+    pub fn reduction_factor(mut self, reduction_factor: u64) -> Self {
+        self.reduction_factor = reduction_factor.max(2);
+        self
+    }
+
+    pub fn min_early_stopping_rate(mut self, min_early_stopping_rate: u32) -> Self {
+        self.min_early_stopping_rate = min_early_stopping_rate;
+        self
+    }
+
+    fn rung_promotion_step(&self, rung: u32) -> u64 {
+        let exponent = self.min_early_stopping_rate + rung;
+        self.min_resource
+            .saturating_mul(self.reduction_factor.saturating_pow(exponent))
+    }
+
+    /// Judges `trial` against the rungs `trials` completed.
+    fn prune_among(&self, study: &Study, trial: &FrozenTrial, trials: &[FrozenTrial]) -> bool {
+        let step = match trial.last_step() {
+            Some(step) => step,
+            None => return false,
+        };
+        let value = trial.intermediate_values()[&step];
+        let mut rung = (0..)
+            .take_while(|&rung| trial.system_attrs().contains_key(&rung_attr(rung)))
+            .count() as u32;
+
+        while step >= self.rung_promotion_step(rung) {
+            if value.is_nan() {
+                return true;
+            }
+            let key = rung_attr(rung);
+            let _ = study.set_trial_system_attr(trial.trial_id(), &key, &value.to_string());
+            let mut competing: Vec<f64> = trials
+                .iter()
+                .filter(|t| t.trial_id() != trial.trial_id())
+                .filter_map(|t| t.system_attrs().get(&key)?.parse().ok())
+                .filter(|v: &f64| !v.is_nan())
+                .collect();
+            competing.push(value);
+            if !self.is_promotable(value, competing, study.direction()) {
+                return true;
+            }
+            rung += 1;
+        }
+        false
+    }
+
+    fn is_promotable(
+        &self,
+        value: f64,
+        mut competing: Vec<f64>,
+        direction: StudyDirection,
+    ) -> bool {
+        competing.sort_by(f64::total_cmp);
+        let n_promotable = (competing.len() / self.reduction_factor as usize).max(1);
+        match direction {
+            StudyDirection::Minimize => value <= competing[n_promotable - 1],
+            StudyDirection::Maximize => value >= competing[competing.len() - n_promotable],
+        }
+    }
+}
+
+impl Default for SuccessiveHalvingPruner {
+    fn default() -> Self {
+        SuccessiveHalvingPruner::new()
+    }
+}
+
+impl Pruner for SuccessiveHalvingPruner {
+    fn prune(&mut self, study: &Study, trial: &FrozenTrial) -> bool {
+        match study.get_trials(None) {
+            Ok(trials) => self.prune_among(study, trial, &trials),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Hyperband runs Successive Halving in several brackets, from the most
+/// aggressive one, which judges trials from `min_resource` on, to the most
+/// conservative one, which lets trials run until close to `max_resource`.
+///
+/// Bracket `i` is a `SuccessiveHalvingPruner` with `min_early_stopping_rate`
+/// `i`, and takes a share of the trials decided by the trial number, larger for
+/// the aggressive brackets. Each bracket only competes with its own trials,
+/// and samplers only see the trials of the bracket of the trial they sample.
+pub struct HyperbandPruner {
+    min_resource: u64,
+    max_resource: u64,
+    reduction_factor: u64,
+}
+
+impl HyperbandPruner {
+    pub fn new(min_resource: u64, max_resource: u64) -> Self {
+        let min_resource = min_resource.max(1);
+        HyperbandPruner {
+            min_resource,
+            max_resource: max_resource.max(min_resource),
+            reduction_factor: 3,
+        }
+    }
+
+    pub fn reduction_factor(mut self, reduction_factor: u64) -> Self {
+        self.reduction_factor = reduction_factor.max(2);
+        self
+    }
+
+    pub fn n_brackets(&self) -> u32 {
+        let mut n_brackets = 1;
+        let mut resource = self.min_resource;
+        while let Some(next) = resource
+            .checked_mul(self.reduction_factor)
+            .filter(|&next| next <= self.max_resource)
+        {
+            resource = next;
+            n_brackets += 1;
+        }
+        n_brackets
+    }
+
+    /// Number of trials out of every `sum of budgets` that go to `bracket`,
+    /// saturating for resource ranges too wide to count them in a `u64`.
+    fn bracket_budget(&self, bracket: u32) -> u64 {
+        let n_brackets = self.n_brackets() as u64;
+        let s = n_brackets - 1 - bracket as u64;
+        let numerator = n_brackets.saturating_mul(self.reduction_factor.saturating_pow(s as u32));
+        numerator.saturating_add(s) / (s + 1)
+    }
+
+    pub fn bracket_of(&self, trial_number: u32) -> u32 {
+        let budgets: Vec<u64> = (0..self.n_brackets())
+            .map(|bracket| self.bracket_budget(bracket))
+            .collect();
+        let total = budgets.iter().fold(0u64, |sum, &b| sum.saturating_add(b));
+        let mut n = trial_number as u64 % total;
+        for (bracket, budget) in budgets.into_iter().enumerate() {
+            if n < budget {
+                return bracket as u32;
+            }
+            n -= budget;
+        }
+        unreachable!()
+    }
+
+    fn bracket_pruner(&self, bracket: u32) -> SuccessiveHalvingPruner {
+        SuccessiveHalvingPruner::new()
+            .min_resource(self.min_resource)
+            .reduction_factor(self.reduction_factor)
+            .min_early_stopping_rate(bracket)
+    }
+}
+
+impl Pruner for HyperbandPruner {
+    fn prune(&mut self, study: &Study, trial: &FrozenTrial) -> bool {
+        let bracket = self.bracket_of(trial.number());
+        let trials = match study.get_trials(None) {
+            Ok(trials) => self.filter_trials(trial.number(), trials),
+            Err(_) => return false,
+        };
+        self.bracket_pruner(bracket)
+            .prune_among(study, trial, &trials)
+    }
+
+    fn filter_trials(&self, trial_number: u32, trials: Vec<FrozenTrial>) -> Vec<FrozenTrial> {
+        let bracket = self.bracket_of(trial_number);
+        trials
+            .into_iter()
+            .filter(|t| self.bracket_of(t.number()) == bracket)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::{
+        create_study, Distribution, InMemoryStorage, Objective, RandomSampler, Sampler, Storage,
+        Trial, TrialError, TrialState,
+    };
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A study with one completed trial per value in `rung_values`, each of
+    /// which recorded it for rung 0, and a running trial.
+    fn study_with_rung(rung_values: &[f64]) -> (Study, u32) {
+        let mut storage = InMemoryStorage::new();
+        let study_id = storage.create_new_study("study").unwrap();
+        for value in rung_values {
+            let trial_id = storage.create_new_trial(study_id, None).unwrap();
+            storage
+                .set_trial_system_attr(trial_id, &rung_attr(0), &value.to_string())
+                .unwrap();
+            storage.set_trial_value(trial_id, *value).unwrap();
+            storage
+                .set_trial_state(trial_id, TrialState::Completed)
+                .unwrap();
+        }
+        let trial_id = storage.create_new_trial(study_id, None).unwrap();
+        let study = create_study().study_name("study").storage(storage).build();
+        (study, trial_id)
+    }
+
+    fn report_and_prune(study: &Study, trial_id: u32, step: u64, value: f64) -> bool {
+        study
+            .storage
+            .borrow_mut()
+            .set_trial_intermediate_value(trial_id, step, value)
+            .unwrap();
+        let trial = study.storage.borrow().get_trial(trial_id).unwrap().unwrap();
+        SuccessiveHalvingPruner::new().prune(study, &trial)
+    }
+
+    #[test]
+    fn successive_halving_promotes_the_best_of_a_rung() {
+        let (study, trial_id) = study_with_rung(&[1.0, 2.0, 3.0, 4.0]);
+        assert!(!report_and_prune(&study, trial_id, 0, 9.0));
+        assert!(!report_and_prune(&study, trial_id, 1, 0.5));
+        let trial = study.storage.borrow().get_trial(trial_id).unwrap().unwrap();
+        assert_eq!(trial.system_attrs()[&rung_attr(0)], "0.5");
+        assert!(!trial.system_attrs().contains_key(&rung_attr(1)));
+        // Alone at rung 1, the trial is promoted again.
+        assert!(!report_and_prune(&study, trial_id, 4, 3.0));
+
+        let (study, trial_id) = study_with_rung(&[1.0, 2.0, 3.0, 4.0]);
+        assert!(report_and_prune(&study, trial_id, 1, 1.5));
+        let (study, trial_id) = study_with_rung(&[]);
+        assert!(report_and_prune(&study, trial_id, 1, f64::NAN));
+    }
+
+    #[test]
+    fn nan_values_of_a_rung_do_not_compete() {
+        let rung_values: Vec<f64> = (0..64)
+            .map(|i| if i % 3 == 0 { f64::NAN } else { i as f64 })
+            .collect();
+        // 42 recorded values and the trial's own compete, so the best 10 are
+        // promoted, the 10th best recorded one being 14.
+        let (study, trial_id) = study_with_rung(&rung_values);
+        assert!(!report_and_prune(&study, trial_id, 1, 13.5));
+        let (study, trial_id) = study_with_rung(&rung_values);
+        assert!(report_and_prune(&study, trial_id, 1, 14.5));
+    }
+
+    #[test]
+    fn hyperband_allocates_more_trials_to_aggressive_brackets() {
+        let pruner = HyperbandPruner::new(1, 27);
+        assert_eq!(pruner.n_brackets(), 4);
+        let budgets: Vec<u64> = (0..4).map(|b| pruner.bracket_budget(b)).collect();
+        assert_eq!(budgets, vec![27, 12, 6, 4]);
+        assert_eq!(pruner.bracket_of(26), 0);
+        assert_eq!(pruner.bracket_of(27), 1);
+        assert_eq!(pruner.bracket_of(48), 3);
+        assert_eq!(pruner.bracket_of(49), 0);
+        assert_eq!(HyperbandPruner::new(1, 2).n_brackets(), 1);
+
+        let widest = HyperbandPruner::new(1, u64::MAX).reduction_factor(2);
+        assert_eq!(widest.n_brackets(), 64);
+        assert_eq!(widest.bracket_of(u32::MAX), 0);
+    }
+
+    /// Trial numbers the sampler saw, by the trial it was sampling.
+    type SeenTrialNumbers = Vec<(u32, Vec<u32>)>;
+
+    #[test]
+    fn samplers_only_see_trials_of_the_same_bracket() {
+        struct RecordingSampler {
+            random: RandomSampler,
+            seen: Rc<RefCell<SeenTrialNumbers>>,
+        }
+
+        impl Sampler for RecordingSampler {
+            fn before_trial(&mut self, study: &Study, trial: &FrozenTrial) {
+                let numbers = study
+                    .get_trials(None)
+                    .unwrap()
+                    .iter()
+                    .map(|t| t.number())
+                    .collect();
+                self.seen.borrow_mut().push((trial.number(), numbers));
+            }
+
+            fn sample_independent(
+                &mut self,
+                study: &Study,
+                trial: &FrozenTrial,
+                name: &str,
+                distribution: &Distribution,
+            ) -> f64 {
+                self.random
+                    .sample_independent(study, trial, name, distribution)
+            }
+        }
+
+        struct Training;
+
+        impl Objective for Training {
+            fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+                let x = trial.suggest_uniform("x", 0.0, 1.0)?;
+                for step in 0..9 {
+                    trial.report(step, x * (9 - step) as f64)?;
+                    if trial.should_prune() {
+                        return Err(TrialError::pruned());
+                    }
+                }
+                Ok(0.0)
+            }
+        }
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sampler = RecordingSampler {
+            random: RandomSampler::new(1),
+            seen: Rc::clone(&seen),
+        };
+        let pruner = HyperbandPruner::new(1, 9);
+        let study = create_study().sampler(sampler).pruner(pruner).build();
+        study.optimize(Training, 30);
+
+        let brackets = HyperbandPruner::new(1, 9);
+        for (number, numbers) in seen.borrow().iter() {
+            let bracket = brackets.bracket_of(*number);
+            assert!(numbers.contains(number));
+            assert!(numbers.iter().all(|&n| brackets.bracket_of(n) == bracket));
+        }
+        assert_eq!(study.get_trials(None).unwrap().len(), 30);
+        assert!(!study
+            .get_trials(Some(&[TrialState::Pruned]))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,782 @@
+//! Storage which appends every update to a file of JSON lines, so that
+//! studies survive the process and several processes can share them.
+
+use super::json::{
+    direction_from_name, direction_name, distribution_from_json, distribution_to_json,
+    state_from_name, state_name, Json,
+};
+use super::{
+    Distribution, FrozenTrial, InMemoryStorage, Storage, StudyDirection, TrialError, TrialState,
+};
+use ordered_float::OrderedFloat;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// A `Storage` kept as a journal of operations in a file, one JSON object per
+/// line.
+///
+/// The state is rebuilt by replaying the journal into an `InMemoryStorage`,
+/// and brought up to date with the lines other processes appended before every
+/// read and write. A write holds an advisory lock on a file next to the
+/// journal while it catches up, checks the operation against the replayed
+/// state and appends it, so the processes sharing the journal agree on trial
+/// ids and reject the same invalid updates. The operating system releases the
+/// lock of a process that crashes.
+///
+/// A line left without its line break by a crash in the middle of an append is
+/// ended by the next write, and skipped by every process if it does not parse,
+/// see `n_skipped_lines`.
+pub struct JournalFileStorage {
+    path: PathBuf,
+    lock_path: PathBuf,
+    replica: RefCell<InMemoryStorage>,
+    /// Number of bytes of the journal replayed into `replica`.
+    offset: Cell<u64>,
+    n_skipped_lines: Cell<u64>,
+}
+
+impl JournalFileStorage {
+    /// Opens the journal at `path`, creating an empty one if there is none.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, TrialError> {
+        let path = path.as_ref().to_path_buf();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| io_error(&path, err))?;
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let storage = JournalFileStorage {
+            path,
+            lock_path: PathBuf::from(lock_path),
+            replica: RefCell::new(InMemoryStorage::new()),
+            offset: Cell::new(0),
+            n_skipped_lines: Cell::new(0),
+        };
+        storage.sync()?;
+        Ok(storage)
+    }
+
+    /// Number of journal lines replayed so far which did not parse or apply.
+    /// Every process sharing the journal skips the same lines.
+    pub fn n_skipped_lines(&self) -> u64 {
+        self.n_skipped_lines.get()
+    }
+
+    /// Replays the complete lines appended to the journal since the last
+    /// call.
+    fn sync(&self) -> Result<(), TrialError> {
+        let mut file = File::open(&self.path).map_err(|err| io_error(&self.path, err))?;
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(self.offset.get()))
+            .and_then(|_| file.read_to_end(&mut bytes))
+            .map_err(|err| io_error(&self.path, err))?;
+        let complete = match bytes.iter().rposition(|&b| b == b'\n') {
+            Some(end) => end + 1,
+            None => return Ok(()),
+        };
+
+        let mut replica = self.replica.borrow_mut();
+        for line in String::from_utf8_lossy(&bytes[..complete]).lines() {
+            // Lines that do not parse are skipped like operations that fail,
+            // in every process alike.
+            let applied = Json::parse(line).map(|operation| apply(&mut replica, &operation));
+            if !matches!(applied, Some(Ok(_))) {
+                self.n_skipped_lines.set(self.n_skipped_lines.get() + 1);
+            }
+        }
+        self.offset.set(self.offset.get() + complete as u64);
+        Ok(())
+    }
+
+    /// Applies `operation` to the replica and appends it to the journal if it
+    /// succeeds, under the lock.
+    fn write(&mut self, operation: Json) -> Result<u32, TrialError> {
+        let _lock = FileLock::acquire(&self.lock_path)?;
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| io_error(&self.path, err))?;
+        self.sync()?;
+        // Bytes past the last line break were left by a crashed append, as no
+        // other process writes while we hold the lock.
+        let len = file
+            .metadata()
+            .map_err(|err| io_error(&self.path, err))?
+            .len();
+        if len > self.offset.get() {
+            file.write_all(b"\n")
+                .map_err(|err| io_error(&self.path, err))?;
+            self.sync()?;
+        }
+        let id = apply(&mut self.replica.borrow_mut(), &operation)?;
+
+        let line = format!("{}\n", operation);
+        if let Err(err) = file.write_all(line.as_bytes()) {
+            // Drop what was appended, and rebuild the replica, which is ahead
+            // of the journal now.
+            let _ = file.set_len(self.offset.get());
+            *self.replica.borrow_mut() = InMemoryStorage::new();
+            self.offset.set(0);
+            self.n_skipped_lines.set(0);
+            let _ = self.sync();
+            return Err(io_error(&self.path, err));
+        }
+        self.offset.set(self.offset.get() + line.len() as u64);
+        Ok(id)
+    }
+
+    /// Brings the replica up to date for a read. Fails when the journal
+    /// cannot be read.
+    fn read(&self) -> Result<std::cell::Ref<'_, InMemoryStorage>, TrialError> {
+        self.sync()?;
+        Ok(self.replica.borrow())
+    }
+}
+
+impl Storage for JournalFileStorage {
+    fn create_new_study(&mut self, study_name: &str) -> Result<u32, TrialError> {
+        self.write(operation(
+            "create_new_study",
+            vec![("study_name", study_name.into())],
+        ))
+    }
+
+    fn get_study_id_from_name(&self, study_name: &str) -> Result<Option<u32>, TrialError> {
+        self.read()?.get_study_id_from_name(study_name)
+    }
+
+    fn get_study_name_from_id(&self, study_id: u32) -> Result<Option<String>, TrialError> {
+        self.read()?.get_study_name_from_id(study_id)
+    }
+
+    fn get_all_study_ids(&self) -> Result<Vec<u32>, TrialError> {
+        self.read()?.get_all_study_ids()
+    }
+
+    fn delete_study(&mut self, study_id: u32) -> Result<(), TrialError> {
+        self.write(operation(
+            "delete_study",
+            vec![("study_id", (study_id as f64).into())],
+        ))
+        .map(|_| ())
+    }
+
+    fn set_study_directions(
+        &mut self,
+        study_id: u32,
+        directions: &[StudyDirection],
+    ) -> Result<(), TrialError> {
+        let directions = directions
+            .iter()
+            .map(|&direction| direction_name(direction).into())
+            .collect();
+        self.write(operation(
+            "set_study_directions",
+            vec![
+                ("study_id", (study_id as f64).into()),
+                ("directions", Json::Array(directions)),
+            ],
+        ))
+        .map(|_| ())
+    }
+
+    fn get_study_directions(&self, study_id: u32) -> Result<Vec<StudyDirection>, TrialError> {
+        self.read()?.get_study_directions(study_id)
+    }
+
+    fn create_new_trial(
+        &mut self,
+        study_id: u32,
+        template: Option<&FrozenTrial>,
+    ) -> Result<u32, TrialError> {
+        let template = template.map_or(Json::Null, trial_to_json);
+        self.write(operation(
+            "create_new_trial",
+            vec![
+                ("study_id", (study_id as f64).into()),
+                ("template", template),
+            ],
+        ))
+    }
+
+    fn set_trial_param(
+        &mut self,
+        trial_id: u32,
+        name: &str,
+        value: f64,
+        distribution: Distribution,
+    ) -> Result<(), TrialError> {
+        self.write(operation(
+            "set_trial_param",
+            vec![
+                ("trial_id", (trial_id as f64).into()),
+                ("name", name.into()),
+                ("value", value.into()),
+                ("distribution", distribution_to_json(&distribution)),
+            ],
+        ))
+        .map(|_| ())
+    }
+
+    fn set_trial_state(&mut self, trial_id: u32, state: TrialState) -> Result<(), TrialError> {
+        self.write(operation(
+            "set_trial_state",
+            vec![
+                ("trial_id", (trial_id as f64).into()),
+                ("state", state_name(state).into()),
+            ],
+        ))
+        .map(|_| ())
+    }
+
+    fn set_trial_values(&mut self, trial_id: u32, values: &[f64]) -> Result<(), TrialError> {
+        let values = Json::Array(values.iter().map(|&v| v.into()).collect());
+        self.write(operation(
+            "set_trial_values",
+            vec![("trial_id", (trial_id as f64).into()), ("values", values)],
+        ))
+        .map(|_| ())
+    }
+
+    fn set_trial_intermediate_value(
+        &mut self,
+        trial_id: u32,
+        step: u64,
+        value: f64,
+    ) -> Result<(), TrialError> {
+        self.write(operation(
+            "set_trial_intermediate_value",
+            vec![
+                ("trial_id", (trial_id as f64).into()),
+                ("step", (step as f64).into()),
+                ("value", value.into()),
+            ],
+        ))
+        .map(|_| ())
+    }
+
+    fn set_trial_user_attr(
+        &mut self,
+        trial_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        self.write(attr_operation(
+            "set_trial_user_attr",
+            "trial_id",
+            trial_id,
+            key,
+            value,
+        ))
+        .map(|_| ())
+    }
+
+    fn set_trial_system_attr(
+        &mut self,
+        trial_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        self.write(attr_operation(
+            "set_trial_system_attr",
+            "trial_id",
+            trial_id,
+            key,
+            value,
+        ))
+        .map(|_| ())
+    }
+
+    fn set_study_user_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        self.write(attr_operation(
+            "set_study_user_attr",
+            "study_id",
+            study_id,
+            key,
+            value,
+        ))
+        .map(|_| ())
+    }
+
+    fn set_study_system_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        self.write(attr_operation(
+            "set_study_system_attr",
+            "study_id",
+            study_id,
+            key,
+            value,
+        ))
+        .map(|_| ())
+    }
+
+    fn compare_and_set_study_system_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+    ) -> Result<bool, TrialError> {
+        let mut operation = attr_operation(
+            "compare_and_set_study_system_attr",
+            "study_id",
+            study_id,
+            key,
+            value,
+        );
+        if let (Json::Object(fields), Some(expected)) = (&mut operation, expected) {
+            fields.insert("expected".to_string(), expected.into());
+        }
+        self.write(operation).map(|set| set == 1)
+    }
+
+    fn get_study_user_attrs(&self, study_id: u32) -> Result<HashMap<String, String>, TrialError> {
+        self.read()?.get_study_user_attrs(study_id)
+    }
+
+    fn get_study_system_attrs(&self, study_id: u32) -> Result<HashMap<String, String>, TrialError> {
+        self.read()?.get_study_system_attrs(study_id)
+    }
+
+    fn get_trial(&self, trial_id: u32) -> Result<Option<FrozenTrial>, TrialError> {
+        self.read()?.get_trial(trial_id)
+    }
+
+    fn get_all_trials(
+        &self,
+        study_id: u32,
+        states: Option<&[TrialState]>,
+    ) -> Result<Vec<FrozenTrial>, TrialError> {
+        self.read()?.get_all_trials(study_id, states)
+    }
+
+    fn get_best_trial(
+        &self,
+        study_id: u32,
+        direction: StudyDirection,
+    ) -> Result<Option<FrozenTrial>, TrialError> {
+        self.read()?.get_best_trial(study_id, direction)
+    }
+}
+
+/// An exclusive advisory lock on a file, held until dropped. The lock file
+/// itself stays in place.
+struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    fn acquire(path: &Path) -> Result<FileLock, TrialError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(|err| io_error(path, err))?;
+        file.lock().map_err(|err| io_error(path, err))?;
+        Ok(FileLock { _file: file })
+    }
+}
+
+fn io_error(path: &Path, err: io::Error) -> TrialError {
+    TrialError::new(&format!("journal {}: {}", path.display(), err))
+}
+
+fn operation(name: &str, fields: Vec<(&str, Json)>) -> Json {
+    let mut object: BTreeMap<String, Json> = fields
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+    object.insert("op".to_string(), name.into());
+    Json::Object(object)
+}
+
+fn attr_operation(name: &str, id_name: &str, id: u32, key: &str, value: &str) -> Json {
+    operation(
+        name,
+        vec![
+            (id_name, (id as f64).into()),
+            ("key", key.into()),
+            ("value", value.into()),
+        ],
+    )
+}
+
+/// Applies a journal line to `storage`, returning the id of the created study
+/// or trial, or 0.
+fn apply(storage: &mut InMemoryStorage, operation: &Json) -> Result<u32, TrialError> {
+    let invalid = || TrialError::new(&format!("invalid journal operation: {}", operation));
+    let field = |key: &str| operation.get(key).ok_or_else(invalid);
+    let id = |key: &str| field(key)?.as_u32().ok_or_else(invalid);
+    let string = |key: &str| field(key)?.as_str().ok_or_else(invalid);
+    let number = |key: &str| field(key)?.as_f64().ok_or_else(invalid);
+
+    match string("op")? {
+        "create_new_study" => storage.create_new_study(string("study_name")?),
+        "delete_study" => storage.delete_study(id("study_id")?).map(|_| 0),
+        "set_study_directions" => {
+            let directions = field("directions")?
+                .as_array()
+                .and_then(|directions| {
+                    directions
+                        .iter()
+                        .map(|direction| direction_from_name(direction.as_str()?))
+                        .collect::<Option<Vec<StudyDirection>>>()
+                })
+                .ok_or_else(invalid)?;
+            storage
+                .set_study_directions(id("study_id")?, &directions)
+                .map(|_| 0)
+        }
+        "create_new_trial" => {
+            let template = match field("template")? {
+                Json::Null => None,
+                template => Some(trial_from_json(template).ok_or_else(invalid)?),
+            };
+            storage.create_new_trial(id("study_id")?, template.as_ref())
+        }
+        "set_trial_param" => {
+            let distribution =
+                distribution_from_json(field("distribution")?).ok_or_else(invalid)?;
+            storage
+                .set_trial_param(
+                    id("trial_id")?,
+                    string("name")?,
+                    number("value")?,
+                    distribution,
+                )
+                .map(|_| 0)
+        }
+        "set_trial_state" => {
+            let state = state_from_name(string("state")?).ok_or_else(invalid)?;
+            storage.set_trial_state(id("trial_id")?, state).map(|_| 0)
+        }
+        "set_trial_values" => {
+            let values = field("values")?
+                .as_array()
+                .and_then(|values| {
+                    values
+                        .iter()
+                        .map(Json::as_f64)
+                        .collect::<Option<Vec<f64>>>()
+                })
+                .ok_or_else(invalid)?;
+            storage
+                .set_trial_values(id("trial_id")?, &values)
+                .map(|_| 0)
+        }
+        "set_trial_intermediate_value" => {
+            let step = field("step")?.as_u64().ok_or_else(invalid)?;
+            storage
+                .set_trial_intermediate_value(id("trial_id")?, step, number("value")?)
+                .map(|_| 0)
+        }
+        "set_trial_user_attr" => storage
+            .set_trial_user_attr(id("trial_id")?, string("key")?, string("value")?)
+            .map(|_| 0),
+        "set_trial_system_attr" => storage
+            .set_trial_system_attr(id("trial_id")?, string("key")?, string("value")?)
+            .map(|_| 0),
+        "set_study_user_attr" => storage
+            .set_study_user_attr(id("study_id")?, string("key")?, string("value")?)
+            .map(|_| 0),
+        "set_study_system_attr" => storage
+            .set_study_system_attr(id("study_id")?, string("key")?, string("value")?)
+            .map(|_| 0),
+        "compare_and_set_study_system_attr" => {
+            let expected = match operation.get("expected") {
+                Some(expected) => Some(expected.as_str().ok_or_else(invalid)?),
+                None => None,
+            };
+            storage
+                .compare_and_set_study_system_attr(
+                    id("study_id")?,
+                    string("key")?,
+                    expected,
+                    string("value")?,
+                )
+                .map(u32::from)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn attrs_to_json(attrs: &HashMap<String, String>) -> Json {
+    Json::Object(
+        attrs
+            .iter()
+            .map(|(key, value)| (key.clone(), value.as_str().into()))
+            .collect(),
+    )
+}
+
+fn attrs_from_json(json: &Json) -> Option<HashMap<String, String>> {
+    json.as_object()?
+        .iter()
+        .map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+        .collect()
+}
+
+/// Everything of a trial but its id and number, for the template of
+/// `create_new_trial`.
+fn trial_to_json(trial: &FrozenTrial) -> Json {
+    let values = match &trial.values {
+        Some(values) => Json::Array(values.iter().map(|v| v.into_inner().into()).collect()),
+        None => Json::Null,
+    };
+    let intermediate_values = trial
+        .intermediate_values
+        .iter()
+        .map(|(&step, &value)| Json::Array(vec![(step as f64).into(), value.into()]))
+        .collect();
+    let params = trial
+        .params
+        .iter()
+        .map(|(name, &value)| (name.clone(), value.into()))
+        .collect();
+    let distributions = trial
+        .distributions
+        .iter()
+        .map(|(name, distribution)| (name.clone(), distribution_to_json(distribution)))
+        .collect();
+    Json::Object(
+        vec![
+            ("state", state_name(trial.state).into()),
+            ("values", values),
+            ("intermediate_values", Json::Array(intermediate_values)),
+            ("params", Json::Object(params)),
+            ("distributions", Json::Object(distributions)),
+            ("user_attrs", attrs_to_json(&trial.user_attrs)),
+            ("system_attrs", attrs_to_json(&trial.system_attrs)),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect(),
+    )
+}
+
+fn trial_from_json(json: &Json) -> Option<FrozenTrial> {
+    let mut trial = FrozenTrial::new(0, state_from_name(json.get("state")?.as_str()?)?);
+    trial.values = match json.get("values")? {
+        Json::Null => None,
+        values => Some(
+            values
+                .as_array()?
+                .iter()
+                .map(|v| v.as_f64().map(OrderedFloat::from))
+                .collect::<Option<Vec<_>>>()?,
+        ),
+    };
+    for pair in json.get("intermediate_values")?.as_array()? {
+        let pair = pair.as_array()?;
+        let (step, value) = (pair.first()?.as_u64()?, pair.get(1)?.as_f64()?);
+        trial.intermediate_values.insert(step, value);
+    }
+    for (name, value) in json.get("params")?.as_object()? {
+        trial.params.insert(name.clone(), value.as_f64()?);
+    }
+    for (name, distribution) in json.get("distributions")?.as_object()? {
+        let distribution = distribution_from_json(distribution)?;
+        trial.distributions.insert(name.clone(), distribution);
+    }
+    trial.user_attrs = attrs_from_json(json.get("user_attrs")?)?;
+    trial.system_attrs = attrs_from_json(json.get("system_attrs")?)?;
+    Some(trial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::{create_study, Objective, Trial};
+    use std::fs;
+    use std::thread;
+
+    /// A journal path in the temporary directory that no other test uses.
+    fn journal_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("minituna-{}-{}.jsonl", name, std::process::id()));
+        remove_journal(&path);
+        path
+    }
+
+    fn remove_journal(path: &Path) {
+        let mut lock_path = path.as_os_str().to_os_string();
+        lock_path.push(".lock");
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(lock_path);
+    }
+
+    struct Model;
+
+    impl Objective for Model {
+        fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+            let x = trial.suggest_uniform("x", -10.0, 10.0)?;
+            let n = trial.suggest_int("n", 1, 8, 1, false)?;
+            let activation = trial.suggest_categorical("activation", &["relu", "tanh"])?;
+            trial.report(0, f64::NAN)?;
+            trial.set_user_attr("note", "line\nbreak \"quoted\"")?;
+            let penalty = if activation == "relu" { 0.0 } else { 1.0 };
+            Ok(x * x + n as f64 + penalty)
+        }
+    }
+
+    #[test]
+    fn journal_is_replayed_by_a_new_storage() {
+        let path = journal_path("replay");
+        let study = create_study()
+            .study_name("replay")
+            .storage(JournalFileStorage::new(&path).unwrap())
+            .build();
+        study.optimize(Model, 5);
+        study.set_system_attr("key", "value");
+        assert!(!study
+            .compare_and_set_system_attr("key", None, "other")
+            .unwrap());
+        assert!(study
+            .compare_and_set_system_attr("claim", None, "value")
+            .unwrap());
+        let waiting = study
+            .storage()
+            .borrow_mut()
+            .create_waiting_trial(study.study_id())
+            .unwrap();
+
+        let replayed = JournalFileStorage::new(&path).unwrap();
+        let study_id = replayed.get_study_id_from_name("replay").unwrap().unwrap();
+        let trials = replayed.get_all_trials(study_id, None).unwrap();
+        let expected = study.get_trials(None).unwrap();
+        assert_eq!(trials.len(), 6);
+        for (trial, expected) in trials.iter().zip(expected.iter()) {
+            assert_eq!(trial.trial_id(), expected.trial_id());
+            assert_eq!(trial.state(), expected.state());
+            assert_eq!(trial.values(), expected.values());
+            assert_eq!(trial.params(), expected.params());
+            assert_eq!(trial.distributions(), expected.distributions());
+            assert_eq!(trial.user_attrs(), expected.user_attrs());
+            let steps = trial.intermediate_values().keys();
+            assert!(steps.eq(expected.intermediate_values().keys()));
+        }
+        assert!(trials[0].intermediate_values()[&0].is_nan());
+        assert_eq!(trials[waiting as usize].state(), TrialState::Waiting);
+        assert_eq!(trials[waiting as usize].number(), 5);
+        assert_eq!(
+            replayed.get_study_system_attrs(study_id).unwrap()["key"],
+            "value"
+        );
+        assert_eq!(
+            replayed.get_study_system_attrs(study_id).unwrap()["claim"],
+            "value"
+        );
+        assert_eq!(
+            replayed.get_study_directions(study_id).unwrap(),
+            vec![StudyDirection::Minimize]
+        );
+
+        study.storage().borrow_mut().delete_study(study_id).unwrap();
+        let replayed = JournalFileStorage::new(&path).unwrap();
+        assert!(replayed.get_all_study_ids().unwrap().is_empty());
+        assert!(replayed.get_trial(0).unwrap().is_none());
+        remove_journal(&path);
+    }
+
+    #[test]
+    fn invalid_operations_are_not_journaled() {
+        let path = journal_path("invalid");
+        let mut storage = JournalFileStorage::new(&path).unwrap();
+        let study_id = storage.create_new_study("study").unwrap();
+        assert!(storage.create_new_study("study").is_err());
+        let trial_id = storage.create_new_trial(study_id, None).unwrap();
+        storage
+            .set_trial_state(trial_id, TrialState::Completed)
+            .unwrap();
+        assert!(storage.set_trial_value(trial_id, 1.0).is_err());
+        assert!(storage
+            .set_trial_state(trial_id, TrialState::Running)
+            .is_err());
+        let lines = fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, 3);
+        remove_journal(&path);
+    }
+
+    #[test]
+    fn a_line_left_by_a_crashed_append_is_skipped_alike() {
+        let path = journal_path("torn");
+        let mut storage = JournalFileStorage::new(&path).unwrap();
+        let study_id = storage.create_new_study("study").unwrap();
+        let mut journal = OpenOptions::new().append(true).open(&path).unwrap();
+        journal.write_all(br#"{"op":"create_new_tr"#).unwrap();
+
+        let mut other = JournalFileStorage::new(&path).unwrap();
+        let trial_id = other.create_new_trial(study_id, None).unwrap();
+        assert_eq!(other.n_skipped_lines(), 1);
+        let replayed = JournalFileStorage::new(&path).unwrap();
+        assert_eq!(replayed.n_skipped_lines(), 1);
+        assert_eq!(storage.get_trial(trial_id).unwrap().unwrap().number(), 0);
+        assert_eq!(replayed.get_all_trials(study_id, None).unwrap().len(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        remove_journal(&path);
+    }
+
+    #[test]
+    fn reads_of_a_missing_journal_fail() {
+        let path = journal_path("missing");
+        let mut storage = JournalFileStorage::new(&path).unwrap();
+        let study_id = storage.create_new_study("study").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let err = storage.get_all_trials(study_id, None).err().unwrap();
+        assert!(err.message().starts_with("journal "));
+        assert!(storage.get_study_id_from_name("study").is_err());
+        remove_journal(&path);
+    }
+
+    #[test]
+    fn processes_share_a_study_through_the_journal() {
+        let path = journal_path("shared");
+        JournalFileStorage::new(&path)
+            .unwrap()
+            .create_new_study("shared")
+            .unwrap();
+
+        let workers: Vec<_> = (0..4)
+            .map(|seed| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let study = create_study()
+                        .study_name("shared")
+                        .storage(JournalFileStorage::new(&path).unwrap())
+                        .seed(seed)
+                        .build();
+                    study.optimize(Model, 10);
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let storage = JournalFileStorage::new(&path).unwrap();
+        let study_id = storage.get_study_id_from_name("shared").unwrap().unwrap();
+        let trials = storage
+            .get_all_trials(study_id, Some(&[TrialState::Completed]))
+            .unwrap();
+        assert_eq!(trials.len(), 40);
+        let mut ids: Vec<u32> = trials.iter().map(|t| t.trial_id()).collect();
+        ids.dedup();
+        assert_eq!(ids, (0..40).collect::<Vec<u32>>());
+        remove_journal(&path);
+    }
+}
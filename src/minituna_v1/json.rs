@@ -0,0 +1,498 @@
+//! Minimal JSON values, and the names and JSON forms in which storages keep
+//! study directions, trial states and distributions.
+//!
+//! Non-finite numbers are written as the bare tokens `NaN`, `Infinity` and
+//! `-Infinity`, as Python's `json` module does, since intermediate values may
+//! be NaN.
+
+use super::{Distribution, ParamValue, StudyDirection, TrialState};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(object) => object.get(key),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u32(&self) -> Option<u32> {
+        self.as_f64()
+            .filter(|n| n.fract() == 0.0 && *n >= 0.0 && *n <= u32::MAX as f64)
+            .map(|n| n as u32)
+    }
+
+    pub(crate) fn as_u64(&self) -> Option<u64> {
+        self.as_f64()
+            .filter(|n| n.fract() == 0.0 && *n >= 0.0)
+            .map(|n| n as u64)
+    }
+
+    pub(crate) fn as_i64(&self) -> Option<i64> {
+        self.as_f64().filter(|n| n.fract() == 0.0).map(|n| n as i64)
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_object(&self) -> Option<&BTreeMap<String, Json>> {
+        match self {
+            Json::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn parse(text: &str) -> Option<Json> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            position: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position == parser.bytes.len() {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    fn write(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Json::Number(number) => {
+                if number.is_nan() {
+                    out.push_str("NaN");
+                } else if number.is_infinite() {
+                    out.push_str(if *number > 0.0 {
+                        "Infinity"
+                    } else {
+                        "-Infinity"
+                    });
+                } else {
+                    let _ = write!(out, "{:?}", number);
+                }
+            }
+            Json::String(value) => write_string(value, out),
+            Json::Array(values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    value.write(out);
+                }
+                out.push(']');
+            }
+            Json::Object(object) => {
+                out.push('{');
+                for (i, (key, value)) in object.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_string(key, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        self.write(&mut out);
+        f.write_str(&out)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(value: f64) -> Self {
+        Json::Number(value)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+fn write_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.bytes[self.position..].starts_with(token.as_bytes()) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+        match *self.bytes.get(self.position)? {
+            b'n' if self.eat("null") => Some(Json::Null),
+            b't' if self.eat("true") => Some(Json::Bool(true)),
+            b'f' if self.eat("false") => Some(Json::Bool(false)),
+            b'N' if self.eat("NaN") => Some(Json::Number(f64::NAN)),
+            b'I' if self.eat("Infinity") => Some(Json::Number(f64::INFINITY)),
+            b'-' if self.eat("-Infinity") => Some(Json::Number(f64::NEG_INFINITY)),
+            b'"' => self.string().map(Json::String),
+            b'[' => {
+                self.position += 1;
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.eat("]") {
+                    return Some(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.skip_whitespace();
+                    if self.eat("]") {
+                        return Some(Json::Array(values));
+                    }
+                    if !self.eat(",") {
+                        return None;
+                    }
+                }
+            }
+            b'{' => {
+                self.position += 1;
+                let mut object = BTreeMap::new();
+                self.skip_whitespace();
+                if self.eat("}") {
+                    return Some(Json::Object(object));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if !self.eat(":") {
+                        return None;
+                    }
+                    object.insert(key, self.value()?);
+                    self.skip_whitespace();
+                    if self.eat("}") {
+                        return Some(Json::Object(object));
+                    }
+                    if !self.eat(",") {
+                        return None;
+                    }
+                }
+            }
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Option<Json> {
+        let start = self.position;
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|b| b"+-0123456789.eE".contains(b))
+        {
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.position]).ok()?;
+        text.parse().ok().map(Json::Number)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        if !self.eat("\"") {
+            return None;
+        }
+        let mut out = String::new();
+        loop {
+            let start = self.position;
+            while !matches!(
+                self.bytes.get(self.position),
+                Some(b'"') | Some(b'\\') | None
+            ) {
+                self.position += 1;
+            }
+            out.push_str(std::str::from_utf8(&self.bytes[start..self.position]).ok()?);
+            match *self.bytes.get(self.position)? {
+                b'"' => {
+                    self.position += 1;
+                    return Some(out);
+                }
+                _ => {
+                    self.position += 1;
+                    let escaped = *self.bytes.get(self.position)?;
+                    self.position += 1;
+                    match escaped {
+                        b'"' => out.push('"'),
+                        b'\\' => out.push('\\'),
+                        b'/' => out.push('/'),
+                        b'b' => out.push('\u{8}'),
+                        b'f' => out.push('\u{c}'),
+                        b'n' => out.push('\n'),
+                        b'r' => out.push('\r'),
+                        b't' => out.push('\t'),
+                        b'u' => out.push(self.unicode_escape()?),
+                        _ => return None,
+                    }
+                }
+            }
+        }
+    }
+
+    /// The character of a `\uXXXX` escape, whose `\u` is already consumed,
+    /// joining surrogate pairs.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex4()?;
+        if (0xd800..0xdc00).contains(&high) {
+            if !self.eat("\\u") {
+                return None;
+            }
+            let low = self.hex4()?;
+            let code = 0x10000 + ((high - 0xd800) << 10) + (low.checked_sub(0xdc00)?);
+            return char::from_u32(code);
+        }
+        char::from_u32(high)
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.bytes.get(self.position..self.position + 4)?;
+        self.position += 4;
+        u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
+
+pub(crate) fn direction_name(direction: StudyDirection) -> &'static str {
+    match direction {
+        StudyDirection::Minimize => "Minimize",
+        StudyDirection::Maximize => "Maximize",
+    }
+}
+
+pub(crate) fn direction_from_name(name: &str) -> Option<StudyDirection> {
+    match name {
+        "Minimize" => Some(StudyDirection::Minimize),
+        "Maximize" => Some(StudyDirection::Maximize),
+        _ => None,
+    }
+}
+
+pub(crate) fn state_name(state: TrialState) -> &'static str {
+    match state {
+        TrialState::Waiting => "Waiting",
+        TrialState::Running => "Running",
+        TrialState::Completed => "Completed",
+        TrialState::Pruned => "Pruned",
+        TrialState::Failed => "Failed",
+    }
+}
+
+pub(crate) fn state_from_name(name: &str) -> Option<TrialState> {
+    match name {
+        "Waiting" => Some(TrialState::Waiting),
+        "Running" => Some(TrialState::Running),
+        "Completed" => Some(TrialState::Completed),
+        "Pruned" => Some(TrialState::Pruned),
+        "Failed" => Some(TrialState::Failed),
+        _ => None,
+    }
+}
+
+fn param_value_to_json(value: &ParamValue) -> Json {
+    let (kind, value) = match value {
+        ParamValue::Bool(value) => ("bool", Json::Bool(*value)),
+        ParamValue::Int(value) => ("int", (*value as f64).into()),
+        ParamValue::Float(value) => ("float", (*value).into()),
+        ParamValue::Str(value) => ("str", value.as_str().into()),
+    };
+    let mut object = BTreeMap::new();
+    object.insert(kind.to_string(), value);
+    Json::Object(object)
+}
+
+fn param_value_from_json(json: &Json) -> Option<ParamValue> {
+    let (kind, value) = json.as_object()?.iter().next()?;
+    match kind.as_str() {
+        "bool" => value.as_bool().map(ParamValue::Bool),
+        "int" => value.as_i64().map(ParamValue::Int),
+        "float" => value.as_f64().map(ParamValue::Float),
+        "str" => value.as_str().map(ParamValue::from),
+        _ => None,
+    }
+}
+
+pub(crate) fn distribution_to_json(distribution: &Distribution) -> Json {
+    let fields: Vec<(&str, Json)> = match distribution {
+        Distribution::Uniform { low, high } => vec![
+            ("type", "Uniform".into()),
+            ("low", (*low).into()),
+            ("high", (*high).into()),
+        ],
+        Distribution::LogUniform { low, high } => vec![
+            ("type", "LogUniform".into()),
+            ("low", (*low).into()),
+            ("high", (*high).into()),
+        ],
+        Distribution::Int {
+            low,
+            high,
+            step,
+            log,
+        } => vec![
+            ("type", "Int".into()),
+            ("low", (*low as f64).into()),
+            ("high", (*high as f64).into()),
+            ("step", (*step as f64).into()),
+            ("log", Json::Bool(*log)),
+        ],
+        Distribution::DiscreteUniform { low, high, q } => vec![
+            ("type", "DiscreteUniform".into()),
+            ("low", (*low).into()),
+            ("high", (*high).into()),
+            ("q", (*q).into()),
+        ],
+        Distribution::Categorical { choices } => vec![
+            ("type", "Categorical".into()),
+            (
+                "choices",
+                Json::Array(choices.iter().map(param_value_to_json).collect()),
+            ),
+        ],
+    };
+    Json::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+pub(crate) fn distribution_from_json(json: &Json) -> Option<Distribution> {
+    let number = |key: &str| json.get(key)?.as_f64();
+    let integer = |key: &str| json.get(key)?.as_i64();
+    match json.get("type")?.as_str()? {
+        "Uniform" => Some(Distribution::Uniform {
+            low: number("low")?,
+            high: number("high")?,
+        }),
+        "LogUniform" => Some(Distribution::LogUniform {
+            low: number("low")?,
+            high: number("high")?,
+        }),
+        "Int" => Some(Distribution::Int {
+            low: integer("low")?,
+            high: integer("high")?,
+            step: integer("step")?,
+            log: json.get("log")?.as_bool()?,
+        }),
+        "DiscreteUniform" => Some(Distribution::DiscreteUniform {
+            low: number("low")?,
+            high: number("high")?,
+            q: number("q")?,
+        }),
+        "Categorical" => Some(Distribution::Categorical {
+            choices: json
+                .get("choices")?
+                .as_array()?
+                .iter()
+                .map(param_value_from_json)
+                .collect::<Option<Vec<ParamValue>>>()?,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trips() {
+        let text = r#"{"a":[1,-2.5,1e-300,NaN,-Infinity],"b":"q\"\\\né","c":null,"d":true}"#;
+        let value = Json::parse(text).unwrap();
+        assert_eq!(value.get("b").unwrap().as_str(), Some("q\"\\\né"));
+        let numbers = value.get("a").unwrap().as_array().unwrap();
+        assert_eq!(numbers[2].as_f64(), Some(1e-300));
+        assert!(numbers[3].as_f64().unwrap().is_nan());
+        let reparsed = Json::parse(&value.to_string()).unwrap();
+        assert_eq!(reparsed.get("b"), value.get("b"));
+        assert_eq!(
+            reparsed.get("a").unwrap().to_string(),
+            "[1.0,-2.5,1e-300,NaN,-Infinity]"
+        );
+        assert_eq!(
+            Json::parse("\"\\ud83d\\ude00\"").unwrap().as_str(),
+            Some("😀")
+        );
+        assert!(Json::parse("[1,]").is_none());
+        assert!(Json::parse("{\"a\":1} x").is_none());
+    }
+}
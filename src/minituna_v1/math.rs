@@ -0,0 +1,422 @@
+//! Numerical helpers shared by the samplers.
+
+use rand::Rng;
+use std::f64::consts::{PI, SQRT_2};
+
+/// Complementary error function with a fractional error below 1.2e-7
+/// (Numerical Recipes, 2nd edition, `erfcc`).
+pub(crate) fn erfc(x: f64) -> f64 {
+    let value = log_erfc_positive(x.abs()).exp();
+    if x >= 0.0 {
+        value
+    } else {
+        2.0 - value
+    }
+}
+
+/// `ln(erfc(z))` for `z >= 0`, accurate far into the tail where `erfc`
+/// underflows.
+fn log_erfc_positive(z: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * z);
+    t.ln() - z * z + erfc_poly(t)
+}
+
+/// Scaled complementary error function `exp(z^2) * erfc(z)` for `z >= 0`.
+pub(crate) fn erfcx(z: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * z);
+    t * erfc_poly(t).exp()
+}
+
+fn erfc_poly(t: f64) -> f64 {
+    -1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))))
+}
+
+pub(crate) fn norm_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / SQRT_2)
+}
+
+pub(crate) fn norm_pdf(x: f64) -> f64 {
+    log_norm_pdf(x).exp()
+}
+
+pub(crate) fn log_norm_pdf(x: f64) -> f64 {
+    -0.5 * x * x - 0.5 * (2.0 * PI).ln()
+}
+
+/// Inverse of the standard normal CDF (Acklam's algorithm refined by one
+/// Halley step).
+pub(crate) fn ndtri(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    let x = if p < P_LOW {
+        let q = (-2.0 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    } else if p <= 1.0 - P_LOW {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        let q = (-2.0 * (1.0 - p).ln()).sqrt();
+        -(((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+
+    let e = norm_cdf(x) - p;
+    let u = e * (2.0 * PI).sqrt() * (0.5 * x * x).exp();
+    x - u / (1.0 + 0.5 * x * u)
+}
+
+/// Samples from the standard normal distribution with the Box-Muller method.
+pub(crate) fn sample_standard_normal<R: Rng + ?Sized>(rng: &mut R) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+/// Samples from a normal distribution truncated to `[low, high]`.
+pub(crate) fn sample_truncated_normal<R: Rng + ?Sized>(
+    rng: &mut R,
+    mu: f64,
+    sigma: f64,
+    low: f64,
+    high: f64,
+) -> f64 {
+    let cdf_low = norm_cdf((low - mu) / sigma);
+    let cdf_high = norm_cdf((high - mu) / sigma);
+    if cdf_high - cdf_low < 1e-12 {
+        // The interval lies far in one tail, where the inverse CDF is unusable.
+        return rng.gen_range(low, high.max(low + f64::EPSILON));
+    }
+    let u = cdf_low + rng.gen::<f64>() * (cdf_high - cdf_low);
+    (mu + sigma * ndtri(u)).max(low).min(high)
+}
+
+pub(crate) fn logsumexp(values: &[f64]) -> f64 {
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + values.iter().map(|v| (v - max).exp()).sum::<f64>().ln()
+}
+
+/// Samples an index with probability proportional to `weights`.
+pub(crate) fn choose_weighted<R: Rng + ?Sized>(rng: &mut R, weights: &[f64]) -> usize {
+    let total: f64 = weights.iter().sum();
+    let mut threshold = rng.gen::<f64>() * total;
+    for (i, w) in weights.iter().enumerate() {
+        if threshold < *w {
+            return i;
+        }
+        threshold -= w;
+    }
+    weights.len() - 1
+}
+
+/// Eigendecomposition of a symmetric matrix with the cyclic Jacobi method.
+/// Returns the eigenvalues and the matrix whose columns are the eigenvectors.
+pub(crate) fn symmetric_eigen(matrix: &[Vec<f64>]) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = matrix.len();
+    let mut a: Vec<Vec<f64>> = matrix.to_vec();
+    let mut v: Vec<Vec<f64>> = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| i != j).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum();
+        if off_diagonal < 1e-30 {
+            break;
+        }
+        for p in 0..n {
+            for q in (p + 1)..n {
+                if a[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for row in a.iter_mut() {
+                    let (akp, akq) = (row[p], row[q]);
+                    row[p] = c * akp - s * akq;
+                    row[q] = s * akp + c * akq;
+                }
+                let (row_p, row_q) = (a[p].clone(), a[q].clone());
+                for k in 0..n {
+                    a[p][k] = c * row_p[k] - s * row_q[k];
+                    a[q][k] = s * row_p[k] + c * row_q[k];
+                }
+                for row in v.iter_mut() {
+                    let (vkp, vkq) = (row[p], row[q]);
+                    row[p] = c * vkp - s * vkq;
+                    row[q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[i][i]).collect(), v)
+}
+
+/// Lower triangular `l` with `l * l^T == matrix`, or `None` when `matrix` is not
+/// positive definite.
+pub(crate) fn cholesky(matrix: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    let mut l = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let dot: f64 = (0..j).map(|k| l[i][k] * l[j][k]).sum();
+            if i == j {
+                let d = matrix[i][i] - dot;
+                if d <= 0.0 || !d.is_finite() {
+                    return None;
+                }
+                l[i][i] = d.sqrt();
+            } else {
+                l[i][j] = (matrix[i][j] - dot) / l[j][j];
+            }
+        }
+    }
+    Some(l)
+}
+
+/// Solves `l * x == b` for a lower triangular `l`.
+pub(crate) fn solve_lower(l: &[Vec<f64>], b: &[f64]) -> Vec<f64> {
+    let mut x = vec![0.0; b.len()];
+    for i in 0..b.len() {
+        let dot: f64 = (0..i).map(|k| l[i][k] * x[k]).sum();
+        x[i] = (b[i] - dot) / l[i][i];
+    }
+    x
+}
+
+/// Solves `l^T * x == b` for a lower triangular `l`.
+pub(crate) fn solve_upper_transposed(l: &[Vec<f64>], b: &[f64]) -> Vec<f64> {
+    let n = b.len();
+    let mut x = vec![0.0; n];
+    for i in (0..n).rev() {
+        let dot: f64 = ((i + 1)..n).map(|k| l[k][i] * x[k]).sum();
+        x[i] = (b[i] - dot) / l[i][i];
+    }
+    x
+}
+
+/// Minimizes `f`, which returns the value and the gradient at a point, within
+/// the box `bounds` with a projected limited-memory BFGS method in the spirit
+/// of L-BFGS-B. Returns the best point found and its value.
+pub(crate) fn minimize_lbfgsb<F>(
+    f: F,
+    x0: &[f64],
+    bounds: &[(f64, f64)],
+    max_iter: usize,
+) -> (Vec<f64>, f64)
+where
+    F: Fn(&[f64]) -> (f64, Vec<f64>),
+{
+    const MEMORY: usize = 10;
+    let project = |x: &[f64]| -> Vec<f64> {
+        x.iter()
+            .zip(bounds)
+            .map(|(v, (low, high))| v.max(*low).min(*high))
+            .collect()
+    };
+    let dot = |a: &[f64], b: &[f64]| -> f64 { a.iter().zip(b).map(|(x, y)| x * y).sum() };
+
+    let mut x = project(x0);
+    let (mut fx, mut g) = f(&x);
+    let mut history: Vec<(Vec<f64>, Vec<f64>, f64)> = Vec::new();
+    for _ in 0..max_iter {
+        // Variables at a bound with the gradient pointing outwards stay fixed.
+        let free: Vec<bool> = (0..x.len())
+            .map(|i| {
+                let (low, high) = bounds[i];
+                !((x[i] <= low && g[i] > 0.0) || (x[i] >= high && g[i] < 0.0))
+            })
+            .collect();
+        let projected_gradient: Vec<f64> = g
+            .iter()
+            .zip(&free)
+            .map(|(g, &free)| if free { *g } else { 0.0 })
+            .collect();
+        if projected_gradient.iter().all(|v| v.abs() < 1e-8) {
+            break;
+        }
+
+        // Two-loop recursion on the free variables.
+        let mut q = projected_gradient.clone();
+        let mut alphas = Vec::with_capacity(history.len());
+        for (s, y, rho) in history.iter().rev() {
+            let alpha = rho * dot(s, &q);
+            for i in 0..q.len() {
+                q[i] -= alpha * y[i];
+            }
+            alphas.push(alpha);
+        }
+        if let Some((s, y, _)) = history.last() {
+            let gamma = dot(s, y) / dot(y, y);
+            q.iter_mut().for_each(|v| *v *= gamma);
+        }
+        for ((s, y, rho), alpha) in history.iter().zip(alphas.iter().rev()) {
+            let beta = rho * dot(y, &q);
+            for i in 0..q.len() {
+                q[i] += (alpha - beta) * s[i];
+            }
+        }
+        let mut direction: Vec<f64> = q
+            .iter()
+            .zip(&free)
+            .map(|(v, &free)| if free { -v } else { 0.0 })
+            .collect();
+        if dot(&direction, &projected_gradient) >= 0.0 {
+            direction = projected_gradient.iter().map(|v| -v).collect();
+            history.clear();
+        }
+
+        let mut step = 1.0;
+        let mut next = None;
+        for _ in 0..30 {
+            let candidate: Vec<f64> = project(
+                &x.iter()
+                    .zip(&direction)
+                    .map(|(v, d)| v + step * d)
+                    .collect::<Vec<f64>>(),
+            );
+            let (f_candidate, g_candidate) = f(&candidate);
+            let moved: Vec<f64> = candidate.iter().zip(&x).map(|(a, b)| a - b).collect();
+            if f_candidate.is_finite() && f_candidate <= fx + 1e-4 * dot(&g, &moved) {
+                next = Some((candidate, f_candidate, g_candidate));
+                break;
+            }
+            step *= 0.5;
+        }
+        let (x_next, f_next, g_next) = match next {
+            Some(next) => next,
+            None => break,
+        };
+
+        let s: Vec<f64> = x_next.iter().zip(&x).map(|(a, b)| a - b).collect();
+        let y: Vec<f64> = g_next.iter().zip(&g).map(|(a, b)| a - b).collect();
+        let sy = dot(&s, &y);
+        if sy > 1e-12 {
+            history.push((s, y, 1.0 / sy));
+            if history.len() > MEMORY {
+                history.remove(0);
+            }
+        }
+        let improvement = fx - f_next;
+        x = x_next;
+        fx = f_next;
+        g = g_next;
+        if improvement.abs() <= 1e-12 * (1.0 + fx.abs()) {
+            break;
+        }
+    }
+    (x, fx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ndtri_inverts_norm_cdf() {
+        for &p in [1e-10, 1e-3, 0.1, 0.5, 0.8, 0.999].iter() {
+            assert!((norm_cdf(ndtri(p)) - p).abs() / p < 1e-6, "{}", p);
+        }
+        assert!((norm_cdf(1.959_963_984_540_054) - 0.975).abs() < 1e-7);
+    }
+
+    #[test]
+    fn minimize_lbfgsb_respects_bounds() {
+        let rosenbrock = |x: &[f64]| {
+            let value = (1.0 - x[0]).powi(2) + 100.0 * (x[1] - x[0] * x[0]).powi(2);
+            let gradient = vec![
+                -2.0 * (1.0 - x[0]) - 400.0 * x[0] * (x[1] - x[0] * x[0]),
+                200.0 * (x[1] - x[0] * x[0]),
+            ];
+            (value, gradient)
+        };
+        let (x, value) = minimize_lbfgsb(rosenbrock, &[-1.0, 2.0], &[(-2.0, 2.0); 2], 200);
+        assert!(value < 1e-8, "{:?} {}", x, value);
+
+        let (x, _) = minimize_lbfgsb(rosenbrock, &[-1.0, 0.0], &[(-2.0, 0.5), (-2.0, 2.0)], 200);
+        assert!(
+            (x[0] - 0.5).abs() < 1e-9 && (x[1] - 0.25).abs() < 1e-4,
+            "{:?}",
+            x
+        );
+    }
+
+    #[test]
+    fn cholesky_solves_linear_systems() {
+        let matrix = vec![vec![4.0, 2.0], vec![2.0, 3.0]];
+        let l = cholesky(&matrix).unwrap();
+        let x = solve_upper_transposed(&l, &solve_lower(&l, &[2.0, 1.0]));
+        assert!((4.0 * x[0] + 2.0 * x[1] - 2.0).abs() < 1e-12);
+        assert!((2.0 * x[0] + 3.0 * x[1] - 1.0).abs() < 1e-12);
+        assert!(cholesky(&[vec![1.0, 2.0], vec![2.0, 1.0]]).is_none());
+    }
+
+    #[test]
+    fn symmetric_eigen_reconstructs_the_matrix() {
+        let matrix = vec![
+            vec![4.0, 1.0, 0.5],
+            vec![1.0, 3.0, 0.2],
+            vec![0.5, 0.2, 1.0],
+        ];
+        let (values, vectors) = symmetric_eigen(&matrix);
+        for i in 0..3 {
+            for j in 0..3 {
+                let reconstructed: f64 = (0..3)
+                    .map(|k| vectors[i][k] * values[k] * vectors[j][k])
+                    .sum();
+                assert!((reconstructed - matrix[i][j]).abs() < 1e-9);
+            }
+        }
+    }
+}
@@ -0,0 +1,569 @@
+//! NSGA-II, an evolutionary sampler for multi-objective studies.
+
+use super::transform::{transform, transformed_bounds, untransform};
+use super::{
+    dominates, intersection_search_space, Distribution, FrozenTrial, RandomSampler, Sampler, Study,
+    StudyDirection, TrialRng, TrialState,
+};
+use rand::rngs::StdRng;
+use rand::Rng;
+use std::collections::HashMap;
+
+const GENERATION_ATTR: &str = "nsga2:generation";
+
+fn parent_population_attr(generation: u32) -> String {
+    format!("nsga2:parent_population:{}", generation)
+}
+
+/// How the parameters of a child are made from those of its two parents.
+/// Categorical parameters are always taken from either parent.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Crossover {
+    /// Takes every parameter from the second parent with `swapping_prob`.
+    Uniform { swapping_prob: f64 },
+    /// Simulated binary crossover. A larger `eta` keeps children closer to
+    /// their parents.
+    Sbx { eta: f64 },
+    /// Samples uniformly from the range spanned by the parents, extended by
+    /// `alpha` times its width on both sides.
+    BlxAlpha { alpha: f64 },
+}
+
+/// Samples the parameters of a generation from the parameters of the best
+/// trials of the previous ones.
+///
+/// Trials are grouped into generations of `population_size` completed trials,
+/// and the generation of a trial is recorded as its system attribute. The
+/// first generation is sampled by an independent sampler. The parents of a
+/// later generation are the `population_size` best trials of the previous
+/// generation and its parents, leaving out trials with a non-finite value,
+/// ranked by non-dominated sorting and then by crowding distance, and their
+/// trial numbers are recorded as a system attribute of the trials sampled from
+/// them. Pruners which keep groups of trials apart, like `HyperbandPruner`,
+/// thus get a population per group. A child is made by
+/// crossover of two parents picked by binary tournament, with probability
+/// `crossover_prob`, or copied from the first one otherwise. Every parameter
+/// is then mutated, i.e. sampled by the independent sampler, with probability
+/// `mutation_prob`, which defaults to one over the number of parameters.
+pub struct NsgaIISampler {
+    population_size: usize,
+    mutation_prob: Option<f64>,
+    crossover: Crossover,
+    crossover_prob: f64,
+    independent_sampler: Box<dyn Sampler>,
+    rng: TrialRng,
+}
+
+impl NsgaIISampler {
+    pub fn new(seed: u64) -> Self {
+        NsgaIISampler {
+            population_size: 50,
+            mutation_prob: None,
+            crossover: Crossover::Uniform { swapping_prob: 0.5 },
+            crossover_prob: 0.9,
+            independent_sampler: Box::new(RandomSampler::new(seed.wrapping_add(1))),
+            rng: TrialRng::new(seed),
+        }
+    }
+
+    pub fn population_size(mut self, population_size: usize) -> Self {
+        self.population_size = population_size.max(2);
+        self
+    }
+
+    pub fn mutation_prob(mut self, mutation_prob: f64) -> Self {
+        self.mutation_prob = Some(mutation_prob);
+        self
+    }
+
+    pub fn crossover(mut self, crossover: Crossover) -> Self {
+        self.crossover = crossover;
+        self
+    }
+
+    pub fn crossover_prob(mut self, crossover_prob: f64) -> Self {
+        self.crossover_prob = crossover_prob;
+        self
+    }
+
+    pub fn independent_sampler<S: Sampler + 'static>(mut self, sampler: S) -> Self {
+        self.independent_sampler = Box::new(sampler);
+        self
+    }
+
+    /// Parents of `generation`, computed from the previous generation on first
+    /// use and cached in the system attributes of `trial`. The cache of the
+    /// first trial which recorded one is used.
+    fn parent_population(
+        &self,
+        study: &Study,
+        trial: &FrozenTrial,
+        completed: &HashMap<u32, FrozenTrial>,
+        generation: u32,
+    ) -> Vec<FrozenTrial> {
+        if generation == 0 {
+            return Vec::new();
+        }
+        let key = parent_population_attr(generation);
+        let trials = match study.get_trials(None) {
+            Ok(trials) => trials,
+            Err(_) => return Vec::new(),
+        };
+        if let Some(numbers) = trials.iter().find_map(|t| t.system_attrs().get(&key)) {
+            return numbers
+                .split_whitespace()
+                .filter_map(|number| completed.get(&number.parse().ok()?).cloned())
+                .collect();
+        }
+
+        let mut population = self.parent_population(study, trial, completed, generation - 1);
+        population.extend(
+            completed
+                .values()
+                .filter(|t| trial_generation(t) == Some(generation - 1))
+                .cloned(),
+        );
+        population.sort_by_key(|t| t.number());
+        let parents = select_elites(population, study.directions(), self.population_size);
+
+        let numbers: Vec<String> = parents.iter().map(|t| t.number().to_string()).collect();
+        let _ = study.set_trial_system_attr(trial.trial_id(), &key, &numbers.join(" "));
+        parents
+    }
+
+    /// Binary tournament: the dominating one of two random parents, or either
+    /// of them when neither dominates.
+    fn select_parent<'a>(
+        &mut self,
+        parents: &'a [FrozenTrial],
+        directions: &[StudyDirection],
+    ) -> &'a FrozenTrial {
+        let a = &parents[self.rng.gen_range(0, parents.len())];
+        let b = &parents[self.rng.gen_range(0, parents.len())];
+        let (a_values, b_values) = (
+            a.values().unwrap_or_default(),
+            b.values().unwrap_or_default(),
+        );
+        if dominates(&b_values, &a_values, directions) {
+            b
+        } else if dominates(&a_values, &b_values, directions) || self.rng.gen_bool(0.5) {
+            a
+        } else {
+            b
+        }
+    }
+
+    fn crossover_param(&mut self, distribution: &Distribution, p0: f64, p1: f64) -> f64 {
+        let pick = |rng: &mut StdRng| if rng.gen_bool(0.5) { p0 } else { p1 };
+        if let Distribution::Categorical { .. } = distribution {
+            return match self.crossover {
+                Crossover::Uniform { swapping_prob } if !self.rng.gen_bool(swapping_prob) => p0,
+                Crossover::Uniform { .. } => p1,
+                _ => pick(&mut self.rng),
+            };
+        }
+
+        let (low, high, _) = transformed_bounds(distribution);
+        let (x0, x1) = (transform(distribution, p0), transform(distribution, p1));
+        let child = match self.crossover {
+            Crossover::Uniform { swapping_prob } => {
+                if self.rng.gen_bool(swapping_prob) {
+                    x1
+                } else {
+                    x0
+                }
+            }
+            Crossover::Sbx { eta } => sbx(&mut self.rng, eta, x0, x1, low, high),
+            Crossover::BlxAlpha { alpha } => {
+                let d = (x0 - x1).abs();
+                let (min, max) = (x0.min(x1) - alpha * d, x0.max(x1) + alpha * d);
+                if min < max {
+                    self.rng.gen_range(min, max)
+                } else {
+                    min
+                }
+            }
+        };
+        untransform(distribution, child.clamp(low, high))
+    }
+}
+
+impl Sampler for NsgaIISampler {
+    fn before_trial(&mut self, study: &Study, trial: &FrozenTrial) {
+        let completed = match study.get_trials(Some(&[TrialState::Completed])) {
+            Ok(completed) => completed,
+            Err(_) => return,
+        };
+        let generation = completed
+            .iter()
+            .filter_map(trial_generation)
+            .max()
+            .unwrap_or(0);
+        let size = completed
+            .iter()
+            .filter(|t| trial_generation(t) == Some(generation))
+            .count();
+        let generation = if size < self.population_size {
+            generation
+        } else {
+            generation + 1
+        };
+        let _ =
+            study.set_trial_system_attr(trial.trial_id(), GENERATION_ATTR, &generation.to_string());
+    }
+
+    fn infer_relative_search_space(
+        &mut self,
+        study: &Study,
+        _trial: &FrozenTrial,
+    ) -> HashMap<String, Distribution> {
+        let trials = study
+            .get_trials(Some(&[TrialState::Completed]))
+            .unwrap_or_default();
+        intersection_search_space(&trials)
+    }
+
+    fn sample_relative(
+        &mut self,
+        study: &Study,
+        trial: &FrozenTrial,
+        search_space: &HashMap<String, Distribution>,
+    ) -> HashMap<String, f64> {
+        let generation = match trial_generation(trial) {
+            Some(generation) if generation > 0 && !search_space.is_empty() => generation,
+            _ => return HashMap::new(),
+        };
+        self.rng.reseed_for(trial);
+        let completed = match study.get_trials(Some(&[TrialState::Completed])) {
+            Ok(completed) => completed,
+            Err(_) => return HashMap::new(),
+        };
+        let completed: HashMap<u32, FrozenTrial> = completed
+            .into_iter()
+            .filter(|t| {
+                let values = t.values();
+                values.is_some_and(|values| values.iter().all(|value| value.is_finite()))
+            })
+            .map(|t| (t.number(), t))
+            .collect();
+        let parents = self.parent_population(study, trial, &completed, generation);
+        if parents.is_empty() {
+            return HashMap::new();
+        }
+
+        let mut search_space: Vec<(&String, &Distribution)> = search_space.iter().collect();
+        search_space.sort_by(|a, b| a.0.cmp(b.0));
+        let p0 = self.select_parent(&parents, study.directions()).clone();
+        let p1 = self.select_parent(&parents, study.directions()).clone();
+        let crossover = self.rng.gen_bool(self.crossover_prob.clamp(0.0, 1.0));
+        let mutation_prob = self
+            .mutation_prob
+            .unwrap_or(1.0 / search_space.len() as f64)
+            .clamp(0.0, 1.0);
+
+        let mut params = HashMap::new();
+        for (name, distribution) in search_space {
+            let (x0, x1) = match (
+                p0.internal_params().get(name),
+                p1.internal_params().get(name),
+            ) {
+                (Some(&x0), Some(&x1)) => (x0, x1),
+                _ => continue,
+            };
+            let child = if crossover {
+                self.crossover_param(distribution, x0, x1)
+            } else {
+                x0
+            };
+            if !self.rng.gen_bool(mutation_prob) {
+                params.insert(name.clone(), child);
+            }
+        }
+        params
+    }
+
+    fn sample_independent(
+        &mut self,
+        study: &Study,
+        trial: &FrozenTrial,
+        name: &str,
+        distribution: &Distribution,
+    ) -> f64 {
+        self.independent_sampler
+            .sample_independent(study, trial, name, distribution)
+    }
+}
+
+fn trial_generation(trial: &FrozenTrial) -> Option<u32> {
+    trial.system_attrs().get(GENERATION_ATTR)?.parse().ok()
+}
+
+/// Simulated binary crossover of `x0` and `x1` bounded by `[low, high]`,
+/// returning either of the two children.
+fn sbx(rng: &mut StdRng, eta: f64, x0: f64, x1: f64, low: f64, high: f64) -> f64 {
+    let (y0, y1) = (x0.min(x1), x0.max(x1));
+    if y1 - y0 < 1e-14 {
+        return x0;
+    }
+    let u: f64 = rng.gen();
+    let spread = |beta: f64| {
+        let alpha = 2.0 - beta.powf(-(eta + 1.0));
+        if u <= 1.0 / alpha {
+            (u * alpha).powf(1.0 / (eta + 1.0))
+        } else {
+            (1.0 / (2.0 - u * alpha)).powf(1.0 / (eta + 1.0))
+        }
+    };
+    if rng.gen_bool(0.5) {
+        let beta_q = spread(1.0 + 2.0 * (y0 - low) / (y1 - y0));
+        0.5 * ((y0 + y1) - beta_q * (y1 - y0))
+    } else {
+        let beta_q = spread(1.0 + 2.0 * (high - y1) / (y1 - y0));
+        0.5 * ((y0 + y1) + beta_q * (y1 - y0))
+    }
+}
+
+/// Splits `values` into fronts of indices: the first front is not dominated
+/// by any point, the second only by points of the first, and so on.
+fn non_dominated_sort(values: &[Vec<f64>], directions: &[StudyDirection]) -> Vec<Vec<usize>> {
+    let n = values.len();
+    let mut dominated_by: Vec<Vec<usize>> = vec![Vec::new(); n];
+    let mut n_dominating = vec![0; n];
+    for i in 0..n {
+        for j in 0..n {
+            if dominates(&values[i], &values[j], directions) {
+                dominated_by[i].push(j);
+            } else if dominates(&values[j], &values[i], directions) {
+                n_dominating[i] += 1;
+            }
+        }
+    }
+
+    let mut fronts = Vec::new();
+    let mut front: Vec<usize> = (0..n).filter(|&i| n_dominating[i] == 0).collect();
+    while !front.is_empty() {
+        let mut next = Vec::new();
+        for &i in front.iter() {
+            for &j in dominated_by[i].iter() {
+                n_dominating[j] -= 1;
+                if n_dominating[j] == 0 {
+                    next.push(j);
+                }
+            }
+        }
+        fronts.push(front);
+        front = next;
+    }
+    fronts
+}
+
+/// Crowding distance of every point of a front: the sum over objectives of the
+/// normalized distance between its neighbours, infinite at the boundaries.
+fn crowding_distance(values: &[&Vec<f64>]) -> Vec<f64> {
+    let n = values.len();
+    let mut distances = vec![0.0; n];
+    let n_objectives = values.first().map_or(0, |v| v.len());
+    for k in 0..n_objectives {
+        let objective: Vec<f64> = values.iter().map(|v| v[k]).collect();
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&a, &b| objective[a].total_cmp(&objective[b]));
+        let (min, max) = (objective[order[0]], objective[order[n - 1]]);
+        distances[order[0]] = f64::INFINITY;
+        distances[order[n - 1]] = f64::INFINITY;
+        if max == min {
+            continue;
+        }
+        for w in order.windows(3) {
+            distances[w[1]] += (objective[w[2]] - objective[w[0]]) / (max - min);
+        }
+    }
+    distances
+}
+
+/// The `size` best of `population` by non-dominated rank, breaking ties in
+/// the last front taken by crowding distance.
+fn select_elites(
+    population: Vec<FrozenTrial>,
+    directions: &[StudyDirection],
+    size: usize,
+) -> Vec<FrozenTrial> {
+    let values: Vec<Vec<f64>> = population
+        .iter()
+        .map(|t| t.values().unwrap_or_default())
+        .collect();
+    let mut elites = Vec::new();
+    for mut front in non_dominated_sort(&values, directions) {
+        if elites.len() + front.len() > size {
+            let front_values: Vec<&Vec<f64>> = front.iter().map(|&i| &values[i]).collect();
+            let distances = crowding_distance(&front_values);
+            let mut order: Vec<usize> = (0..front.len()).collect();
+            order.sort_by(|&a, &b| distances[b].total_cmp(&distances[a]));
+            front = order.into_iter().map(|i| front[i]).collect();
+            front.truncate(size - elites.len());
+        }
+        elites.extend(front);
+        if elites.len() == size {
+            break;
+        }
+    }
+    elites.into_iter().map(|i| population[i].clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::hyperband::HyperbandPruner;
+    use crate::minituna_v1::{create_study, MultiObjective, Trial, TrialError};
+
+    /// Schaffer's problem, whose Pareto set is `0 <= x <= 2`.
+    struct Schaffer;
+
+    impl MultiObjective for Schaffer {
+        fn objectives(&self, trial: Trial<'_>) -> Result<Vec<f64>, TrialError> {
+            let x = trial.suggest_uniform("x", -10.0, 10.0)?;
+            let y = trial.suggest_uniform("y", -10.0, 10.0)?;
+            Ok(vec![x * x + y * y, (x - 2.0).powi(2) + y * y])
+        }
+    }
+
+    /// Numbers of the parents of `generation` the first trial recorded.
+    fn recorded_parents(study: &Study, generation: u32) -> Vec<u32> {
+        let key = parent_population_attr(generation);
+        let trials = study.get_trials(None).unwrap();
+        let numbers = trials.iter().find_map(|t| t.system_attrs().get(&key));
+        numbers
+            .unwrap()
+            .split_whitespace()
+            .map(|number| number.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn non_dominated_sort_ranks_fronts() {
+        let directions = [StudyDirection::Minimize, StudyDirection::Maximize];
+        let values = vec![
+            vec![1.0, 1.0],
+            vec![0.0, 2.0],
+            vec![2.0, 2.0],
+            vec![1.0, 0.0],
+            vec![3.0, 0.0],
+        ];
+        let fronts = non_dominated_sort(&values, &directions);
+        assert_eq!(fronts, vec![vec![1], vec![0, 2], vec![3], vec![4]]);
+    }
+
+    #[test]
+    fn crowding_distance_prefers_boundaries() {
+        let values = [
+            vec![0.0, 4.0],
+            vec![1.0, 3.0],
+            vec![3.0, 1.0],
+            vec![4.0, 0.0],
+        ];
+        let values: Vec<&Vec<f64>> = values.iter().collect();
+        let distances = crowding_distance(&values);
+        assert!(distances[0].is_infinite() && distances[3].is_infinite());
+        assert!((distances[1] - 1.5).abs() < 1e-12);
+        assert!((distances[2] - 1.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn nsga2_approaches_the_pareto_set() {
+        let crossovers = [
+            Crossover::Uniform { swapping_prob: 0.5 },
+            Crossover::Sbx { eta: 20.0 },
+            Crossover::BlxAlpha { alpha: 0.5 },
+        ];
+        for &crossover in crossovers.iter() {
+            let sampler = NsgaIISampler::new(1)
+                .population_size(10)
+                .crossover(crossover);
+            let study = create_study()
+                .directions(&[StudyDirection::Minimize, StudyDirection::Minimize])
+                .sampler(sampler)
+                .build();
+            study.optimize(Schaffer, 300);
+
+            let trials = study.get_trials(Some(&[TrialState::Completed])).unwrap();
+            let generations: Vec<u32> = trials.iter().filter_map(trial_generation).collect();
+            assert_eq!(generations.len(), 300);
+            assert_eq!(*generations.iter().max().unwrap(), 29);
+
+            // The parents of the last generation are close to the Pareto set,
+            // `0 <= x <= 2` and `y = 0`, where the sum of the objectives is
+            // between 2 and 4.
+            let parents = recorded_parents(&study, 29);
+            assert_eq!(parents.len(), 10);
+            for number in parents {
+                let parent = trials.iter().find(|t| t.number() == number);
+                let parent = parent.unwrap();
+                let x = parent.internal_params()["x"];
+                let values = parent.values().unwrap();
+                assert!((-0.5..=2.5).contains(&x), "{:?} {}", crossover, x);
+                assert!(values[0] + values[1] < 6.0, "{:?} {:?}", crossover, values);
+            }
+        }
+    }
+
+    #[test]
+    fn non_finite_values_are_left_out_of_the_parents() {
+        struct PartlyNonFinite;
+
+        impl MultiObjective for PartlyNonFinite {
+            fn objectives(&self, trial: Trial<'_>) -> Result<Vec<f64>, TrialError> {
+                let x = trial.suggest_uniform("x", -10.0, 10.0)?;
+                if x < -5.0 {
+                    Ok(vec![f64::NAN, f64::NAN])
+                } else if x > 5.0 {
+                    Ok(vec![f64::INFINITY, x])
+                } else {
+                    Ok(vec![x * x, (x - 2.0).powi(2)])
+                }
+            }
+        }
+
+        let study = create_study()
+            .directions(&[StudyDirection::Minimize, StudyDirection::Minimize])
+            .sampler(NsgaIISampler::new(1).population_size(4))
+            .build();
+        study.optimize(PartlyNonFinite, 40);
+
+        let trials = study.get_trials(Some(&[TrialState::Completed])).unwrap();
+        assert_eq!(trials.len(), 40);
+        let is_finite = |t: &FrozenTrial| t.values().unwrap().iter().all(|v| v.is_finite());
+        assert!(!trials.iter().all(is_finite));
+        for generation in 1..10 {
+            for number in recorded_parents(&study, generation) {
+                let parent = trials.iter().find(|t| t.number() == number);
+                assert!(is_finite(parent.unwrap()));
+            }
+        }
+        assert!(study.best_trials().unwrap().iter().all(is_finite));
+    }
+
+    #[test]
+    fn brackets_of_hyperband_have_their_own_parents() {
+        let pruner = HyperbandPruner::new(1, 9);
+        let study = create_study()
+            .directions(&[StudyDirection::Minimize, StudyDirection::Minimize])
+            .sampler(NsgaIISampler::new(1).population_size(4))
+            .pruner(HyperbandPruner::new(1, 9))
+            .build();
+        study.optimize(Schaffer, 80);
+
+        let mut n_recorded = 0;
+        for trial in study.get_trials(None).unwrap() {
+            let bracket = pruner.bracket_of(trial.number());
+            for (key, numbers) in trial.system_attrs() {
+                if !key.starts_with("nsga2:parent_population:") {
+                    continue;
+                }
+                n_recorded += 1;
+                for number in numbers.split_whitespace() {
+                    assert_eq!(pruner.bracket_of(number.parse().unwrap()), bracket);
+                }
+            }
+        }
+        assert!(n_recorded > 0);
+    }
+}
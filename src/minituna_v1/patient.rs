@@ -0,0 +1,143 @@
+//! Pruner which tolerates a trial that stops improving for a while.
+
+use super::{FrozenTrial, Pruner, Study, StudyDirection};
+
+/// Waits until the intermediate values of a trial have not improved on the
+/// best value before the last `patience + 1` steps by more than `min_delta`,
+/// then prunes the trial, or lets the wrapped pruner decide if there is one.
+pub struct PatientPruner {
+    wrapped: Option<Box<dyn Pruner>>,
+    patience: usize,
+    min_delta: f64,
+}
+
+impl PatientPruner {
+    pub fn new(patience: usize) -> Self {
+        PatientPruner {
+            wrapped: None,
+            patience,
+            min_delta: 0.0,
+        }
+    }
+
+    pub fn wrapped<P: Pruner + 'static>(mut self, pruner: P) -> Self {
+        self.wrapped = Some(Box::new(pruner));
+        self
+    }
+
+    pub fn min_delta(mut self, min_delta: f64) -> Self {
+        self.min_delta = min_delta.max(0.0);
+        self
+    }
+}
+
+impl Pruner for PatientPruner {
+    fn prune(&mut self, study: &Study, trial: &FrozenTrial) -> bool {
+        let values: Vec<f64> = trial.intermediate_values().values().copied().collect();
+        if values.len() <= self.patience + 1 {
+            return false;
+        }
+        let (before, recent) = values.split_at(values.len() - self.patience - 1);
+        let best = |values: &[f64], direction: StudyDirection| {
+            let values = values.iter().copied().filter(|v| !v.is_nan());
+            match direction {
+                StudyDirection::Minimize => values.fold(f64::INFINITY, f64::min),
+                StudyDirection::Maximize => values.fold(f64::NEG_INFINITY, f64::max),
+            }
+        };
+        let direction = study.direction();
+        let (best_before, best_recent) = (best(before, direction), best(recent, direction));
+        let stalled = match direction {
+            StudyDirection::Minimize => best_recent > best_before - self.min_delta,
+            StudyDirection::Maximize => best_recent < best_before + self.min_delta,
+        };
+        if !stalled {
+            return false;
+        }
+        match self.wrapped.as_mut() {
+            Some(pruner) => pruner.prune(study, trial),
+            None => true,
+        }
+    }
+
+    fn filter_trials(&self, trial_number: u32, trials: Vec<FrozenTrial>) -> Vec<FrozenTrial> {
+        match self.wrapped.as_ref() {
+            Some(pruner) => pruner.filter_trials(trial_number, trials),
+            None => trials,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::threshold::ThresholdPruner;
+    use crate::minituna_v1::{create_study, InMemoryStorage, Storage};
+
+    fn prune(mut pruner: PatientPruner, direction: StudyDirection, values: &[f64]) -> bool {
+        let mut storage = InMemoryStorage::new();
+        let study_id = storage.create_new_study("study").unwrap();
+        let trial_id = storage.create_new_trial(study_id, None).unwrap();
+        for (step, &value) in values.iter().enumerate() {
+            storage
+                .set_trial_intermediate_value(trial_id, step as u64, value)
+                .unwrap();
+        }
+        let trial = storage.get_trial(trial_id).unwrap().unwrap();
+        let study = create_study()
+            .study_name("study")
+            .direction(direction)
+            .storage(storage)
+            .build();
+        pruner.prune(&study, &trial)
+    }
+
+    #[test]
+    fn patient_pruner_waits_for_patience_steps() {
+        let minimize = StudyDirection::Minimize;
+        assert!(!prune(PatientPruner::new(2), minimize, &[1.0, 2.0, 3.0]));
+        assert!(prune(
+            PatientPruner::new(2),
+            minimize,
+            &[1.0, 2.0, 3.0, 4.0]
+        ));
+        assert!(!prune(
+            PatientPruner::new(2),
+            minimize,
+            &[1.0, 2.0, 3.0, 0.5]
+        ));
+        let maximize = StudyDirection::Maximize;
+        assert!(!prune(PatientPruner::new(1), maximize, &[1.0, 2.0, 3.0]));
+        assert!(prune(PatientPruner::new(1), maximize, &[3.0, 2.0, 1.0]));
+    }
+
+    #[test]
+    fn patient_pruner_honors_min_delta_and_wrapped_pruner() {
+        let minimize = StudyDirection::Minimize;
+        let values = [1.0, 0.98, 0.97];
+        assert!(!prune(PatientPruner::new(1), minimize, &values));
+        assert!(prune(
+            PatientPruner::new(1).min_delta(0.1),
+            minimize,
+            &values
+        ));
+        assert!(!prune(
+            PatientPruner::new(1).min_delta(0.1),
+            minimize,
+            &[1.0, 0.8, 0.85]
+        ));
+        let maximize = StudyDirection::Maximize;
+        let values = [1.0, 1.02, 1.03];
+        assert!(!prune(PatientPruner::new(1), maximize, &values));
+        assert!(prune(
+            PatientPruner::new(1).min_delta(0.1),
+            maximize,
+            &values
+        ));
+
+        let values = [1.0, 1.05, 1.02];
+        let wrapped = || PatientPruner::new(1).wrapped(ThresholdPruner::new().upper(2.0));
+        assert!(!prune(wrapped(), minimize, &values));
+        assert!(prune(wrapped(), minimize, &[1.0, 3.0, 3.0]));
+    }
+}
@@ -0,0 +1,243 @@
+//! Pruners which compare a trial with the other trials at the same step.
+
+use super::{FrozenTrial, Pruner, Study, StudyDirection, TrialState};
+
+/// Prunes a trial whose best intermediate value so far is worse than the
+/// given percentile of the intermediate values completed trials reported at
+/// the same step.
+///
+/// Nothing is pruned until `n_startup_trials` trials have completed, nor
+/// before step `n_warmup_steps`. After that, a trial is judged once every
+/// `interval_steps` steps, counted from `n_warmup_steps`. A trial whose
+/// intermediate values are all NaN is always pruned.
+pub struct PercentilePruner {
+    percentile: f64,
+    n_startup_trials: usize,
+    n_warmup_steps: u64,
+    interval_steps: u64,
+}
+
+impl PercentilePruner {
+    /// `percentile` is between 0 and 100. Smaller values prune more trials.
+    pub fn new(percentile: f64) -> Self {
+        PercentilePruner {
+            percentile: percentile.clamp(0.0, 100.0),
+            n_startup_trials: 5,
+            n_warmup_steps: 0,
+            interval_steps: 1,
+        }
+    }
+
+    pub fn n_startup_trials(mut self, n_startup_trials: usize) -> Self {
+        self.n_startup_trials = n_startup_trials;
+        self
+    }
+
+    pub fn n_warmup_steps(mut self, n_warmup_steps: u64) -> Self {
+        self.n_warmup_steps = n_warmup_steps;
+        self
+    }
+
+    pub fn interval_steps(mut self, interval_steps: u64) -> Self {
+        self.interval_steps = interval_steps.max(1);
+        self
+    }
+}
+
+impl Pruner for PercentilePruner {
+    fn prune(&mut self, study: &Study, trial: &FrozenTrial) -> bool {
+        let step = match trial.last_step() {
+            Some(step) => step,
+            None => return false,
+        };
+        if !is_judged_at(trial, step, self.n_warmup_steps, self.interval_steps) {
+            return false;
+        }
+        let completed = match study.get_trials(Some(&[TrialState::Completed])) {
+            Ok(completed) if completed.len() >= self.n_startup_trials => completed,
+            _ => return false,
+        };
+
+        let direction = study.direction();
+        let best = match best_intermediate_value(trial, direction) {
+            Some(best) => best,
+            None => return true,
+        };
+        let mut values: Vec<f64> = completed
+            .iter()
+            .filter_map(|t| t.intermediate_values().get(&step).copied())
+            .filter(|v| !v.is_nan())
+            .collect();
+        if values.is_empty() {
+            return false;
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        match direction {
+            StudyDirection::Minimize => best > percentile(&values, self.percentile),
+            StudyDirection::Maximize => best < percentile(&values, 100.0 - self.percentile),
+        }
+    }
+}
+
+/// Prunes a trial whose best intermediate value so far is worse than the
+/// median of the intermediate values completed trials reported at the same
+/// step, i.e. a `PercentilePruner` at the 50th percentile.
+pub struct MedianPruner(PercentilePruner);
+
+impl MedianPruner {
+    pub fn new() -> Self {
+        MedianPruner(PercentilePruner::new(50.0))
+    }
+
+    pub fn n_startup_trials(self, n_startup_trials: usize) -> Self {
+        MedianPruner(self.0.n_startup_trials(n_startup_trials))
+    }
+
+    pub fn n_warmup_steps(self, n_warmup_steps: u64) -> Self {
+        MedianPruner(self.0.n_warmup_steps(n_warmup_steps))
+    }
+
+    pub fn interval_steps(self, interval_steps: u64) -> Self {
+        MedianPruner(self.0.interval_steps(interval_steps))
+    }
+}
+
+impl Default for MedianPruner {
+    fn default() -> Self {
+        MedianPruner::new()
+    }
+}
+
+impl Pruner for MedianPruner {
+    fn prune(&mut self, study: &Study, trial: &FrozenTrial) -> bool {
+        self.0.prune(study, trial)
+    }
+}
+
+/// Whether a pruner which waits for `n_warmup_steps` and then judges trials
+/// every `interval_steps` judges `trial` at its last reported `step`, i.e.
+/// whether a judging step lies after the previously reported step and at or
+/// before `step`.
+pub(crate) fn is_judged_at(
+    trial: &FrozenTrial,
+    step: u64,
+    n_warmup_steps: u64,
+    interval_steps: u64,
+) -> bool {
+    if step < n_warmup_steps {
+        return false;
+    }
+    let previous = trial.intermediate_values().range(..step).next_back();
+    let boundary = match previous {
+        Some((&previous, _)) if previous >= n_warmup_steps => {
+            let k = (previous - n_warmup_steps) / interval_steps + 1;
+            n_warmup_steps + k * interval_steps
+        }
+        _ => n_warmup_steps,
+    };
+    boundary <= step
+}
+
+/// The best of the intermediate values of `trial` under `direction`, ignoring
+/// NaN. `None` when every value is NaN.
+fn best_intermediate_value(trial: &FrozenTrial, direction: StudyDirection) -> Option<f64> {
+    let values = trial
+        .intermediate_values()
+        .values()
+        .copied()
+        .filter(|v| !v.is_nan());
+    match direction {
+        StudyDirection::Minimize => values.fold(None, |best, v| Some(best.map_or(v, |b| v.min(b)))),
+        StudyDirection::Maximize => values.fold(None, |best, v| Some(best.map_or(v, |b| v.max(b)))),
+    }
+}
+
+/// The `q`-th percentile of sorted `values`, interpolating linearly between
+/// the closest ranks.
+fn percentile(values: &[f64], q: f64) -> f64 {
+    let rank = q / 100.0 * (values.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    values[low] + (values[high] - values[low]) * (rank - low as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::{create_study, InMemoryStorage, Storage};
+
+    /// A study whose completed trial `i` reported `i` at steps 0 to 4, and
+    /// a running trial which reported `values` from step 0.
+    fn study_with_running_trial(direction: StudyDirection, values: &[f64]) -> (Study, u32) {
+        let mut storage = InMemoryStorage::new();
+        let study_id = storage.create_new_study("study").unwrap();
+        for i in 0..5 {
+            let trial_id = storage.create_new_trial(study_id, None).unwrap();
+            for step in 0..5 {
+                storage
+                    .set_trial_intermediate_value(trial_id, step, i as f64)
+                    .unwrap();
+            }
+            storage.set_trial_value(trial_id, i as f64).unwrap();
+            storage
+                .set_trial_state(trial_id, TrialState::Completed)
+                .unwrap();
+        }
+        let trial_id = storage.create_new_trial(study_id, None).unwrap();
+        for (step, &value) in values.iter().enumerate() {
+            storage
+                .set_trial_intermediate_value(trial_id, step as u64, value)
+                .unwrap();
+        }
+        let study = create_study()
+            .study_name("study")
+            .direction(direction)
+            .storage(storage)
+            .build();
+        (study, trial_id)
+    }
+
+    fn prune<P: Pruner>(mut pruner: P, direction: StudyDirection, values: &[f64]) -> bool {
+        let (study, trial_id) = study_with_running_trial(direction, values);
+        let trial = &study.get_trials(None).unwrap()[trial_id as usize];
+        pruner.prune(&study, trial)
+    }
+
+    #[test]
+    fn median_pruner_compares_with_completed_trials() {
+        let minimize = StudyDirection::Minimize;
+        let maximize = StudyDirection::Maximize;
+        assert!(prune(MedianPruner::new(), minimize, &[2.5]));
+        assert!(!prune(MedianPruner::new(), minimize, &[1.5]));
+        assert!(!prune(MedianPruner::new(), maximize, &[2.5]));
+        assert!(prune(MedianPruner::new(), maximize, &[1.5]));
+        // The best value so far counts, not the last one.
+        assert!(!prune(MedianPruner::new(), minimize, &[1.0, 3.0]));
+        assert!(prune(MedianPruner::new(), minimize, &[f64::NAN]));
+        assert!(!prune(MedianPruner::new(), minimize, &[]));
+    }
+
+    #[test]
+    fn percentile_pruner_honors_startup_warmup_and_interval() {
+        let minimize = StudyDirection::Minimize;
+        assert!(prune(PercentilePruner::new(25.0), minimize, &[1.5]));
+        assert!(!prune(PercentilePruner::new(75.0), minimize, &[2.5]));
+        let startup = PercentilePruner::new(25.0).n_startup_trials(6);
+        assert!(!prune(startup, minimize, &[1.5]));
+        let warmup = PercentilePruner::new(25.0).n_warmup_steps(2);
+        assert!(!prune(warmup, minimize, &[9.0, 9.0]));
+        let warmup = PercentilePruner::new(25.0).n_warmup_steps(2);
+        assert!(prune(warmup, minimize, &[9.0, 9.0, 9.0]));
+        let interval = PercentilePruner::new(25.0).interval_steps(2);
+        assert!(!prune(interval, minimize, &[9.0, 9.0]));
+        let interval = PercentilePruner::new(25.0).interval_steps(2);
+        assert!(prune(interval, minimize, &[9.0, 9.0, 9.0]));
+    }
+
+    #[test]
+    fn percentile_interpolates_between_ranks() {
+        let values = [0.0, 1.0, 2.0, 4.0];
+        assert_eq!(percentile(&values, 0.0), 0.0);
+        assert_eq!(percentile(&values, 50.0), 1.5);
+        assert_eq!(percentile(&values, 100.0), 4.0);
+    }
+}
@@ -0,0 +1,451 @@
+//! Quasi-Monte Carlo sampler.
+//!
+//! Low-discrepancy sequences cover the search space more evenly than uniform
+//! random points, which matters for small trial budgets. The search space of
+//! the first completed trial is mapped onto the unit hypercube and the `n`-th
+//! trial takes the `n`-th point of the sequence. The index of the last point is
+//! kept in the study's system attributes, so a resumed study carries on with
+//! the sequence rather than starting over, and workers sharing a storage take
+//! distinct points.
+
+use super::transform::{transformed_bounds, untransform};
+use super::{Distribution, FrozenTrial, RandomSampler, Sampler, Study, TrialError, TrialState};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum QmcType {
+    Sobol,
+    Halton,
+}
+
+/// Degree, coefficients of the primitive polynomial and initial direction
+/// numbers of the Sobol sequence for dimensions 2 to 21 (Joe and Kuo, 2008).
+const SOBOL_DIRECTIONS: [(u32, u32, &[u32]); 20] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+    (6, 19, &[1, 1, 1, 15, 7, 5]),
+    (6, 22, &[1, 3, 1, 15, 13, 25]),
+    (6, 25, &[1, 1, 5, 5, 19, 61]),
+    (7, 1, &[1, 3, 7, 11, 23, 15, 103]),
+    (7, 4, &[1, 3, 7, 13, 13, 15, 69]),
+];
+
+/// Highest dimension the Sobol sequence is available for.
+pub const SOBOL_MAX_DIM: usize = SOBOL_DIRECTIONS.len() + 1;
+
+const SOBOL_BITS: usize = 32;
+
+/// Samples the search space of the first completed trial with a Sobol or Halton
+/// sequence. Parameters outside that search space, and every parameter of the
+/// first trial, are sampled by an independent sampler. Sobol sequences are
+/// limited to `SOBOL_MAX_DIM` parameters; larger search spaces are sampled
+/// independently as well.
+///
+/// Scrambling randomizes the sequence while keeping its uniformity: Sobol
+/// points get a random digital shift and Halton points a random permutation of
+/// the digits of every base. The scrambling is seeded by the study's seed when
+/// one is given to `StudyBuilder::seed`, and by the seed the sampler is made
+/// with otherwise. The latter also seeds the default independent sampler.
+pub struct QmcSampler {
+    qmc_type: QmcType,
+    scramble: bool,
+    seed: u64,
+    independent_sampler: Box<dyn Sampler>,
+}
+
+impl QmcSampler {
+    pub fn new(qmc_type: QmcType, seed: u64) -> Self {
+        QmcSampler {
+            qmc_type,
+            scramble: false,
+            seed,
+            independent_sampler: Box::new(RandomSampler::new(seed)),
+        }
+    }
+
+    pub fn scramble(mut self, scramble: bool) -> Self {
+        self.scramble = scramble;
+        self
+    }
+
+    pub fn independent_sampler<S: Sampler + 'static>(mut self, sampler: S) -> Self {
+        self.independent_sampler = Box::new(sampler);
+        self
+    }
+
+    fn scramble_seed(&self, study: &Study) -> u64 {
+        study.seed().unwrap_or(self.seed)
+    }
+
+    /// Key of the study system attribute holding the index of the last point,
+    /// distinct per sequence so that differently configured samplers do not
+    /// share it.
+    fn sample_id_key(&self, scramble_seed: u64) -> String {
+        let scramble = if self.scramble {
+            format!("scrambled:{}", scramble_seed)
+        } else {
+            "unscrambled".to_string()
+        };
+        format!("qmc:{:?}:{}:sample_id", self.qmc_type, scramble)
+    }
+
+    fn next_sample_id(&self, study: &Study, scramble_seed: u64) -> Result<u64, TrialError> {
+        let key = self.sample_id_key(scramble_seed);
+        loop {
+            let last = study.system_attrs()?.remove(&key);
+            let sample_id = last
+                .as_ref()
+                .and_then(|id| id.parse::<u64>().ok())
+                .map_or(0, |id| id + 1);
+            // Another worker may take the same index first.
+            if study.compare_and_set_system_attr(&key, last.as_deref(), &sample_id.to_string())? {
+                return Ok(sample_id);
+            }
+        }
+    }
+
+    fn point(&self, sample_id: u64, dim: usize, scramble_seed: u64) -> Vec<f64> {
+        let mut rng: StdRng = SeedableRng::seed_from_u64(scramble_seed);
+        match self.qmc_type {
+            QmcType::Sobol => (0..dim)
+                .map(|d| {
+                    let shift = if self.scramble { rng.gen::<u32>() } else { 0 };
+                    let bits = sobol(sample_id, d) ^ shift;
+                    f64::from(bits) / (1u64 << SOBOL_BITS) as f64
+                })
+                .collect(),
+            QmcType::Halton => primes(dim)
+                .into_iter()
+                .map(|base| {
+                    let mut permutation: Vec<u64> = (0..base).collect();
+                    if self.scramble {
+                        permutation[1..].shuffle(&mut rng);
+                    }
+                    radical_inverse(sample_id, base, &permutation)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Sampler for QmcSampler {
+    fn infer_relative_search_space(
+        &mut self,
+        study: &Study,
+        _trial: &FrozenTrial,
+    ) -> HashMap<String, Distribution> {
+        let trials = study
+            .get_trials(Some(&[TrialState::Completed]))
+            .unwrap_or_default();
+        let first_trial = match trials.iter().min_by_key(|t| t.trial_id()) {
+            Some(trial) => trial,
+            None => return HashMap::new(),
+        };
+        let mut search_space = first_trial.distributions().clone();
+        search_space.retain(|_, d| !d.single());
+        if self.qmc_type == QmcType::Sobol && search_space.len() > SOBOL_MAX_DIM {
+            return HashMap::new();
+        }
+        search_space
+    }
+
+    fn sample_relative(
+        &mut self,
+        study: &Study,
+        _trial: &FrozenTrial,
+        search_space: &HashMap<String, Distribution>,
+    ) -> HashMap<String, f64> {
+        if search_space.is_empty() {
+            return HashMap::new();
+        }
+        let mut search_space: Vec<(&String, &Distribution)> = search_space.iter().collect();
+        search_space.sort_by(|a, b| a.0.cmp(b.0));
+
+        let scramble_seed = self.scramble_seed(study);
+        let sample_id = match self.next_sample_id(study, scramble_seed) {
+            Ok(sample_id) => sample_id,
+            Err(_) => return HashMap::new(),
+        };
+        let point = self.point(sample_id, search_space.len(), scramble_seed);
+        search_space
+            .into_iter()
+            .zip(point)
+            .map(|((name, d), u)| (name.clone(), from_unit(d, u)))
+            .collect()
+    }
+
+    fn sample_independent(
+        &mut self,
+        study: &Study,
+        trial: &FrozenTrial,
+        name: &str,
+        distribution: &Distribution,
+    ) -> f64 {
+        self.independent_sampler
+            .sample_independent(study, trial, name, distribution)
+    }
+}
+
+/// Maps a coordinate in `[0, 1)` to the internal representation of a parameter.
+fn from_unit(distribution: &Distribution, u: f64) -> f64 {
+    match distribution {
+        Distribution::Categorical { choices } => {
+            ((u * choices.len() as f64).floor()).min(choices.len() as f64 - 1.0)
+        }
+        _ => {
+            let (low, high, _) = transformed_bounds(distribution);
+            untransform(distribution, low + u * (high - low))
+        }
+    }
+}
+
+/// The `index`-th point of the `dim`-th Sobol sequence, as a 32 bit fraction.
+fn sobol(index: u64, dim: usize) -> u32 {
+    let mut directions = [0u32; SOBOL_BITS];
+    if dim == 0 {
+        for (k, v) in directions.iter_mut().enumerate() {
+            *v = 1 << (SOBOL_BITS - 1 - k);
+        }
+    } else {
+        let (s, a, m) = SOBOL_DIRECTIONS[dim - 1];
+        let s = s as usize;
+        for k in 0..SOBOL_BITS {
+            directions[k] = if k < s {
+                m[k] << (SOBOL_BITS - 1 - k)
+            } else {
+                let mut v = directions[k - s] ^ (directions[k - s] >> s);
+                for l in 1..s {
+                    if (a >> (s - 1 - l)) & 1 == 1 {
+                        v ^= directions[k - l];
+                    }
+                }
+                v
+            };
+        }
+    }
+
+    let mut bits = 0;
+    for (k, v) in directions.iter().enumerate() {
+        if (index >> k) & 1 == 1 {
+            bits ^= v;
+        }
+    }
+    bits
+}
+
+/// Radical inverse of `index` in `base`, mapping every digit through
+/// `permutation`.
+fn radical_inverse(mut index: u64, base: u64, permutation: &[u64]) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut factor = inv_base;
+    let mut value = 0.0;
+    while index > 0 {
+        value += permutation[(index % base) as usize] as f64 * factor;
+        index /= base;
+        factor *= inv_base;
+    }
+    value
+}
+
+fn primes(n: usize) -> Vec<u64> {
+    let mut primes = Vec::with_capacity(n);
+    let mut candidate = 2;
+    while primes.len() < n {
+        if primes.iter().all(|p| candidate % p != 0) {
+            primes.push(candidate);
+        }
+        candidate += 1;
+    }
+    primes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::sqlite::SqliteStorage;
+    use crate::minituna_v1::{create_study, Objective, ParamValue, Storage, Trial, TrialError};
+    use std::collections::HashSet;
+    use std::fs;
+    use std::thread;
+
+    struct Model;
+
+    impl Objective for Model {
+        fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+            let x = trial.suggest_uniform("x", 0.0, 1.0)?;
+            let y = trial.suggest_loguniform("y", 1e-3, 1.0)?;
+            let n = trial.suggest_int("n", 0, 3, 1, false)?;
+            let c = trial.suggest_categorical("c", &["a", "b", "c"])?;
+            Ok(x + y + n as f64 + c.len() as f64)
+        }
+    }
+
+    #[test]
+    fn sobol_matches_the_reference_sequence() {
+        let first: Vec<f64> = (0..8)
+            .map(|i| f64::from(sobol(i, 0)) / 4294967296.0)
+            .collect();
+        assert_eq!(
+            first,
+            vec![0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875]
+        );
+        let second: Vec<f64> = (0..4)
+            .map(|i| f64::from(sobol(i, 1)) / 4294967296.0)
+            .collect();
+        assert_eq!(second, vec![0.0, 0.5, 0.75, 0.25]);
+    }
+
+    #[test]
+    fn halton_is_the_radical_inverse() {
+        let sampler = QmcSampler::new(QmcType::Halton, 0);
+        let point = sampler.point(5, 2, 0);
+        assert_eq!(point[0], 0.625);
+        assert!((point[1] - (2.0 / 3.0 + 1.0 / 9.0)).abs() < 1e-12);
+        let scrambled = QmcSampler::new(QmcType::Halton, 0).scramble(true);
+        let points: Vec<Vec<f64>> = (0..27).map(|i| scrambled.point(i, 2, 0)).collect();
+        let mut thirds = [0; 3];
+        for p in points.iter() {
+            thirds[(p[1] * 3.0) as usize] += 1;
+        }
+        assert_eq!(thirds, [9, 9, 9]);
+    }
+
+    #[test]
+    fn points_cover_every_stratum() {
+        for &qmc_type in [QmcType::Sobol, QmcType::Halton].iter() {
+            let sampler = QmcSampler::new(qmc_type, 3).scramble(true);
+            let n = if qmc_type == QmcType::Sobol { 16 } else { 8 };
+            let mut strata = vec![0; n];
+            for i in 0..n as u64 {
+                let u = sampler.point(i, 1, 3)[0];
+                strata[(u * n as f64) as usize] += 1;
+            }
+            assert!(
+                strata.iter().all(|&c| c == 1),
+                "{:?} {:?}",
+                qmc_type,
+                strata
+            );
+        }
+    }
+
+    #[test]
+    fn sequence_continues_when_the_study_is_resumed() {
+        let study = create_study()
+            .sampler(QmcSampler::new(QmcType::Sobol, 1).scramble(true))
+            .build();
+        study.optimize(Model, 5);
+        let key = QmcSampler::new(QmcType::Sobol, 1)
+            .scramble(true)
+            .sample_id_key(1);
+        assert_eq!(study.system_attrs().unwrap()[&key], "3");
+
+        let resumed = create_study()
+            .study_name(study.study_name())
+            .shared_storage(study.storage())
+            .sampler(QmcSampler::new(QmcType::Sobol, 1).scramble(true))
+            .build();
+        resumed.optimize(Model, 2);
+        assert_eq!(resumed.system_attrs().unwrap()[&key], "5");
+
+        for trial in resumed.get_trials(None).unwrap() {
+            for (name, value) in trial.internal_params() {
+                assert!(trial.distributions()[name].contains(*value));
+            }
+            assert!(matches!(trial.params()["n"], ParamValue::Int(_)));
+        }
+    }
+
+    #[test]
+    fn samplers_with_the_same_seed_scramble_alike() {
+        let scrambled_points = |qmc_type, seed| {
+            let study = create_study()
+                .sampler(QmcSampler::new(qmc_type, seed).scramble(true))
+                .build();
+            study.optimize(Model, 6);
+            let trials = study.get_trials(None).unwrap();
+            let params: Vec<_> = trials[1..].iter().map(|t| t.params().clone()).collect();
+            params
+        };
+        for &qmc_type in [QmcType::Sobol, QmcType::Halton].iter() {
+            let points = scrambled_points(qmc_type, 4);
+            assert_eq!(points, scrambled_points(qmc_type, 4));
+            assert_ne!(points, scrambled_points(qmc_type, 5));
+        }
+    }
+
+    #[test]
+    fn studies_with_the_same_seed_scramble_alike() {
+        let scrambled_points = |study_seed, sampler_seed| {
+            let study = create_study()
+                .seed(study_seed)
+                .sampler(QmcSampler::new(QmcType::Sobol, sampler_seed).scramble(true))
+                .build();
+            study.optimize(Model, 6);
+            let trials = study.get_trials(None).unwrap();
+            let params: Vec<_> = trials[1..].iter().map(|t| t.params().clone()).collect();
+            params
+        };
+        let points = scrambled_points(4, 0);
+        assert_eq!(points, scrambled_points(4, 1));
+        assert_ne!(points, scrambled_points(5, 0));
+    }
+
+    #[test]
+    fn workers_sharing_a_storage_take_distinct_points() {
+        let path = std::env::temp_dir().join(format!("minituna-qmc-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        SqliteStorage::new(&path)
+            .unwrap()
+            .create_new_study("qmc")
+            .unwrap();
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let study = create_study()
+                        .study_name("qmc")
+                        .storage(SqliteStorage::new(&path).unwrap())
+                        .sampler(QmcSampler::new(QmcType::Sobol, 2).scramble(true))
+                        .build();
+                    study.optimize(Model, 5);
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let storage = SqliteStorage::new(&path).unwrap();
+        let study_id = storage.get_study_id_from_name("qmc").unwrap().unwrap();
+        let trials = storage.get_all_trials(study_id, None).unwrap();
+        assert_eq!(trials.len(), 20);
+        let xs: HashSet<u64> = trials
+            .iter()
+            .map(|t| t.internal_params()["x"].to_bits())
+            .collect();
+        assert_eq!(xs.len(), 20);
+        for suffix in ["", "-wal", "-shm"].iter() {
+            let mut file = path.as_os_str().to_os_string();
+            file.push(suffix);
+            let _ = fs::remove_file(file);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Callback which runs trials again after the study fails them.
+
+use super::{FailedTrialCallback, FrozenTrial, Study, TrialState};
+
+const FAILED_TRIAL_ATTR: &str = "failed_trial";
+const RETRY_HISTORY_ATTR: &str = "retry_history";
+
+/// Enqueues a failed trial again as a waiting trial with the same params and
+/// user attributes, which `optimize` runs before sampling new trials.
+///
+/// The retry records the number of the trial that failed first as the system
+/// attribute `failed_trial`, and the numbers of every failed attempt as
+/// `retry_history`. A trial is not retried once it failed `max_retry` retries.
+pub struct RetryFailedTrialCallback {
+    max_retry: Option<usize>,
+}
+
+impl RetryFailedTrialCallback {
+    pub fn new() -> Self {
+        RetryFailedTrialCallback { max_retry: None }
+    }
+
+    pub fn max_retry(mut self, max_retry: usize) -> Self {
+        self.max_retry = Some(max_retry);
+        self
+    }
+
+    /// Number of the trial that `trial` retries, if it is a retry.
+    pub fn retried_trial_number(trial: &FrozenTrial) -> Option<u32> {
+        trial.system_attrs().get(FAILED_TRIAL_ATTR)?.parse().ok()
+    }
+
+    /// Numbers of the failed attempts before `trial`, oldest first.
+    pub fn retry_history(trial: &FrozenTrial) -> Vec<u32> {
+        trial
+            .system_attrs()
+            .get(RETRY_HISTORY_ATTR)
+            .map(|history| {
+                history
+                    .split_whitespace()
+                    .filter_map(|number| number.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Default for RetryFailedTrialCallback {
+    fn default() -> Self {
+        RetryFailedTrialCallback::new()
+    }
+}
+
+impl FailedTrialCallback for RetryFailedTrialCallback {
+    fn on_failed_trial(&self, study: &Study, trial: &FrozenTrial) {
+        let mut history = RetryFailedTrialCallback::retry_history(trial);
+        if self
+            .max_retry
+            .is_some_and(|max_retry| history.len() >= max_retry)
+        {
+            return;
+        }
+        history.push(trial.number());
+        let history: Vec<String> = history.iter().map(|number| number.to_string()).collect();
+        let failed_trial =
+            RetryFailedTrialCallback::retried_trial_number(trial).unwrap_or(trial.number());
+
+        let mut retry = FrozenTrial::new(0, TrialState::Waiting);
+        retry.params = trial.params.clone();
+        retry.distributions = trial.distributions.clone();
+        retry.user_attrs = trial.user_attrs.clone();
+        retry
+            .system_attrs
+            .insert(FAILED_TRIAL_ATTR.to_string(), failed_trial.to_string());
+        retry
+            .system_attrs
+            .insert(RETRY_HISTORY_ATTR.to_string(), history.join(" "));
+        let enqueued = study
+            .storage
+            .borrow_mut()
+            .create_new_trial(study.study_id(), Some(&retry));
+        if let Err(err) = enqueued {
+            eprintln!(
+                "cannot retry trial_id={}: {}",
+                trial.trial_id(),
+                err.message()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::{create_study, unix_time, Objective, Trial, TrialError};
+    use std::time::Duration;
+
+    struct Quadratic;
+
+    impl Objective for Quadratic {
+        fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+            let x = trial.suggest_uniform("x", -10.0, 10.0)?;
+            Ok(x * x)
+        }
+    }
+
+    /// Makes the running trials of the study look abandoned a minute ago.
+    fn abandon_running_trials(study: &Study) {
+        let heartbeat = (unix_time() - 60.0).to_string();
+        for trial in study.get_trials(Some(&[TrialState::Running])).unwrap() {
+            study
+                .set_trial_system_attr(trial.trial_id(), "heartbeat", &heartbeat)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn failed_trials_are_retried_with_their_params() {
+        let study = create_study()
+            .heartbeat_interval(Duration::from_secs(1))
+            .failed_trial_callback(RetryFailedTrialCallback::new().max_retry(1))
+            .build();
+        let trial_id = study
+            .storage
+            .borrow_mut()
+            .create_new_trial(study.study_id(), None)
+            .unwrap();
+        Trial::new(trial_id, &study)
+            .suggest_uniform("x", 1.0, 2.0)
+            .unwrap();
+        let x = study.get_trials(None).unwrap()[0].params()["x"].clone();
+
+        abandon_running_trials(&study);
+        assert_eq!(study.fail_stale_trials().unwrap(), vec![trial_id]);
+        let retry = &study.get_trials(None).unwrap()[1];
+        assert_eq!(retry.state(), TrialState::Waiting);
+        assert_eq!(
+            RetryFailedTrialCallback::retried_trial_number(retry),
+            Some(0)
+        );
+        assert_eq!(RetryFailedTrialCallback::retry_history(retry), vec![0]);
+
+        // The retry runs before new trials are sampled.
+        study.optimize(Quadratic, 1);
+        let retry = study.get_trials(None).unwrap()[1].clone();
+        assert_eq!(retry.state(), TrialState::Completed);
+        assert_eq!(retry.params()["x"], x);
+        assert_eq!(study.get_trials(None).unwrap().len(), 2);
+
+        // A retry which fails is not retried again after max_retry retries.
+        let mut template = retry;
+        template.state = TrialState::Running;
+        let retry_id = study
+            .storage
+            .borrow_mut()
+            .create_new_trial(study.study_id(), Some(&template))
+            .unwrap();
+        abandon_running_trials(&study);
+        assert_eq!(study.fail_stale_trials().unwrap(), vec![retry_id]);
+        assert_eq!(study.get_trials(None).unwrap().len(), 3);
+    }
+}
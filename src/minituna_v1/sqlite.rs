@@ -0,0 +1,917 @@
+//! Storage in a SQLite database file, so that studies survive the process,
+//! several processes can share them, and they can be inspected afterwards,
+//! e.g. with the `sqlite3` shell.
+
+use super::json::{
+    direction_from_name, direction_name, distribution_from_json, distribution_to_json,
+    state_from_name, state_name, Json,
+};
+use super::{Distribution, FrozenTrial, Storage, StudyDirection, TrialError, TrialState};
+use ordered_float::OrderedFloat;
+use rusqlite::{params, Connection, OptionalExtension, Row, TransactionBehavior};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Version of the tables below, kept as the `user_version` of the database. A
+/// database of another version is refused rather than misread.
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE studies (
+    study_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    study_name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE study_directions (
+    study_id INTEGER NOT NULL REFERENCES studies ON DELETE CASCADE,
+    objective INTEGER NOT NULL,
+    direction TEXT NOT NULL,
+    PRIMARY KEY (study_id, objective)
+);
+CREATE TABLE study_user_attrs (
+    study_id INTEGER NOT NULL REFERENCES studies ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (study_id, key)
+);
+CREATE TABLE study_system_attrs (
+    study_id INTEGER NOT NULL REFERENCES studies ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (study_id, key)
+);
+CREATE TABLE trials (
+    trial_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    study_id INTEGER NOT NULL REFERENCES studies ON DELETE CASCADE,
+    number INTEGER NOT NULL,
+    state TEXT NOT NULL,
+    UNIQUE (study_id, number)
+);
+CREATE TABLE trial_values (
+    trial_id INTEGER NOT NULL REFERENCES trials ON DELETE CASCADE,
+    objective INTEGER NOT NULL,
+    value REAL,
+    PRIMARY KEY (trial_id, objective)
+);
+CREATE TABLE trial_intermediate_values (
+    trial_id INTEGER NOT NULL REFERENCES trials ON DELETE CASCADE,
+    step INTEGER NOT NULL,
+    value REAL,
+    PRIMARY KEY (trial_id, step)
+);
+CREATE TABLE trial_params (
+    trial_id INTEGER NOT NULL REFERENCES trials ON DELETE CASCADE,
+    param_name TEXT NOT NULL,
+    param_value REAL NOT NULL,
+    distribution TEXT NOT NULL,
+    PRIMARY KEY (trial_id, param_name)
+);
+CREATE TABLE trial_user_attrs (
+    trial_id INTEGER NOT NULL REFERENCES trials ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (trial_id, key)
+);
+CREATE TABLE trial_system_attrs (
+    trial_id INTEGER NOT NULL REFERENCES trials ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (trial_id, key)
+);
+";
+
+/// How long an update waits for other processes to release the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A `Storage` kept in a SQLite database file.
+///
+/// Every update runs in a transaction which takes the write lock of the
+/// database before it checks the update against the stored state, so the
+/// processes sharing the file agree on trial ids and numbers and reject the
+/// same invalid updates. Every read sees a consistent snapshot. The database is in
+/// write-ahead log mode, so reads do not wait for updates, and a crash rolls
+/// back the update in progress.
+///
+/// Values and intermediate values which are NaN are stored as `NULL`. Ids
+/// start at 1, as SQLite assigns them.
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it with the current schema if
+    /// it has none. Fails for a database of another schema version.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, TrialError> {
+        let path = path.as_ref();
+        let mut connection = Connection::open(path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let version: i64 = transaction.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version == 0 {
+            transaction.execute_batch(SCHEMA)?;
+            transaction.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        } else if version != SCHEMA_VERSION {
+            return Err(TrialError::new(&format!(
+                "{}: schema version {} is not supported, expected {}",
+                path.display(),
+                version,
+                SCHEMA_VERSION
+            )));
+        }
+        transaction.commit()?;
+        Ok(SqliteStorage { connection })
+    }
+
+    /// Runs an update in a transaction which holds the write lock from the
+    /// start, and commits it if `f` succeeds.
+    fn write<T>(
+        &mut self,
+        f: impl FnOnce(&Connection) -> Result<T, TrialError>,
+    ) -> Result<T, TrialError> {
+        let transaction = self
+            .connection
+            .transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let value = f(&transaction)?;
+        transaction.commit()?;
+        Ok(value)
+    }
+
+    /// Runs a read in a transaction, so that it sees a single state of the
+    /// database.
+    fn read<T>(
+        &self,
+        f: impl FnOnce(&Connection) -> Result<T, TrialError>,
+    ) -> Result<T, TrialError> {
+        let transaction = self.connection.unchecked_transaction()?;
+        f(&transaction)
+    }
+}
+
+impl From<rusqlite::Error> for TrialError {
+    fn from(err: rusqlite::Error) -> Self {
+        TrialError::new(&format!("sqlite: {}", err))
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn create_new_study(&mut self, study_name: &str) -> Result<u32, TrialError> {
+        self.write(|db| {
+            if study_id_from_name(db, study_name)?.is_some() {
+                return Err(TrialError::new(&format!(
+                    "study {} already exists",
+                    study_name
+                )));
+            }
+            db.execute("INSERT INTO studies (study_name) VALUES (?1)", [study_name])?;
+            Ok(db.last_insert_rowid() as u32)
+        })
+    }
+
+    fn get_study_id_from_name(&self, study_name: &str) -> Result<Option<u32>, TrialError> {
+        self.read(|db| study_id_from_name(db, study_name))
+    }
+
+    fn get_study_name_from_id(&self, study_id: u32) -> Result<Option<String>, TrialError> {
+        self.read(|db| study_name_from_id(db, study_id))
+    }
+
+    fn get_all_study_ids(&self) -> Result<Vec<u32>, TrialError> {
+        self.read(|db| {
+            let mut statement = db.prepare("SELECT study_id FROM studies ORDER BY study_id")?;
+            let study_ids = statement
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            Ok(study_ids)
+        })
+    }
+
+    fn delete_study(&mut self, study_id: u32) -> Result<(), TrialError> {
+        self.write(|db| {
+            existing_study_name(db, study_id)?;
+            db.execute("DELETE FROM studies WHERE study_id = ?1", [study_id])?;
+            Ok(())
+        })
+    }
+
+    fn set_study_directions(
+        &mut self,
+        study_id: u32,
+        directions: &[StudyDirection],
+    ) -> Result<(), TrialError> {
+        self.write(|db| {
+            let study_name = existing_study_name(db, study_id)?;
+            let current = study_directions(db, study_id)?;
+            if !current.is_empty() && current != directions {
+                return Err(TrialError::new(&format!(
+                    "cannot change directions of study {} from {:?} to {:?}",
+                    study_name, current, directions
+                )));
+            }
+            if current.is_empty() {
+                for (objective, &direction) in directions.iter().enumerate() {
+                    db.execute(
+                        "INSERT INTO study_directions VALUES (?1, ?2, ?3)",
+                        params![study_id, objective as u32, direction_name(direction)],
+                    )?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn get_study_directions(&self, study_id: u32) -> Result<Vec<StudyDirection>, TrialError> {
+        self.read(|db| study_directions(db, study_id))
+    }
+
+    fn create_new_trial(
+        &mut self,
+        study_id: u32,
+        template: Option<&FrozenTrial>,
+    ) -> Result<u32, TrialError> {
+        self.write(|db| {
+            existing_study_name(db, study_id)?;
+            let number: u32 = db.query_row(
+                "SELECT COUNT(*) FROM trials WHERE study_id = ?1",
+                [study_id],
+                |row| row.get(0),
+            )?;
+            let state = template.map_or(TrialState::Running, |template| template.state);
+            db.execute(
+                "INSERT INTO trials (study_id, number, state) VALUES (?1, ?2, ?3)",
+                params![study_id, number, state_name(state)],
+            )?;
+            let trial_id = db.last_insert_rowid() as u32;
+            if let Some(template) = template {
+                insert_trial_contents(db, trial_id, template)?;
+            }
+            Ok(trial_id)
+        })
+    }
+
+    fn set_trial_param(
+        &mut self,
+        trial_id: u32,
+        name: &str,
+        value: f64,
+        distribution: Distribution,
+    ) -> Result<(), TrialError> {
+        self.write(|db| {
+            let (study_id, state) = trial_study_and_state(db, trial_id)?;
+            let mut previous = db.prepare(
+                "SELECT p.distribution FROM trial_params p
+                 JOIN trials t ON p.trial_id = t.trial_id
+                 WHERE t.study_id = ?1 AND p.param_name = ?2",
+            )?;
+            let mut rows = previous.query(params![study_id, name])?;
+            while let Some(row) = rows.next()? {
+                if !read_distribution(row, 0)?.is_compatible(&distribution) {
+                    return Err(TrialError::new(&format!(
+                        "distribution of param {} is incompatible with previous trials: {:?}",
+                        name, distribution
+                    )));
+                }
+            }
+            if !distribution.contains(value) {
+                return Err(TrialError::new(&format!(
+                    "param {}={} is out of {:?}",
+                    name, value, distribution
+                )));
+            }
+            check_running(trial_id, state)?;
+            insert_param(db, trial_id, name, value, &distribution)
+        })
+    }
+
+    fn set_trial_state(&mut self, trial_id: u32, state: TrialState) -> Result<(), TrialError> {
+        self.write(|db| {
+            let (_, current) = trial_study_and_state(db, trial_id)?;
+            if !current.can_transition_to(state) {
+                return Err(TrialError::new(&format!(
+                    "cannot change state of trial_id={} from {:?} to {:?}",
+                    trial_id, current, state
+                )));
+            }
+            db.execute(
+                "UPDATE trials SET state = ?1 WHERE trial_id = ?2",
+                params![state_name(state), trial_id],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn set_trial_values(&mut self, trial_id: u32, values: &[f64]) -> Result<(), TrialError> {
+        self.write(|db| {
+            let (_, state) = trial_study_and_state(db, trial_id)?;
+            check_running(trial_id, state)?;
+            db.execute("DELETE FROM trial_values WHERE trial_id = ?1", [trial_id])?;
+            insert_values(db, trial_id, values)
+        })
+    }
+
+    fn set_trial_intermediate_value(
+        &mut self,
+        trial_id: u32,
+        step: u64,
+        value: f64,
+    ) -> Result<(), TrialError> {
+        self.write(|db| {
+            let (_, state) = trial_study_and_state(db, trial_id)?;
+            check_running(trial_id, state)?;
+            insert_intermediate_value(db, trial_id, step, value)
+        })
+    }
+
+    fn set_trial_user_attr(
+        &mut self,
+        trial_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        self.write(|db| {
+            let (_, state) = trial_study_and_state(db, trial_id)?;
+            check_running(trial_id, state)?;
+            set_attr(db, "trial_user_attrs", "trial_id", trial_id, key, value)
+        })
+    }
+
+    fn set_trial_system_attr(
+        &mut self,
+        trial_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        self.write(|db| {
+            let (_, state) = trial_study_and_state(db, trial_id)?;
+            check_running(trial_id, state)?;
+            set_attr(db, "trial_system_attrs", "trial_id", trial_id, key, value)
+        })
+    }
+
+    fn set_study_user_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        self.write(|db| {
+            existing_study_name(db, study_id)?;
+            set_attr(db, "study_user_attrs", "study_id", study_id, key, value)
+        })
+    }
+
+    fn set_study_system_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        value: &str,
+    ) -> Result<(), TrialError> {
+        self.write(|db| {
+            existing_study_name(db, study_id)?;
+            set_attr(db, "study_system_attrs", "study_id", study_id, key, value)
+        })
+    }
+
+    fn compare_and_set_study_system_attr(
+        &mut self,
+        study_id: u32,
+        key: &str,
+        expected: Option<&str>,
+        value: &str,
+    ) -> Result<bool, TrialError> {
+        self.write(|db| {
+            existing_study_name(db, study_id)?;
+            let current: Option<String> = db
+                .query_row(
+                    "SELECT value FROM study_system_attrs WHERE study_id = ?1 AND key = ?2",
+                    params![study_id, key],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if current.as_deref() != expected {
+                return Ok(false);
+            }
+            set_attr(db, "study_system_attrs", "study_id", study_id, key, value)?;
+            Ok(true)
+        })
+    }
+
+    fn get_study_user_attrs(&self, study_id: u32) -> Result<HashMap<String, String>, TrialError> {
+        self.read(|db| attrs(db, "study_user_attrs", "study_id", study_id))
+    }
+
+    fn get_study_system_attrs(&self, study_id: u32) -> Result<HashMap<String, String>, TrialError> {
+        self.read(|db| attrs(db, "study_system_attrs", "study_id", study_id))
+    }
+
+    fn get_trial(&self, trial_id: u32) -> Result<Option<FrozenTrial>, TrialError> {
+        self.read(|db| Ok(load_trials(db, "t.trial_id = ?1", trial_id)?.pop()))
+    }
+
+    fn get_all_trials(
+        &self,
+        study_id: u32,
+        states: Option<&[TrialState]>,
+    ) -> Result<Vec<FrozenTrial>, TrialError> {
+        let trials = self.read(|db| load_trials(db, "t.study_id = ?1", study_id))?;
+        Ok(match states {
+            Some(states) => trials
+                .into_iter()
+                .filter(|trial| states.contains(&trial.state))
+                .collect(),
+            None => trials,
+        })
+    }
+}
+
+fn study_id_from_name(db: &Connection, study_name: &str) -> Result<Option<u32>, TrialError> {
+    Ok(db
+        .query_row(
+            "SELECT study_id FROM studies WHERE study_name = ?1",
+            [study_name],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn study_name_from_id(db: &Connection, study_id: u32) -> Result<Option<String>, TrialError> {
+    Ok(db
+        .query_row(
+            "SELECT study_name FROM studies WHERE study_id = ?1",
+            [study_id],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn existing_study_name(db: &Connection, study_id: u32) -> Result<String, TrialError> {
+    study_name_from_id(db, study_id)?
+        .ok_or_else(|| TrialError::new(&format!("study_id={} is not found", study_id)))
+}
+
+fn study_directions(db: &Connection, study_id: u32) -> Result<Vec<StudyDirection>, TrialError> {
+    let mut statement = db
+        .prepare("SELECT direction FROM study_directions WHERE study_id = ?1 ORDER BY objective")?;
+    let mut rows = statement.query([study_id])?;
+    let mut directions = Vec::new();
+    while let Some(row) = rows.next()? {
+        let direction: String = row.get(0)?;
+        directions.push(
+            direction_from_name(&direction)
+                .ok_or_else(|| invalid_column("direction", &direction))?,
+        );
+    }
+    Ok(directions)
+}
+
+fn trial_study_and_state(db: &Connection, trial_id: u32) -> Result<(u32, TrialState), TrialError> {
+    let row = db
+        .query_row(
+            "SELECT study_id, state FROM trials WHERE trial_id = ?1",
+            [trial_id],
+            |row| Ok((row.get(0)?, row.get::<_, String>(1)?)),
+        )
+        .optional()?;
+    let (study_id, state) =
+        row.ok_or_else(|| TrialError::new(&format!("trial_id={} is not found", trial_id)))?;
+    let state = state_from_name(&state).ok_or_else(|| invalid_column("state", &state))?;
+    Ok((study_id, state))
+}
+
+fn check_running(trial_id: u32, state: TrialState) -> Result<(), TrialError> {
+    if state != TrialState::Running {
+        return Err(TrialError::new(&format!(
+            "cannot update trial_id={} in state {:?}",
+            trial_id, state
+        )));
+    }
+    Ok(())
+}
+
+fn invalid_column(column: &str, value: &str) -> TrialError {
+    TrialError::new(&format!("invalid {} in the database: {}", column, value))
+}
+
+fn read_distribution(row: &Row<'_>, index: usize) -> Result<Distribution, TrialError> {
+    let text: String = row.get(index)?;
+    Json::parse(&text)
+        .and_then(|json| distribution_from_json(&json))
+        .ok_or_else(|| invalid_column("distribution", &text))
+}
+
+fn insert_trial_contents(
+    db: &Connection,
+    trial_id: u32,
+    template: &FrozenTrial,
+) -> Result<(), TrialError> {
+    if let Some(values) = template.values() {
+        insert_values(db, trial_id, &values)?;
+    }
+    for (&step, &value) in template.intermediate_values.iter() {
+        insert_intermediate_value(db, trial_id, step, value)?;
+    }
+    for (name, &value) in template.params.iter() {
+        if let Some(distribution) = template.distributions.get(name) {
+            insert_param(db, trial_id, name, value, distribution)?;
+        }
+    }
+    for (key, value) in template.user_attrs.iter() {
+        set_attr(db, "trial_user_attrs", "trial_id", trial_id, key, value)?;
+    }
+    for (key, value) in template.system_attrs.iter() {
+        set_attr(db, "trial_system_attrs", "trial_id", trial_id, key, value)?;
+    }
+    Ok(())
+}
+
+fn insert_values(db: &Connection, trial_id: u32, values: &[f64]) -> Result<(), TrialError> {
+    for (objective, &value) in values.iter().enumerate() {
+        db.execute(
+            "INSERT INTO trial_values VALUES (?1, ?2, ?3)",
+            params![trial_id, objective as u32, value],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_intermediate_value(
+    db: &Connection,
+    trial_id: u32,
+    step: u64,
+    value: f64,
+) -> Result<(), TrialError> {
+    db.execute(
+        "INSERT OR REPLACE INTO trial_intermediate_values VALUES (?1, ?2, ?3)",
+        params![trial_id, step as i64, value],
+    )?;
+    Ok(())
+}
+
+fn insert_param(
+    db: &Connection,
+    trial_id: u32,
+    name: &str,
+    value: f64,
+    distribution: &Distribution,
+) -> Result<(), TrialError> {
+    db.execute(
+        "INSERT OR REPLACE INTO trial_params VALUES (?1, ?2, ?3, ?4)",
+        params![
+            trial_id,
+            name,
+            value,
+            distribution_to_json(distribution).to_string()
+        ],
+    )?;
+    Ok(())
+}
+
+fn set_attr(
+    db: &Connection,
+    table: &str,
+    id_column: &str,
+    id: u32,
+    key: &str,
+    value: &str,
+) -> Result<(), TrialError> {
+    db.execute(
+        &format!(
+            "INSERT OR REPLACE INTO {} ({}, key, value) VALUES (?1, ?2, ?3)",
+            table, id_column
+        ),
+        params![id, key, value],
+    )?;
+    Ok(())
+}
+
+fn attrs(
+    db: &Connection,
+    table: &str,
+    id_column: &str,
+    id: u32,
+) -> Result<HashMap<String, String>, TrialError> {
+    let mut statement = db.prepare(&format!(
+        "SELECT key, value FROM {} WHERE {} = ?1",
+        table, id_column
+    ))?;
+    let attrs = statement
+        .query_map([id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(attrs)
+}
+
+/// Trials matching `condition` on the `trials` table aliased as `t`, whose
+/// `?1` is `id`, in the order they were created.
+fn load_trials(db: &Connection, condition: &str, id: u32) -> Result<Vec<FrozenTrial>, TrialError> {
+    let mut trials = Vec::new();
+    let mut index = HashMap::new();
+    let mut statement = db.prepare(&format!(
+        "SELECT t.trial_id, t.number, t.state FROM trials t WHERE {} ORDER BY t.trial_id",
+        condition
+    ))?;
+    let mut rows = statement.query([id])?;
+    while let Some(row) = rows.next()? {
+        let trial_id: u32 = row.get(0)?;
+        let state: String = row.get(2)?;
+        let state = state_from_name(&state).ok_or_else(|| invalid_column("state", &state))?;
+        index.insert(trial_id, trials.len());
+        trials.push(FrozenTrial {
+            number: row.get(1)?,
+            ..FrozenTrial::new(trial_id, state)
+        });
+    }
+
+    // Calls `f` with every row of a table of trial contents and its trial.
+    let mut for_each_row =
+        |table: &str,
+         columns: &str,
+         f: &mut dyn FnMut(&mut FrozenTrial, &Row<'_>) -> Result<(), TrialError>|
+         -> Result<(), TrialError> {
+            let mut statement = db.prepare(&format!(
+                "SELECT x.trial_id, {} FROM {} x JOIN trials t ON x.trial_id = t.trial_id
+             WHERE {} ORDER BY x.rowid",
+                columns, table, condition
+            ))?;
+            let mut rows = statement.query([id])?;
+            while let Some(row) = rows.next()? {
+                let trial_id: u32 = row.get(0)?;
+                f(&mut trials[index[&trial_id]], row)?;
+            }
+            Ok(())
+        };
+    for_each_row("trial_values", "x.objective, x.value", &mut |trial, row| {
+        let objective: usize = row.get(1)?;
+        let value: Option<f64> = row.get(2)?;
+        let values = trial.values.get_or_insert_with(Vec::new);
+        values.resize(values.len().max(objective + 1), OrderedFloat(f64::NAN));
+        values[objective] = OrderedFloat(value.unwrap_or(f64::NAN));
+        Ok(())
+    })?;
+    for_each_row(
+        "trial_intermediate_values",
+        "x.step, x.value",
+        &mut |trial, row| {
+            let step: i64 = row.get(1)?;
+            let value: Option<f64> = row.get(2)?;
+            trial
+                .intermediate_values
+                .insert(step as u64, value.unwrap_or(f64::NAN));
+            Ok(())
+        },
+    )?;
+    for_each_row(
+        "trial_params",
+        "x.param_name, x.param_value, x.distribution",
+        &mut |trial, row| {
+            let name: String = row.get(1)?;
+            trial.params.insert(name.clone(), row.get(2)?);
+            trial.distributions.insert(name, read_distribution(row, 3)?);
+            Ok(())
+        },
+    )?;
+    for_each_row("trial_user_attrs", "x.key, x.value", &mut |trial, row| {
+        trial.user_attrs.insert(row.get(1)?, row.get(2)?);
+        Ok(())
+    })?;
+    for_each_row("trial_system_attrs", "x.key, x.value", &mut |trial, row| {
+        trial.system_attrs.insert(row.get(1)?, row.get(2)?);
+        Ok(())
+    })?;
+    Ok(trials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::{create_study, get_all_study_summaries, Objective, Trial};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::thread;
+
+    /// A database path in the temporary directory that no other test uses.
+    fn database_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("minituna-{}-{}.db", name, std::process::id()));
+        remove_database(&path);
+        path
+    }
+
+    fn remove_database(path: &Path) {
+        for suffix in ["", "-wal", "-shm"].iter() {
+            let mut file = path.as_os_str().to_os_string();
+            file.push(suffix);
+            let _ = fs::remove_file(file);
+        }
+    }
+
+    struct Model;
+
+    impl Objective for Model {
+        fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+            let x = trial.suggest_uniform("x", -10.0, 10.0)?;
+            let n = trial.suggest_int("n", 1, 8, 1, false)?;
+            let activation = trial.suggest_categorical("activation", &["relu", "tanh"])?;
+            trial.report(0, f64::NAN)?;
+            trial.set_user_attr("note", "line\nbreak \"quoted\"")?;
+            let penalty = if activation == "relu" { 0.0 } else { 1.0 };
+            Ok(x * x + n as f64 + penalty)
+        }
+    }
+
+    #[test]
+    fn studies_are_read_back_by_a_new_storage() {
+        let path = database_path("reopen");
+        let study = create_study()
+            .study_name("reopen")
+            .storage(SqliteStorage::new(&path).unwrap())
+            .build();
+        study.optimize(Model, 5);
+        study.set_system_attr("key", "value");
+        assert!(!study
+            .compare_and_set_system_attr("key", None, "other")
+            .unwrap());
+        assert!(study
+            .compare_and_set_system_attr("claim", None, "value")
+            .unwrap());
+        let waiting = study
+            .storage()
+            .borrow_mut()
+            .create_waiting_trial(study.study_id())
+            .unwrap();
+
+        let reopened = SqliteStorage::new(&path).unwrap();
+        let study_id = reopened.get_study_id_from_name("reopen").unwrap().unwrap();
+        let trials = reopened.get_all_trials(study_id, None).unwrap();
+        let expected = study.get_trials(None).unwrap();
+        assert_eq!(trials.len(), 6);
+        for (trial, expected) in trials.iter().zip(expected.iter()) {
+            assert_eq!(trial.trial_id(), expected.trial_id());
+            assert_eq!(trial.number(), expected.number());
+            assert_eq!(trial.state(), expected.state());
+            assert_eq!(trial.values(), expected.values());
+            assert_eq!(trial.params(), expected.params());
+            assert_eq!(trial.distributions(), expected.distributions());
+            assert_eq!(trial.user_attrs(), expected.user_attrs());
+            assert_eq!(trial.system_attrs(), expected.system_attrs());
+            let steps = trial.intermediate_values().keys();
+            assert!(steps.eq(expected.intermediate_values().keys()));
+        }
+        assert!(trials[0].intermediate_values()[&0].is_nan());
+        let waiting = reopened.get_trial(waiting).unwrap().unwrap();
+        assert_eq!(waiting.state(), TrialState::Waiting);
+        assert_eq!(waiting.number(), 5);
+        assert_eq!(
+            reopened.get_study_system_attrs(study_id).unwrap()["key"],
+            "value"
+        );
+        assert_eq!(
+            reopened.get_study_system_attrs(study_id).unwrap()["claim"],
+            "value"
+        );
+        assert_eq!(
+            reopened.get_study_directions(study_id).unwrap(),
+            vec![StudyDirection::Minimize]
+        );
+        let best = reopened
+            .get_best_trial(study_id, StudyDirection::Minimize)
+            .unwrap();
+        assert_eq!(
+            best.map(|trial| trial.trial_id()),
+            study.best_trial().unwrap().map(|trial| trial.trial_id())
+        );
+
+        study.storage().borrow_mut().delete_study(study_id).unwrap();
+        let reopened = SqliteStorage::new(&path).unwrap();
+        assert!(reopened.get_all_study_ids().unwrap().is_empty());
+        assert!(reopened.get_trial(trials[0].trial_id()).unwrap().is_none());
+        remove_database(&path);
+    }
+
+    #[test]
+    fn invalid_updates_are_rejected() {
+        let path = database_path("invalid");
+        let mut storage = SqliteStorage::new(&path).unwrap();
+        let study_id = storage.create_new_study("study").unwrap();
+        assert!(storage.create_new_study("study").is_err());
+        assert!(storage.create_new_trial(study_id + 1, None).is_err());
+        storage
+            .set_study_directions(study_id, &[StudyDirection::Maximize])
+            .unwrap();
+        assert!(storage
+            .set_study_directions(study_id, &[StudyDirection::Minimize])
+            .is_err());
+
+        let trial_id = storage.create_new_trial(study_id, None).unwrap();
+        let uniform = Distribution::Uniform {
+            low: 0.0,
+            high: 1.0,
+        };
+        storage
+            .set_trial_param(trial_id, "x", 0.5, uniform.clone())
+            .unwrap();
+        assert!(storage
+            .set_trial_param(trial_id, "y", 2.0, uniform.clone())
+            .is_err());
+        let categorical = Distribution::Categorical {
+            choices: vec!["a".into()],
+        };
+        let other = storage.create_new_trial(study_id, None).unwrap();
+        assert!(storage
+            .set_trial_param(other, "x", 0.0, categorical)
+            .is_err());
+
+        storage
+            .set_trial_state(trial_id, TrialState::Completed)
+            .unwrap();
+        assert!(storage.set_trial_value(trial_id, 1.0).is_err());
+        assert!(storage
+            .set_trial_param(trial_id, "z", 0.5, uniform)
+            .is_err());
+        assert!(storage
+            .set_trial_state(trial_id, TrialState::Running)
+            .is_err());
+        let trial = storage.get_trial(trial_id).unwrap().unwrap();
+        assert_eq!(trial.values(), None);
+        assert_eq!(trial.params().len(), 1);
+        remove_database(&path);
+    }
+
+    #[test]
+    fn databases_of_other_schema_versions_are_refused() {
+        let path = database_path("version");
+        SqliteStorage::new(&path)
+            .unwrap()
+            .create_new_study("study")
+            .unwrap();
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", 2)
+            .unwrap();
+        let err = SqliteStorage::new(&path).err().unwrap();
+        assert!(err.message().contains("schema version 2"));
+        remove_database(&path);
+    }
+
+    #[test]
+    fn reads_of_a_damaged_database_fail() {
+        let path = database_path("damaged");
+        let mut storage = SqliteStorage::new(&path).unwrap();
+        let study_id = storage.create_new_study("study").unwrap();
+        let trial_id = storage.create_new_trial(study_id, None).unwrap();
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch("DROP TABLE trial_params")
+            .unwrap();
+
+        let err = storage.get_trial(trial_id).err().unwrap();
+        assert!(err.message().contains("trial_params"));
+        assert!(get_all_study_summaries(&storage).is_err());
+        let study = create_study()
+            .study_name("study")
+            .storage(storage)
+            .load()
+            .unwrap();
+        assert!(study.get_trials(None).is_err());
+        assert!(study.best_trial().is_err());
+        remove_database(&path);
+    }
+
+    #[test]
+    fn processes_share_a_study_through_the_database() {
+        let path = database_path("shared");
+        SqliteStorage::new(&path)
+            .unwrap()
+            .create_new_study("shared")
+            .unwrap();
+
+        let workers: Vec<_> = (0..4)
+            .map(|seed| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let study = create_study()
+                        .study_name("shared")
+                        .storage(SqliteStorage::new(&path).unwrap())
+                        .seed(seed)
+                        .build();
+                    study.optimize(Model, 10);
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let storage = SqliteStorage::new(&path).unwrap();
+        let study_id = storage.get_study_id_from_name("shared").unwrap().unwrap();
+        let trials = storage
+            .get_all_trials(study_id, Some(&[TrialState::Completed]))
+            .unwrap();
+        assert_eq!(trials.len(), 40);
+        let mut numbers: Vec<u32> = trials.iter().map(|t| t.number()).collect();
+        numbers.sort_unstable();
+        assert_eq!(numbers, (0..40).collect::<Vec<u32>>());
+        remove_database(&path);
+    }
+}
@@ -0,0 +1,114 @@
+//! Pruner which stops trials whose intermediate values leave a fixed range.
+
+use super::percentile::is_judged_at;
+use super::{FrozenTrial, Pruner, Study};
+
+/// Prunes a trial whose last intermediate value is NaN, below `lower` or
+/// above `upper`, e.g. to stop a diverging training early. Either bound may be
+/// left unset.
+///
+/// Nothing is pruned before step `n_warmup_steps`. After that, a trial is
+/// judged once every `interval_steps` steps, counted from `n_warmup_steps`.
+pub struct ThresholdPruner {
+    lower: Option<f64>,
+    upper: Option<f64>,
+    n_warmup_steps: u64,
+    interval_steps: u64,
+}
+
+impl ThresholdPruner {
+    pub fn new() -> Self {
+        ThresholdPruner {
+            lower: None,
+            upper: None,
+            n_warmup_steps: 0,
+            interval_steps: 1,
+        }
+    }
+
+    pub fn lower(mut self, lower: f64) -> Self {
+        self.lower = Some(lower);
+        self
+    }
+
+    pub fn upper(mut self, upper: f64) -> Self {
+        self.upper = Some(upper);
+        self
+    }
+
+    pub fn n_warmup_steps(mut self, n_warmup_steps: u64) -> Self {
+        self.n_warmup_steps = n_warmup_steps;
+        self
+    }
+
+    pub fn interval_steps(mut self, interval_steps: u64) -> Self {
+        self.interval_steps = interval_steps.max(1);
+        self
+    }
+}
+
+impl Default for ThresholdPruner {
+    fn default() -> Self {
+        ThresholdPruner::new()
+    }
+}
+
+impl Pruner for ThresholdPruner {
+    fn prune(&mut self, _study: &Study, trial: &FrozenTrial) -> bool {
+        let step = match trial.last_step() {
+            Some(step) => step,
+            None => return false,
+        };
+        if !is_judged_at(trial, step, self.n_warmup_steps, self.interval_steps) {
+            return false;
+        }
+        let value = trial.intermediate_values()[&step];
+        value.is_nan()
+            || self.lower.is_some_and(|lower| value < lower)
+            || self.upper.is_some_and(|upper| value > upper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::{create_study, InMemoryStorage, Storage};
+
+    fn prune(mut pruner: ThresholdPruner, values: &[f64]) -> bool {
+        let mut storage = InMemoryStorage::new();
+        let study_id = storage.create_new_study("study").unwrap();
+        let trial_id = storage.create_new_trial(study_id, None).unwrap();
+        for (step, &value) in values.iter().enumerate() {
+            storage
+                .set_trial_intermediate_value(trial_id, step as u64, value)
+                .unwrap();
+        }
+        let trial = storage.get_trial(trial_id).unwrap().unwrap();
+        let study = create_study().study_name("study").storage(storage).build();
+        pruner.prune(&study, &trial)
+    }
+
+    #[test]
+    fn threshold_pruner_prunes_values_out_of_bounds() {
+        let bounded = || ThresholdPruner::new().lower(0.0).upper(1.0);
+        assert!(!prune(bounded(), &[]));
+        assert!(!prune(bounded(), &[0.5]));
+        assert!(prune(bounded(), &[-0.5]));
+        assert!(prune(bounded(), &[1.5]));
+        assert!(prune(bounded(), &[f64::NAN]));
+        assert!(prune(ThresholdPruner::new(), &[f64::NAN]));
+        assert!(!prune(ThresholdPruner::new().upper(1.0), &[-1e9]));
+        // Only the last value counts.
+        assert!(!prune(bounded(), &[1.5, 0.5]));
+    }
+
+    #[test]
+    fn threshold_pruner_honors_warmup_and_interval() {
+        let warmup = || ThresholdPruner::new().upper(1.0).n_warmup_steps(2);
+        assert!(!prune(warmup(), &[9.0, 9.0]));
+        assert!(prune(warmup(), &[9.0, 9.0, 9.0]));
+        let interval = || ThresholdPruner::new().upper(1.0).interval_steps(3);
+        assert!(!prune(interval(), &[9.0, 9.0]));
+        assert!(prune(interval(), &[9.0, 9.0, 9.0, 9.0]));
+    }
+}
@@ -0,0 +1,604 @@
+//! Tree-structured Parzen Estimator sampler.
+//!
+//! Completed trials are split into the best `gamma(n)` trials and the rest. A
+//! Parzen estimator is fit to each group, candidates are drawn from the
+//! estimator of the good trials, and the candidate maximizing the ratio of the
+//! two densities is suggested.
+
+use super::math;
+use super::transform::{transform, transformed_bounds, untransform};
+use super::{
+    intersection_search_space, Distribution, FrozenTrial, RandomSampler, Sampler, Study,
+    StudyDirection, TrialRng, TrialState,
+};
+use rand::rngs::StdRng;
+use std::collections::HashMap;
+
+/// Number of trials regarded as good out of `n` completed trials.
+pub fn default_gamma(n: usize) -> usize {
+    ((0.1 * n as f64).ceil() as usize).min(25)
+}
+
+/// Weights of `n` observations ordered from the oldest trial to the newest one.
+/// The latest 25 trials get the full weight and older ones ramp up to it.
+pub fn default_weights(n: usize) -> Vec<f64> {
+    if n < 25 {
+        return vec![1.0; n];
+    }
+    let n_ramp = n - 25;
+    let mut weights: Vec<f64> = (0..n_ramp)
+        .map(|i| {
+            let start = 1.0 / n as f64;
+            if n_ramp == 1 {
+                start
+            } else {
+                start + (1.0 - start) * i as f64 / (n_ramp - 1) as f64
+            }
+        })
+        .collect();
+    weights.extend(vec![1.0; 25]);
+    weights
+}
+
+pub struct TpeSampler {
+    n_startup_trials: usize,
+    n_ei_candidates: usize,
+    gamma: Box<dyn Fn(usize) -> usize>,
+    weights: Box<dyn Fn(usize) -> Vec<f64>>,
+    prior_weight: f64,
+    consider_prior: bool,
+    consider_magic_clip: bool,
+    consider_endpoints: bool,
+    multivariate: bool,
+    rng: TrialRng,
+    random_sampler: RandomSampler,
+}
+
+impl TpeSampler {
+    pub fn new(seed: u64) -> Self {
+        TpeSampler {
+            n_startup_trials: 10,
+            n_ei_candidates: 24,
+            gamma: Box::new(default_gamma),
+            weights: Box::new(default_weights),
+            prior_weight: 1.0,
+            consider_prior: true,
+            consider_magic_clip: true,
+            consider_endpoints: false,
+            multivariate: false,
+            rng: TrialRng::new(seed),
+            random_sampler: RandomSampler::new(seed.wrapping_add(1)),
+        }
+    }
+
+    /// Number of completed trials sampled at random before TPE kicks in.
+    pub fn n_startup_trials(mut self, n_startup_trials: usize) -> Self {
+        self.n_startup_trials = n_startup_trials;
+        self
+    }
+
+    /// Number of candidates drawn from the estimator of the good trials.
+    pub fn n_ei_candidates(mut self, n_ei_candidates: usize) -> Self {
+        self.n_ei_candidates = n_ei_candidates.max(1);
+        self
+    }
+
+    pub fn gamma<F: Fn(usize) -> usize + 'static>(mut self, gamma: F) -> Self {
+        self.gamma = Box::new(gamma);
+        self
+    }
+
+    pub fn weights<F: Fn(usize) -> Vec<f64> + 'static>(mut self, weights: F) -> Self {
+        self.weights = Box::new(weights);
+        self
+    }
+
+    /// Weight of the prior component, which spans the whole search space.
+    pub fn prior_weight(mut self, prior_weight: f64) -> Self {
+        self.prior_weight = prior_weight;
+        self
+    }
+
+    pub fn consider_prior(mut self, consider_prior: bool) -> Self {
+        self.consider_prior = consider_prior;
+        self
+    }
+
+    /// Bounds the bandwidth of every kernel from below by the search space
+    /// width divided by the number of observations.
+    pub fn consider_magic_clip(mut self, consider_magic_clip: bool) -> Self {
+        self.consider_magic_clip = consider_magic_clip;
+        self
+    }
+
+    /// Takes the distance to the bounds into account for the bandwidth of the
+    /// outermost kernels.
+    pub fn consider_endpoints(mut self, consider_endpoints: bool) -> Self {
+        self.consider_endpoints = consider_endpoints;
+        self
+    }
+
+    /// Samples the intersection search space jointly instead of one parameter
+    /// at a time.
+    pub fn multivariate(mut self, multivariate: bool) -> Self {
+        self.multivariate = multivariate;
+        self
+    }
+
+    /// Samples `search_space` with TPE, or returns `None` while there are not
+    /// enough completed trials, or they cannot be read.
+    fn sample(
+        &mut self,
+        study: &Study,
+        search_space: &[(String, Distribution)],
+    ) -> Option<Vec<f64>> {
+        let trials = study.get_trials(Some(&[TrialState::Completed])).ok()?;
+        if trials.len() < self.n_startup_trials {
+            return None;
+        }
+        let mut trials: Vec<&FrozenTrial> = trials
+            .iter()
+            .filter(|t| {
+                search_space
+                    .iter()
+                    .all(|(name, _)| t.internal_params().contains_key(name))
+            })
+            .collect();
+
+        let sign = match study.direction() {
+            StudyDirection::Minimize => 1.0,
+            StudyDirection::Maximize => -1.0,
+        };
+        // Trials whose value is NaN or infinite rank last, so they fall into
+        // the group of bad trials.
+        let loss = |trial: &FrozenTrial| {
+            let value = sign * trial.value().unwrap_or(f64::NAN);
+            if value.is_finite() {
+                value
+            } else {
+                f64::INFINITY
+            }
+        };
+        trials.sort_by(|a, b| loss(a).total_cmp(&loss(b)));
+        let n_below = (self.gamma)(trials.len()).min(trials.len());
+        let (below, above) = trials.split_at(n_below);
+
+        let below = self.parzen_estimator(below, search_space);
+        let above = self.parzen_estimator(above, search_space);
+        let rng = &mut self.rng;
+        let best = (0..self.n_ei_candidates)
+            .map(|_| below.sample(rng))
+            .map(|x| {
+                let score = below.log_pdf(&x) - above.log_pdf(&x);
+                (x, score)
+            })
+            .fold(
+                None,
+                |best: Option<(Vec<f64>, f64)>, (x, score)| match best {
+                    Some((_, best_score)) if best_score >= score || score.is_nan() => best,
+                    _ => Some((x, score)),
+                },
+            )
+            .map(|(x, _)| x)?;
+        Some(
+            search_space
+                .iter()
+                .zip(best)
+                .map(|((_, d), x)| untransform(d, x))
+                .collect(),
+        )
+    }
+
+    fn parzen_estimator(
+        &self,
+        trials: &[&FrozenTrial],
+        search_space: &[(String, Distribution)],
+    ) -> ParzenEstimator {
+        // Older observations get smaller weights, so order them by trial id.
+        let mut trials = trials.to_vec();
+        trials.sort_by_key(|t| t.trial_id());
+        let observations: Vec<Vec<f64>> = search_space
+            .iter()
+            .map(|(name, d)| {
+                trials
+                    .iter()
+                    .map(|t| transform(d, t.internal_params()[name]))
+                    .collect()
+            })
+            .collect();
+
+        let n = trials.len();
+        let consider_prior = self.consider_prior || n == 0;
+        let mut weights = if n == 0 {
+            Vec::new()
+        } else {
+            (self.weights)(n)
+        };
+        if consider_prior {
+            weights.push(self.prior_weight);
+        }
+        let total: f64 = weights.iter().sum();
+        let weights = weights.iter().map(|w| w / total).collect();
+
+        let kernels = search_space
+            .iter()
+            .zip(observations)
+            .map(|((_, d), mus)| self.kernel(d, mus, consider_prior, search_space.len()))
+            .collect();
+        ParzenEstimator { weights, kernels }
+    }
+
+    fn kernel(
+        &self,
+        distribution: &Distribution,
+        mut mus: Vec<f64>,
+        consider_prior: bool,
+        n_params: usize,
+    ) -> Kernel {
+        let n = mus.len();
+        if let Distribution::Categorical { choices } = distribution {
+            let n_choices = choices.len();
+            let mut probs: Vec<Vec<f64>> = mus
+                .iter()
+                .map(|&index| {
+                    let mut p = vec![self.prior_weight / n_choices as f64; n_choices];
+                    p[index as usize] += 1.0;
+                    let total: f64 = p.iter().sum();
+                    p.iter().map(|v| v / total).collect()
+                })
+                .collect();
+            if consider_prior {
+                probs.push(vec![1.0 / n_choices as f64; n_choices]);
+            }
+            return Kernel::Categorical { probs };
+        }
+
+        let (low, high, step) = transformed_bounds(distribution);
+        let range = high - low;
+        let mut sigmas = if self.multivariate {
+            let sigma = 0.2 * (n.max(1) as f64).powf(-1.0 / (n_params as f64 + 4.0)) * range;
+            vec![sigma; n]
+        } else {
+            self.neighbor_sigmas(&mus, low, high, consider_prior)
+        };
+        if consider_prior {
+            mus.push(0.5 * (low + high));
+            sigmas.truncate(n);
+            sigmas.push(range);
+        }
+
+        let min_sigma = if self.consider_magic_clip {
+            range / (100.0f64).min(1.0 + mus.len() as f64)
+        } else {
+            f64::EPSILON
+        };
+        let sigmas = sigmas
+            .iter()
+            .map(|s| s.max(min_sigma).min(range).max(f64::EPSILON))
+            .collect();
+        Kernel::Numerical {
+            mus,
+            sigmas,
+            low,
+            high,
+            step,
+        }
+    }
+
+    /// Bandwidths from the distances to the neighboring observations, the prior
+    /// included, for univariate TPE.
+    fn neighbor_sigmas(&self, mus: &[f64], low: f64, high: f64, consider_prior: bool) -> Vec<f64> {
+        let mut points: Vec<f64> = mus.to_vec();
+        if consider_prior {
+            points.push(0.5 * (low + high));
+        }
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        order.sort_by(|&a, &b| points[a].partial_cmp(&points[b]).unwrap());
+
+        let mut with_endpoints = vec![low];
+        with_endpoints.extend(order.iter().map(|&i| points[i]));
+        with_endpoints.push(high);
+        let m = with_endpoints.len();
+        let mut sorted_sigmas: Vec<f64> = (1..m - 1)
+            .map(|i| {
+                let left = with_endpoints[i] - with_endpoints[i - 1];
+                let right = with_endpoints[i + 1] - with_endpoints[i];
+                left.max(right)
+            })
+            .collect();
+        if !self.consider_endpoints && m >= 4 {
+            sorted_sigmas[0] = with_endpoints[2] - with_endpoints[1];
+            let last = sorted_sigmas.len() - 1;
+            sorted_sigmas[last] = with_endpoints[m - 2] - with_endpoints[m - 3];
+        }
+
+        let mut sigmas = vec![0.0; points.len()];
+        for (sorted_index, &i) in order.iter().enumerate() {
+            sigmas[i] = sorted_sigmas[sorted_index];
+        }
+        sigmas
+    }
+}
+
+impl Sampler for TpeSampler {
+    fn infer_relative_search_space(
+        &mut self,
+        study: &Study,
+        _trial: &FrozenTrial,
+    ) -> HashMap<String, Distribution> {
+        if !self.multivariate {
+            return HashMap::new();
+        }
+        let trials = study
+            .get_trials(Some(&[TrialState::Completed]))
+            .unwrap_or_default();
+        let mut search_space = intersection_search_space(&trials);
+        search_space.retain(|_, d| !d.single());
+        search_space
+    }
+
+    fn sample_relative(
+        &mut self,
+        study: &Study,
+        trial: &FrozenTrial,
+        search_space: &HashMap<String, Distribution>,
+    ) -> HashMap<String, f64> {
+        if search_space.is_empty() {
+            return HashMap::new();
+        }
+        self.rng.reseed_for(trial);
+        let mut search_space: Vec<(String, Distribution)> = search_space
+            .iter()
+            .map(|(name, d)| (name.clone(), d.clone()))
+            .collect();
+        search_space.sort_by(|a, b| a.0.cmp(&b.0));
+
+        match self.sample(study, &search_space) {
+            Some(values) => search_space
+                .into_iter()
+                .map(|(name, _)| name)
+                .zip(values)
+                .collect(),
+            None => HashMap::new(),
+        }
+    }
+
+    fn sample_independent(
+        &mut self,
+        study: &Study,
+        trial: &FrozenTrial,
+        name: &str,
+        distribution: &Distribution,
+    ) -> f64 {
+        self.rng.reseed_for(trial);
+        let search_space = [(name.to_string(), distribution.clone())];
+        match self.sample(study, &search_space) {
+            Some(values) => values[0],
+            None => self
+                .random_sampler
+                .sample_independent(study, trial, name, distribution),
+        }
+    }
+}
+
+/// A mixture whose components are products of one kernel per parameter.
+struct ParzenEstimator {
+    weights: Vec<f64>,
+    kernels: Vec<Kernel>,
+}
+
+enum Kernel {
+    /// Truncated normal kernels over `[low, high]`. With a `step`, the kernels
+    /// are discretized onto the grid `low + step / 2 + k * step`.
+    Numerical {
+        mus: Vec<f64>,
+        sigmas: Vec<f64>,
+        low: f64,
+        high: f64,
+        step: Option<f64>,
+    },
+    Categorical {
+        probs: Vec<Vec<f64>>,
+    },
+}
+
+impl ParzenEstimator {
+    fn sample(&self, rng: &mut StdRng) -> Vec<f64> {
+        let component = math::choose_weighted(rng, &self.weights);
+        self.kernels
+            .iter()
+            .map(|kernel| match kernel {
+                Kernel::Numerical {
+                    mus,
+                    sigmas,
+                    low,
+                    high,
+                    step,
+                } => {
+                    let x = math::sample_truncated_normal(
+                        rng,
+                        mus[component],
+                        sigmas[component],
+                        *low,
+                        *high,
+                    );
+                    match step {
+                        Some(step) => {
+                            let first = low + 0.5 * step;
+                            let last = high - 0.5 * step;
+                            (((x - first) / step).round() * step + first)
+                                .max(first)
+                                .min(last)
+                        }
+                        None => x,
+                    }
+                }
+                Kernel::Categorical { probs } => {
+                    math::choose_weighted(rng, &probs[component]) as f64
+                }
+            })
+            .collect()
+    }
+
+    fn log_pdf(&self, x: &[f64]) -> f64 {
+        let log_densities: Vec<f64> = self
+            .weights
+            .iter()
+            .enumerate()
+            .map(|(component, w)| {
+                let log_kernels: f64 = self
+                    .kernels
+                    .iter()
+                    .zip(x)
+                    .map(|(kernel, &x)| kernel.log_pdf(component, x))
+                    .sum();
+                w.ln() + log_kernels
+            })
+            .collect();
+        math::logsumexp(&log_densities)
+    }
+}
+
+impl Kernel {
+    fn log_pdf(&self, component: usize, x: f64) -> f64 {
+        match self {
+            Kernel::Numerical {
+                mus,
+                sigmas,
+                low,
+                high,
+                step,
+            } => {
+                let (mu, sigma) = (mus[component], sigmas[component]);
+                let cdf = |v: f64| math::norm_cdf((v - mu) / sigma);
+                let log_z = (cdf(*high) - cdf(*low)).max(f64::MIN_POSITIVE).ln();
+                match step {
+                    Some(step) => {
+                        let mass = cdf(x + 0.5 * step) - cdf(x - 0.5 * step);
+                        mass.max(f64::MIN_POSITIVE).ln() - log_z
+                    }
+                    None => math::log_norm_pdf((x - mu) / sigma) - sigma.ln() - log_z,
+                }
+            }
+            Kernel::Categorical { probs } => probs[component][x as usize].ln(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::minituna_v1::{create_study, Objective, ParamValue, Trial, TrialError};
+
+    struct Model;
+
+    impl Objective for Model {
+        fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+            let x = trial.suggest_uniform("x", -10.0, 10.0)?;
+            let lr = trial.suggest_loguniform("lr", 1e-5, 1.0)?;
+            let n = trial.suggest_int("n", 1, 9, 2, false)?;
+            let activation = trial.suggest_categorical("activation", &["relu", "tanh"])?;
+            let penalty = if activation == "relu" { 0.0 } else { 5.0 };
+            Ok((x - 2.0).powi(2) + (lr.log10() + 3.0).powi(2) + (n - 5).pow(2) as f64 + penalty)
+        }
+    }
+
+    fn best_value(sampler: TpeSampler) -> f64 {
+        let study = create_study().sampler(sampler).build();
+        study.optimize(Model, 80);
+        study.best_trial().unwrap().unwrap().value().unwrap()
+    }
+
+    #[test]
+    fn default_weights_ramp_up_to_the_latest_trials() {
+        assert_eq!(default_weights(3), vec![1.0; 3]);
+        let weights = default_weights(30);
+        assert_eq!(weights.len(), 30);
+        assert!((weights[0] - 1.0 / 30.0).abs() < 1e-12);
+        assert!(weights.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn tpe_beats_random_search() {
+        let tpe = best_value(TpeSampler::new(1));
+        let random = {
+            let study = create_study().seed(1).build();
+            study.optimize(Model, 80);
+            study.best_trial().unwrap().unwrap().value().unwrap()
+        };
+        assert!(tpe < random, "tpe={} random={}", tpe, random);
+        assert!(tpe < 2.0);
+    }
+
+    #[test]
+    fn multivariate_tpe_finds_good_params() {
+        let value = best_value(
+            TpeSampler::new(3)
+                .multivariate(true)
+                .consider_endpoints(true),
+        );
+        assert!(value < 3.0, "{}", value);
+    }
+
+    #[test]
+    fn resumed_study_samples_like_an_uninterrupted_run() {
+        let sampler = || TpeSampler::new(4).n_startup_trials(3);
+        let uninterrupted = create_study().sampler(sampler()).build();
+        uninterrupted.optimize(Model, 10);
+
+        let interrupted = create_study().study_name("tpe").sampler(sampler()).build();
+        interrupted.optimize(Model, 5);
+        let resumed = create_study()
+            .study_name("tpe")
+            .shared_storage(interrupted.storage())
+            .sampler(sampler())
+            .load()
+            .unwrap();
+        resumed.optimize(Model, 5);
+
+        let trials = resumed.get_trials(None).unwrap();
+        for (trial, expected) in trials.iter().zip(uninterrupted.get_trials(None).unwrap()) {
+            assert_eq!(trial.internal_params(), expected.internal_params());
+        }
+    }
+
+    #[test]
+    fn nan_values_do_not_stop_the_sampler() {
+        struct SometimesNan;
+
+        impl Objective for SometimesNan {
+            fn objective(&self, trial: Trial<'_>) -> Result<f64, TrialError> {
+                let x = trial.suggest_uniform("x", -10.0, 10.0)?;
+                Ok(if x < 0.0 { f64::NAN } else { x * x })
+            }
+        }
+
+        for &multivariate in [false, true].iter() {
+            let study = create_study()
+                .sampler(
+                    TpeSampler::new(6)
+                        .n_startup_trials(2)
+                        .multivariate(multivariate),
+                )
+                .build();
+            study.optimize(SometimesNan, 40);
+            let completed = study.get_trials(Some(&[TrialState::Completed])).unwrap();
+            assert_eq!(completed.len(), 40);
+            assert!(study.best_trial().unwrap().unwrap().value().unwrap() < 1.0);
+        }
+    }
+
+    #[test]
+    fn suggested_values_stay_in_their_distribution() {
+        let study = create_study()
+            .sampler(TpeSampler::new(5).n_startup_trials(2))
+            .build();
+        study.optimize(Model, 30);
+        for trial in study.get_trials(None).unwrap() {
+            for (name, value) in trial.internal_params() {
+                assert!(trial.distributions()[name].contains(*value));
+            }
+            assert!(matches!(trial.params()["n"], ParamValue::Int(n) if n % 2 == 1));
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Maps the internal representation of numerical parameters to a continuous
+//! space and back, for samplers which model parameters as real numbers.
+//!
+//! Log-scaled distributions are modeled in log space, and discrete ones are
+//! widened by half a step on each side so that every grid point covers an
+//! interval of the same width.
+
+use super::Distribution;
+
+/// Bounds of the continuous space, and the grid step for discrete distributions
+/// which are not log-scaled.
+pub(crate) fn transformed_bounds(distribution: &Distribution) -> (f64, f64, Option<f64>) {
+    match *distribution {
+        Distribution::Uniform { low, high } => (low, high, None),
+        Distribution::LogUniform { low, high } => (low.ln(), high.ln(), None),
+        Distribution::Int {
+            low,
+            high,
+            step,
+            log,
+        } => {
+            if log {
+                ((low as f64 - 0.5).ln(), (high as f64 + 0.5).ln(), None)
+            } else {
+                let step = step as f64;
+                (
+                    low as f64 - 0.5 * step,
+                    high as f64 + 0.5 * step,
+                    Some(step),
+                )
+            }
+        }
+        Distribution::DiscreteUniform { low, high, q } => (low - 0.5 * q, high + 0.5 * q, Some(q)),
+        Distribution::Categorical { .. } => (0.0, 0.0, None),
+    }
+}
+
+pub(crate) fn transform(distribution: &Distribution, internal_repr: f64) -> f64 {
+    match *distribution {
+        Distribution::LogUniform { .. } | Distribution::Int { log: true, .. } => internal_repr.ln(),
+        _ => internal_repr,
+    }
+}
+
+/// Inverse of `transform`, rounding to the grid of discrete distributions.
+pub(crate) fn untransform(distribution: &Distribution, x: f64) -> f64 {
+    match *distribution {
+        Distribution::Uniform { low, high } => x.max(low).min(high),
+        Distribution::LogUniform { low, high } => x.exp().max(low).min(high),
+        Distribution::Int {
+            low,
+            high,
+            step,
+            log,
+        } => {
+            let (low, high, step) = (low as f64, high as f64, step as f64);
+            let x = if log { x.exp() } else { x };
+            (((x - low) / step).round() * step + low).max(low).min(high)
+        }
+        Distribution::DiscreteUniform { low, high, q } => {
+            (((x - low) / q).round() * q + low).max(low).min(high)
+        }
+        Distribution::Categorical { .. } => x,
+    }
+}